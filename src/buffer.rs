@@ -1,9 +1,28 @@
-use std::{fs::read, path::PathBuf};
+mod fill;
+mod hex;
+mod piece_table;
+mod region;
+
+pub use hex::{parse_hex, HEX_ROW_LEN};
+pub use region::SortKey;
+
+use crate::editor::{BufferId, Dired, View};
+use crate::files::{hash_bytes, is_writable, FileStamp};
 use log::*;
+use piece_table::PieceTable;
+use std::{
+    fs::{canonicalize, metadata, read},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
 const INIT_LEN: usize = 10;
 const DEFAULT_CHAR: char = '\0';
 
+/// Files at least this big are memory-mapped instead of being read into the gap
+/// buffer.
+const LARGE_FILE_THRESHOLD: u64 = 32 * 1024 * 1024;
+
 pub type Position = (usize, usize);
 pub type Selection = (Position, Position);
 
@@ -22,220 +41,451 @@ pub enum Direction {
     Right,
 }
 
+#[derive(Clone, PartialEq, Debug)]
+pub enum Mode {
+    Text,
+    /// Binary files, shown as offset, hex and ASCII columns
+    Hex,
+    /// Listing of the open buffers, one line per buffer after the header
+    BufferList(Vec<BufferId>),
+    /// Listing of a directory, one line per entry after the header
+    Dired(Dired),
+    /// Listing of a directory whose file names are being edited
+    Wdired(Dired),
+}
+
+impl Mode {
+    /// Whether buffers in this mode are edited as text.
+    pub fn is_text(&self) -> bool {
+        matches!(self, Mode::Text | Mode::Wdired(_))
+    }
+
+    /// Name of the mode, which is also the name of its keymap overlay.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Text => "text",
+            Mode::Hex => "hex",
+            Mode::BufferList(_) => "buffer-list",
+            Mode::Dired(_) => "dired",
+            Mode::Wdired(_) => "wdired",
+        }
+    }
+}
+
+/// A change to the text, `start..end` was replaced by `len` characters.
+#[derive(Clone, Copy, Debug)]
+struct Edit {
+    start: usize,
+    end: usize,
+    len: usize,
+}
+
+impl Edit {
+    /// Where `offset` ends up after the edit. Offsets in the replaced text move
+    /// to its start.
+    fn shift(&self, offset: usize) -> usize {
+        if offset <= self.start {
+            offset
+        } else if offset >= self.end {
+            offset - (self.end - self.start) + self.len
+        } else {
+            self.start
+        }
+    }
+}
+
+/// Text and metadata of a file. Cursors live in the views of the buffer,
+/// which are passed to the methods that need one.
 #[derive(Debug)]
 pub struct Buffer {
+    pub name: String,
     pub path: Option<PathBuf>,
     pub data: Box<[char]>,
     pub gap_start: usize,
     pub gap_len: usize,
-    pub cursor_offset: usize,
     pub modified: bool,
-    pub mark: Option<Position>,
-    pub clipboard: Option<Box<[char]>>,
+    /// Set for files that can't be written, edits are refused
+    pub read_only: bool,
+    /// Whether the buffer is paged through like with less, see the `view`
+    /// keymap overlay. Buffers in view mode are also read-only.
+    pub view_mode: bool,
+    /// Whether the file was backed up, which only the first save does
+    pub backed_up: bool,
+    /// Version of the text last written to the recovery file
+    pub autosaved: usize,
+    /// The file as it was last read or written, to notice other programs
+    /// changing it
+    pub disk: Option<FileStamp>,
+    /// Set for large files, which are edited through this instead of `data`
+    pub large_file: Option<PieceTable>,
+    pub mode: Mode,
+    /// Edits not yet applied to every view, see `sync_view`
+    edits: Vec<Edit>,
+    /// Number of edits dropped from the start of `edits`
+    forgotten_edits: usize,
 }
 
 impl Buffer {
-    pub fn new(path: Option<PathBuf>) -> Self {
-        let mut data: Box<[char]> = vec![DEFAULT_CHAR; INIT_LEN].into_boxed_slice();
-        let mut gap_len = INIT_LEN;
-
-        if let Some(file_path) = &path {
-            if file_path.exists() {
-                gap_len = 0;
-                data = read(&file_path)
-                    .expect(format!("Unable to read file: {:?}", file_path).as_str())
-                    .iter()
-                    .map(|&byte| byte as char)
-                    .collect::<Vec<_>>()
-                    .into_boxed_slice();
+    /// An empty buffer without a file.
+    pub fn new() -> Self {
+        let data = vec![DEFAULT_CHAR; INIT_LEN].into_boxed_slice();
+        Buffer::with_data(None, data, INIT_LEN, None, None)
+    }
+
+    /// A buffer visiting `path`, which is read unless it is large, and left
+    /// empty if it doesn't exist yet.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let empty = || vec![DEFAULT_CHAR; INIT_LEN].into_boxed_slice();
+        let len = metadata(&path).map_or(0, |m| m.len());
+
+        if len >= LARGE_FILE_THRESHOLD {
+            match PieceTable::open(&path) {
+                Ok(table) => {
+                    info!("Opened {:?} as a large file ({} bytes)", path, len);
+                    return Ok(Buffer::with_data(
+                        Some(path),
+                        empty(),
+                        INIT_LEN,
+                        Some(table),
+                        None,
+                    ));
+                }
+                Err(e) => warn!("Unable to map {:?}, reading it instead: {}", path, e),
             }
         }
 
+        if !path.exists() {
+            return Ok(Buffer::with_data(Some(path), empty(), INIT_LEN, None, None));
+        }
+
+        let bytes = read(&path)?;
+        let hash = hash_bytes(&bytes);
+        let data = bytes
+            .iter()
+            .map(|&byte| byte as char)
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        Ok(Buffer::with_data(Some(path), data, 0, None, Some(hash)))
+    }
+
+    fn with_data(
+        path: Option<PathBuf>,
+        data: Box<[char]>,
+        gap_len: usize,
+        large_file: Option<PieceTable>,
+        hash: Option<u64>,
+    ) -> Self {
+        let sample = match &large_file {
+            Some(table) => table.read(0, hex::BINARY_SAMPLE_LEN),
+            None => data[..data.len() - gap_len]
+                .iter()
+                .take(hex::BINARY_SAMPLE_LEN)
+                .map(|&c| c as u8)
+                .collect(),
+        };
+        let mode = if hex::is_binary(&sample) {
+            info!("Opened {:?} in hex mode", path);
+            Mode::Hex
+        } else {
+            Mode::Text
+        };
+
+        let read_only = path
+            .as_deref()
+            .is_some_and(|p| p.exists() && !is_writable(p));
+
         Buffer {
+            name: buffer_name(path.as_ref()),
+            disk: path.as_deref().and_then(|p| FileStamp::new(p, hash)),
             path,
             data,
             gap_start: 0,
             gap_len,
-            cursor_offset: 0,
             modified: false,
-            mark: None,
-            clipboard: None,
+            read_only,
+            view_mode: false,
+            backed_up: false,
+            autosaved: 0,
+            large_file,
+            mode,
+            edits: vec![],
+            forgotten_edits: 0,
+        }
+    }
+
+    pub fn is_large(&self) -> bool {
+        self.large_file.is_some()
+    }
+
+    /// Whether this is a large file still mapped from its path, which other
+    /// programs then change in place rather than by replacing it. The mapped
+    /// text can't be relied on once that happens.
+    pub fn maps_file(&self) -> bool {
+        match (&self.large_file, &self.path) {
+            (Some(table), Some(path)) => table.is_mapped(path),
+            _ => false,
+        }
+    }
+
+    /// Directory that file names typed in this buffer are relative to, the one
+    /// it lists or the one its file is in.
+    pub fn directory(&self) -> Option<PathBuf> {
+        match &self.mode {
+            Mode::Dired(dired) => Some(dired.directory.clone()),
+            _ => self
+                .path
+                .as_ref()
+                .and_then(|p| canonicalize(p).ok())
+                .and_then(|p| p.parent().map(Path::to_path_buf)),
         }
     }
 
+    /// Replaces the whole text, for buffers whose contents are generated.
+    pub fn set_text(&mut self, text: &str) {
+        let len = self.char_count();
+        self.splice(0, len, &text.chars().collect::<Vec<_>>());
+    }
+
+    /// Adds `text` at the end, also of read-only buffers, for buffers whose
+    /// contents are generated.
+    pub fn append(&mut self, text: &str) {
+        let len = self.char_count();
+        self.splice(len, len, &text.chars().collect::<Vec<_>>());
+        self.modified = false;
+    }
+
     pub fn clear(&mut self) {
+        self.edits.push(Edit {
+            start: 0,
+            end: self.char_count(),
+            len: 0,
+        });
         self.data = vec![DEFAULT_CHAR; INIT_LEN].into_boxed_slice();
         self.gap_start = 0;
         self.gap_len = INIT_LEN;
-        self.cursor_offset = 0;
         self.modified = true;
     }
 
-    // ---------- Editing ----------
+    /// Reads the file again, replacing only the text that changed on disk so
+    /// that cursors elsewhere stay where they are. Large files are mapped
+    /// again instead, dropping their edits.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let path = self.path.clone().ok_or(io::ErrorKind::NotFound)?;
+        if let Some(table) = &mut self.large_file {
+            let old_len = table.len();
+            *table = PieceTable::open(&path)?;
+            self.edits.push(Edit {
+                start: 0,
+                end: old_len,
+                len: table.len(),
+            });
+            self.modified = false;
+            self.disk = FileStamp::new(&path, None);
+            return Ok(());
+        }
 
-    pub fn go(&mut self, object: TextObject, direction: Direction) {
-        let offset = self.get_object_offset(object, direction);
-        let mut new_cursor_offset = self.cursor_offset as i32 + offset;
-        let gap_end = (self.gap_start + self.gap_len) as i32;
+        let bytes = read(&path)?;
+        let new = bytes.iter().map(|&byte| byte as char).collect::<Vec<_>>();
+        let old = self.text();
 
-        // From before the gap to after the gap
-        if self.cursor_offset <= self.gap_start && (self.gap_start as i32) < new_cursor_offset {
-            new_cursor_offset += self.gap_len as i32;
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if prefix + suffix < old.len().max(new.len()) {
+            self.splice(prefix, old.len() - suffix, &new[prefix..new.len() - suffix]);
         }
 
-        // From after the gap to before the gap
-        if new_cursor_offset <= gap_end && gap_end < self.cursor_offset as i32 {
-            new_cursor_offset -= self.gap_len as i32;
+        self.modified = false;
+        self.disk = FileStamp::new(&path, Some(hash_bytes(&bytes)));
+        Ok(())
+    }
+
+    /// Hash of the text as it would be written, `None` for large files.
+    pub fn content_hash(&self) -> Option<u64> {
+        if self.is_large() {
+            return None;
         }
+        let bytes = self.text().iter().map(|&c| c as u8).collect::<Vec<_>>();
+        Some(hash_bytes(&bytes))
+    }
+
+    /// Number of edits made to the text so far.
+    pub fn version(&self) -> usize {
+        self.forgotten_edits + self.edits.len()
+    }
 
-        new_cursor_offset = new_cursor_offset.max(0).min(self.data.len() as i32);
+    // ---------- Views ----------
 
-        if new_cursor_offset == gap_end {
-            new_cursor_offset = self.gap_start as i32;
+    /// A view at the start of the buffer.
+    pub fn view(&self) -> View {
+        View {
+            synced: self.version(),
+            ..View::default()
         }
+    }
 
-        self.cursor_offset = new_cursor_offset as usize;
-        self.align_gap();
+    /// Moves the cursor and mark of `view` past the edits made through other
+    /// views since it was last synced.
+    pub fn sync_view(&self, view: &mut View) {
+        let synced = view.synced.saturating_sub(self.forgotten_edits);
 
-        assert!(!self.in_gap(self.cursor_offset), "Cursor in gap");
+        for edit in self.edits.iter().skip(synced) {
+            view.cursor = edit.shift(view.cursor);
+            view.mark = view.mark.map(|mark| edit.shift(mark));
+        }
+        view.synced = self.version();
     }
 
-    pub fn delete(&mut self, object: TextObject, direction: Direction) {
-        let offset = self
-            .get_object_offset(object.clone(), direction.clone())
-            .max(-1 * self.cursor_offset as i32)
-            .min((self.data.len() - self.gap_len - self.cursor_offset) as i32);
+    /// Drops the edit log once every view has been synced.
+    pub fn forget_edits(&mut self) {
+        self.forgotten_edits += self.edits.len();
+        self.edits.clear();
+    }
+
+    // ---------- Editing ----------
+
+    pub fn go(&self, view: &mut View, object: TextObject, direction: Direction) {
+        self.sync_view(view);
+
+        view.cursor = if self.mode == Mode::Hex {
+            view.low_nibble = false;
+            self.hex_object_offset(view.cursor, object, direction)
+        } else {
+            self.object_offset(view.cursor, object, direction)
+        };
+    }
 
-        self.align_gap();
+    pub fn delete(&mut self, view: &mut View, object: TextObject, direction: Direction) {
+        self.sync_view(view);
 
-        if offset < 0 {
-            self.gap_start = (self.gap_start as i32 + offset) as usize;
-            self.cursor_offset = self.gap_start;
+        if !self.mode.is_text() {
+            warn!("{} can't be edited as text", self.name);
+            return;
         }
 
-        self.gap_len += offset.abs() as usize;
-        self.modified = true;
+        let target = self.object_offset(view.cursor, object, direction);
+        let (start, end) = (target.min(view.cursor), target.max(view.cursor));
+
+        self.replace_range(view, start, end, &[]);
     }
 
-    pub fn insert(&mut self, c: char) {
-        // Buffer out of space
-        if self.gap_len == 0 {
-            self.grow();
+    pub fn insert(&mut self, view: &mut View, c: char) {
+        self.sync_view(view);
+
+        if !self.mode.is_text() {
+            warn!("{} can't be edited as text", self.name);
+            return;
         }
 
-        self.align_gap();
-        self.data[self.gap_start] = c;
-        self.gap_start += 1;
-        self.cursor_offset += 1;
-        self.gap_len -= 1;
-        self.modified = true;
+        self.replace_range(view, view.cursor, view.cursor, &[c]);
     }
 
-    pub fn go_to_start(&mut self) {
-        self.cursor_offset = 0;
-        self.align_gap();
+    pub fn go_to_start(&self, view: &mut View) {
+        self.sync_view(view);
+        view.cursor = 0;
     }
 
-    pub fn go_to_end(&mut self) {
-        self.cursor_offset = self.data.len();
-        self.align_gap();
+    pub fn go_to_end(&self, view: &mut View) {
+        self.sync_view(view);
+        view.cursor = self.char_count();
     }
 
-    // ---------- Selection ----------
+    /// Replaces the characters in `start..end` of `text()` with `text`, leaving
+    /// the cursor of `view` at the end of the inserted text.
+    pub fn replace_range(&mut self, view: &mut View, start: usize, end: usize, text: &[char]) {
+        self.sync_view(view);
 
-    pub fn toggle_selection(&mut self) {
-        match self.mark {
-            None => self.mark = Some(self.cursor_position()),
-            Some(_) => self.mark = None,
+        if self.read_only {
+            warn!("{} is read-only", self.name);
+            return;
         }
-    }
+        if let Mode::Wdired(dired) = &self.mode {
+            if !dired.allows_edit(&self.text(), start, end, text) {
+                warn!("Only the file names in {} can be edited", self.name);
+                return;
+            }
+        }
+        self.splice(start, end, text);
 
-    pub fn get_selection(&self) -> Option<Selection> {
-        if let Some(mark) = self.mark {
-            let cursor_position = self.cursor_position();
-            let (mark_line, mark_col) = mark;
-            let (cursor_line, cursor_col) = cursor_position;
+        view.synced = self.version();
+        view.cursor = start + text.len();
+    }
 
-            if mark_line == cursor_line {
-                if mark_col >= cursor_col {
-                    return Some((cursor_position, mark));
+    // ---------- Selection ----------
 
-                } else if mark_col < cursor_col {
-                    return Some((mark, cursor_position));
-                }
-            } else if mark_line > cursor_line {
-                return Some((cursor_position, mark));
+    pub fn toggle_selection(&self, view: &mut View) {
+        self.sync_view(view);
 
-            } else if mark_line < cursor_line {
-                return Some((mark, cursor_position));
-            }
+        match view.mark {
+            None => view.mark = Some(view.cursor),
+            Some(_) => view.mark = None,
         }
-        None
     }
 
-    pub fn copy_to_clipboard(&mut self) {
-        if let Some(mark) = self.mark {
-            // TODO: This doesn't need to call text(), optimise later!
-            let text = self.text();
-            let mark_offset = text
-                .iter()
-                .enumerate()
-                .filter(|(i, c)| c == &&'\n' || i == &0)
-                .nth(mark.0 - 1)
-                .unwrap()
-                .0
-                + mark.1
-                - 1;
-            let (a, b) = (mark_offset.min(self.cursor_offset), mark_offset.max(self.cursor_offset));
-
-            let mut new_clipboard = text[a..b].iter().map(|c| c.to_owned()).collect::<Vec<_>>();
-            if !new_clipboard.is_empty() && new_clipboard[0] == '\n' {
-                new_clipboard.remove(0);
-            }
+    /// Start and end of the selection of `view`, the end being just past the
+    /// last selected character.
+    pub fn get_selection(&self, view: &View) -> Option<Selection> {
+        let mark = view.mark?;
+        let (start, end) = (mark.min(view.cursor), mark.max(view.cursor));
+        Some((self.position_of(start), self.position_of(end)))
+    }
 
-            self.clipboard = Some(new_clipboard.into_boxed_slice());
-            self.toggle_selection();
-        }
+    /// The selected text, which is deselected.
+    pub fn copy_selection(&self, view: &mut View) -> Option<String> {
+        self.sync_view(view);
+
+        let mark = view.mark.take()?;
+        let (start, end) = (mark.min(view.cursor), mark.max(view.cursor));
+        Some(self.text()[start..end].iter().collect())
     }
 
-    pub fn paste_from_clipboard(&mut self) {
-        let clipboard = self.clipboard.clone();
-        if let Some(text) = clipboard {
-            for c in text.iter() {
-                self.insert(*c);
-            }
+    pub fn paste(&mut self, view: &mut View, text: &str) {
+        self.sync_view(view);
+
+        if !self.mode.is_text() {
+            warn!("{} can't be edited as text", self.name);
+            return;
         }
+
+        let text = text.chars().collect::<Vec<_>>();
+        self.replace_range(view, view.cursor, view.cursor, &text);
     }
 
-    pub fn delete_selection(&mut self) {
-        if let Some(mark) = self.mark {
-            let mark_offset = self
-                .text()
-                .iter()
-                .enumerate()
-                .filter(|(i, c)| c == &&'\n' || i == &0)
-                .nth(mark.0 - 1)
-                .unwrap()
-                .0
-                + mark.1
-                - 1 + (mark.0 != 1) as usize;
-
-            let mut diff = self.cursor_offset as i32 - mark_offset as i32;
-            while diff > 0 {
-                self.delete(TextObject::Char, Direction::Left);
-                diff -= 1;
-            }
-            while diff < 0 {
-                self.delete(TextObject::Char, Direction::Right);
-                diff += 1;
-            }
-            self.toggle_selection();
+    pub fn delete_selection(&mut self, view: &mut View) {
+        self.sync_view(view);
+
+        if !self.mode.is_text() {
+            warn!("{} can't be edited as text", self.name);
+            return;
+        }
+
+        if let Some(mark) = view.mark {
+            let (start, end) = (mark.min(view.cursor), mark.max(view.cursor));
+            self.replace_range(view, start, end, &[]);
+            view.mark = None;
         }
     }
 
     // ---------- Accessing content ----------
 
+    pub fn char_count(&self) -> usize {
+        match &self.large_file {
+            Some(table) => table.len(),
+            None => self.data.len() - self.gap_len,
+        }
+    }
+
+    /// Number of newlines in the buffer. For large files this only counts the
+    /// lines that have been indexed so far.
     pub fn line_count(&self) -> usize {
+        if let Some(table) = &self.large_file {
+            return table.indexed_newline_count();
+        }
+
         self.data
             .iter()
             .enumerate()
@@ -244,6 +494,15 @@ impl Buffer {
     }
 
     pub fn text(&self) -> Box<[char]> {
+        if let Some(table) = &self.large_file {
+            return table
+                .read(0, table.len())
+                .iter()
+                .map(|&byte| byte as char)
+                .collect::<Vec<_>>()
+                .into_boxed_slice();
+        }
+
         self.data
             .into_iter()
             .enumerate()
@@ -263,27 +522,98 @@ impl Buffer {
             .into_boxed_slice()
     }
 
-    pub fn cursor_position(&self) -> Position {
-        assert!(self.is_aligned(), "Gap must be aligned");
+    /// Up to `count` lines starting from `first`, counting from 0. Unlike
+    /// `text_lines()` this only reads the lines asked for in large files.
+    pub fn lines(&self, first: usize, count: usize) -> Vec<Box<[char]>> {
+        match &self.large_file {
+            Some(table) => (first..first + count)
+                .map_while(|line| {
+                    let start = table.line_start(line)?;
+                    let line = table
+                        .read(start, table.line_end(line))
+                        .iter()
+                        .map(|&byte| byte as char)
+                        .collect::<Vec<_>>();
+                    Some(line.into_boxed_slice())
+                })
+                .collect(),
+            None => self
+                .text_lines()
+                .iter()
+                .skip(first)
+                .take(count)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Offset of the start of `line`, counting from 0, if there is such a
+    /// line. Large files are only indexed as far as that line.
+    pub fn line_offset(&self, line: usize) -> Option<usize> {
+        if let Some(table) = &self.large_file {
+            return table.line_start(line);
+        }
 
         let text = self.text();
+        let mut starts = std::iter::once(0).chain(
+            text.iter()
+                .enumerate()
+                .filter(|(_, c)| c == &&'\n')
+                .map(|(i, _)| i + 1),
+        );
+        starts.nth(line)
+    }
 
-        let line = 1 + text
-            .iter()
-            .take(self.cursor_offset)
-            .filter(|c| c == &&'\n')
-            .count();
+    /// The characters in `start..end`, or up to the end of the text. Unlike
+    /// `text()` this only reads the characters asked for in large files.
+    pub fn text_range(&self, start: usize, end: usize) -> Box<[char]> {
+        let end = end.min(self.char_count());
+        let start = start.min(end);
 
-        let column = self.cursor_offset as i32
-            - text
+        match &self.large_file {
+            Some(table) => table
+                .read(start, end)
                 .iter()
-                .take(self.cursor_offset)
-                .enumerate()
-                .filter(|(_, c)| c == &&'\n')
-                .map(|(i, _)| i)
-                .fold(None, |_, x| Some(x as i32))
-                .unwrap_or(-1);
-        (line, column as usize)
+                .map(|&byte| byte as char)
+                .collect::<Vec<_>>()
+                .into_boxed_slice(),
+            None => self.text()[start..end].into(),
+        }
+    }
+
+    pub fn cursor_position(&self, view: &View) -> Position {
+        self.position_of(view.cursor)
+    }
+
+    /// Line and column of `offset`, both counting from 1.
+    pub fn position_of(&self, offset: usize) -> Position {
+        let offset = offset.min(self.char_count());
+
+        if let Some(table) = &self.large_file {
+            let line = table.line_of(offset);
+            let line_start = table.line_start(line).unwrap_or(0);
+            return (line + 1, offset - line_start + 1);
+        }
+
+        let text = self.text();
+        let before = &text[..offset];
+        let line = 1 + before.iter().filter(|c| c == &&'\n').count();
+        let line_start = before.iter().rposition(|c| c == &'\n').map_or(0, |i| i + 1);
+
+        (line, offset - line_start + 1)
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        match &self.large_file {
+            Some(table) => table.write_to(writer),
+            None => writer.write_all(
+                self.text()
+                    .iter()
+                    .map(|&char| char as u8)
+                    .collect::<Vec<_>>()
+                    .as_slice(),
+            ),
+        }
     }
 
     // ---------- Accessing debug content ----------
@@ -315,8 +645,35 @@ impl Buffer {
 
     // ---------- Helper functions ----------
 
-    fn is_aligned(&self) -> bool {
-        self.gap_start == self.cursor_offset
+    /// Replaces `start..end` of `text()` with `text`, recording the edit so
+    /// that the views of the buffer can follow it.
+    fn splice(&mut self, start: usize, end: usize, text: &[char]) {
+        assert!(
+            start <= end && end <= self.char_count(),
+            "Range out of bounds"
+        );
+
+        self.edits.push(Edit {
+            start,
+            end,
+            len: text.len(),
+        });
+        self.modified = true;
+
+        if let Some(table) = &mut self.large_file {
+            table.delete(start, end);
+            table.insert(start, &text.iter().map(|&c| c as u8).collect::<Vec<_>>());
+            return;
+        }
+
+        self.move_gap(end);
+        self.gap_start -= end - start;
+        self.gap_len += end - start;
+
+        self.reserve(text.len());
+        self.data[self.gap_start..self.gap_start + text.len()].copy_from_slice(text);
+        self.gap_start += text.len();
+        self.gap_len -= text.len();
     }
 
     pub fn in_gap(&self, offset: usize) -> bool {
@@ -333,66 +690,60 @@ impl Buffer {
         self.gap_start < offset && offset <= (self.gap_start + self.gap_len)
     }
 
-    fn align_gap(&mut self) {
-        assert!(
-            self.cursor_offset <= self.data.len(),
-            "Cursor out of bounds"
-        );
+    /// Moves the gap to `offset`, counted in characters of `text()`.
+    fn move_gap(&mut self, offset: usize) {
+        assert!(offset <= self.char_count(), "Offset out of bounds");
 
-        let diff: i32 = self.cursor_offset as i32 - self.gap_start as i32;
-
-        if diff < 0 {
+        if offset < self.gap_start {
             // before: [--|--__________-----]
             // after : [--|_________--------]
 
-            self.data.copy_within(
-                self.cursor_offset..(self.cursor_offset as i32 - diff) as usize,
-                self.cursor_offset + self.gap_len,
-            );
-            self.gap_start = self.cursor_offset;
-        } else if diff > 0 {
+            self.data
+                .copy_within(offset..self.gap_start, offset + self.gap_len);
+        } else if offset > self.gap_start {
             // before: [-----__________---|-]
             // after : [--------|_________--]
 
             let gap_end: usize = self.gap_start + self.gap_len;
 
-            self.data.copy_within(
-                gap_end..(gap_end + (diff - self.gap_len as i32) as usize),
-                self.gap_start,
-            );
-            self.gap_start += diff as usize - self.gap_len;
-            self.cursor_offset = self.gap_start;
+            self.data
+                .copy_within(gap_end..(gap_end + offset - self.gap_start), self.gap_start);
         }
 
-        assert!(self.is_aligned(), "align_gap did not align properly.");
+        self.gap_start = offset;
     }
 
-    fn grow(&mut self) {
-        assert_eq!(
-            self.cursor_offset, self.gap_start,
-            "Gap must be aligned before calling grow\n"
-        );
+    /// Makes sure the gap can hold at least `additional` characters without
+    /// growing again.
+    fn reserve(&mut self, additional: usize) {
+        if self.gap_len >= additional {
+            return;
+        }
 
-        assert_eq!(self.gap_len, 0, "Gap must be length 0 when grow is called",);
+        let right_chars_count = self.data.len() - self.gap_start - self.gap_len;
+        let new_gap_len = additional + INIT_LEN;
+        let mut new_data: Box<[char]> =
+            vec![DEFAULT_CHAR; self.gap_start + new_gap_len + right_chars_count].into_boxed_slice();
 
-        // The number of characters to the right of the cursor before grow
-        let right_chars_count: usize = self.data.len() - self.gap_start;
+        new_data[..self.gap_start].copy_from_slice(&self.data[..self.gap_start]);
+        new_data[self.gap_start + new_gap_len..]
+            .copy_from_slice(&self.data[self.gap_start + self.gap_len..]);
 
-        // Reallocation
-        let mut new_data: Box<[char]> =
-            vec![DEFAULT_CHAR; self.data.len() + INIT_LEN].into_boxed_slice();
-        new_data[..self.data.len()].copy_from_slice(&self.data[..self.data.len()]);
         self.data = new_data;
+        self.gap_len = new_gap_len;
+    }
 
-        // Moving right chars to the end
-        self.gap_len = self.data.len() - self.gap_start - right_chars_count;
-        self.data.copy_within(
-            self.gap_start..(self.gap_start + right_chars_count),
-            self.gap_start + self.gap_len,
-        )
+    /// Offset that moving from `cursor` by `object` in `direction` ends up at.
+    pub fn object_offset(&self, cursor: usize, object: TextObject, direction: Direction) -> usize {
+        if let Some(table) = &self.large_file {
+            return table.object_offset(cursor, object, direction);
+        }
+
+        let offset = self.get_object_offset(cursor, object, direction);
+        (cursor as i32 + offset).clamp(0, self.char_count() as i32) as usize
     }
 
-    fn get_object_offset(&self, object: TextObject, direction: Direction) -> i32 {
+    fn get_object_offset(&self, cursor: usize, object: TextObject, direction: Direction) -> i32 {
         let mut offset: i32 = 0;
 
         match object {
@@ -402,60 +753,11 @@ impl Buffer {
                 _ => {}
             },
             TextObject::Word => {
-                let word_boundaries = [
-                    ' ', '!', '\"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.',
-                    '/', ':', ';', '<', '=', '>', '?', '@', '[', '\\', ']', '^', '_', '`', '{',
-                    '|', '}', '~', '\n',
-                ];
-                let text = self.text();
-                match direction {
-                    Direction::Left => {
-                        let first_char = text
-                            .iter()
-                            .take(self.cursor_offset)
-                            .rev()
-                            .enumerate()
-                            .find(|(_, c)| !word_boundaries.contains(c))
-                            .map(|(i, _)| i)
-                            .unwrap_or(0);
-
-                        offset = text
-                            .iter()
-                            .take(self.cursor_offset - first_char)
-                            .rev()
-                            .enumerate()
-                            .find(|(_, c)| word_boundaries.contains(c))
-                            .map(|(i, _)| i)
-                            .unwrap_or(self.cursor_offset) as i32
-                            + first_char as i32;
-
-                        offset *= -1;
-                    }
-                    Direction::Right => {
-                        let first_char = text
-                            .iter()
-                            .skip(self.cursor_offset)
-                            .enumerate()
-                            .find(|(_, c)| !word_boundaries.contains(c))
-                            .map(|(i, _)| i)
-                            .unwrap_or(text.len() - self.cursor_offset);
-
-                        offset = text
-                            .iter()
-                            .skip(first_char + self.cursor_offset)
-                            .enumerate()
-                            .find(|(_, c)| word_boundaries.contains(c))
-                            .map(|(i, _)| i)
-                            .unwrap_or(text.len() - self.cursor_offset)
-                            as i32
-                            + first_char as i32;
-                    }
-                    _ => {}
-                }
+                offset = word_offset(&self.text(), cursor, direction);
             }
             TextObject::Line => {
                 let text_lines = self.text_lines();
-                let (line, column) = self.cursor_position();
+                let (line, column) = self.position_of(cursor);
 
                 match direction {
                     Direction::Up => {
@@ -476,15 +778,10 @@ impl Buffer {
                     }
                     Direction::Down => {
                         offset = text_lines
-                            .iter()
-                            .nth(line)
-                            .map(|next_line| {
-                                (text_lines
-                                    .iter()
-                                    .nth(line - 1)
-                                    .expect("Could not get current line")
-                                    .len()
-                                    + next_line.len().min(column).max(1)
+                            .get(line - 1)
+                            .zip(text_lines.get(line))
+                            .map(|(current_line, next_line)| {
+                                (current_line.len() + next_line.len().min(column).max(1)
                                     - (column - 1)) as i32
                             })
                             .unwrap_or(0);
@@ -507,3 +804,65 @@ impl Buffer {
         offset
     }
 }
+
+/// Offset from `cursor` to the next word boundary in `direction`.
+fn word_offset(text: &[char], cursor: usize, direction: Direction) -> i32 {
+    let word_boundaries = [
+        ' ', '!', '\"', '#', '$', '%', '&', '\'', '(', ')', '*', '+', ',', '-', '.', '/', ':', ';',
+        '<', '=', '>', '?', '@', '[', '\\', ']', '^', '_', '`', '{', '|', '}', '~', '\n',
+    ];
+    let mut offset: i32 = 0;
+
+    match direction {
+        Direction::Left => {
+            let first_char = text
+                .iter()
+                .take(cursor)
+                .rev()
+                .enumerate()
+                .find(|(_, c)| !word_boundaries.contains(c))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+
+            offset = text
+                .iter()
+                .take(cursor - first_char)
+                .rev()
+                .enumerate()
+                .find(|(_, c)| word_boundaries.contains(c))
+                .map(|(i, _)| i)
+                .unwrap_or(cursor) as i32
+                + first_char as i32;
+
+            offset *= -1;
+        }
+        Direction::Right => {
+            let first_char = text
+                .iter()
+                .skip(cursor)
+                .enumerate()
+                .find(|(_, c)| !word_boundaries.contains(c))
+                .map(|(i, _)| i)
+                .unwrap_or(text.len() - cursor);
+
+            offset = text
+                .iter()
+                .skip(first_char + cursor)
+                .enumerate()
+                .find(|(_, c)| word_boundaries.contains(c))
+                .map(|(i, _)| i)
+                .unwrap_or(text.len() - cursor) as i32
+                + first_char as i32;
+        }
+        _ => {}
+    }
+    offset
+}
+
+/// Name shown for a buffer visiting `path`.
+pub fn buffer_name(path: Option<&PathBuf>) -> String {
+    path.and_then(|p| p.file_name())
+        .map_or("[No Name]".to_owned(), |name| {
+            name.to_string_lossy().into_owned()
+        })
+}
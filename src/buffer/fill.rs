@@ -0,0 +1,452 @@
+use std::{ffi::OsStr, path::Path};
+
+use crate::{buffer::Buffer, editor::View};
+
+/// Comment markers that are repeated at the start of every filled line. Longer
+/// markers come first so that `///` is not mistaken for `//`. `*` is one too
+/// inside `/* */` comments, and `#` isn't in Markdown, where it starts
+/// headings.
+const COMMENT_MARKERS: [&str; 8] = ["///", "//!", "//", "--", ";;", ";", "#", ">"];
+
+/// Markers of list items, which start a paragraph of their own.
+const BULLETS: [char; 3] = ['*', '-', '+'];
+
+impl Buffer {
+    // ---------- Filling ----------
+
+    /// Reflows the paragraph around the cursor so that no line goes past
+    /// `fill_column`, keeping the indentation and comment prefix of its first
+    /// line.
+    pub fn fill_paragraph(&mut self, view: &mut View, fill_column: usize) {
+        self.sync_view(view);
+
+        let markdown = self.is_markdown();
+        let lines = self.text_lines();
+        let (line, _) = self.cursor_position(view);
+        let prefix_len = fill_prefix_len(&lines, line - 1, markdown);
+        let prefix = lines[line - 1][..prefix_len].to_vec();
+
+        let content = &lines[line - 1][prefix_len..];
+        if is_blank(content) || is_heading(content, markdown) {
+            return;
+        }
+
+        let in_paragraph = |i: usize| {
+            let len = fill_prefix_len(&lines, i, markdown);
+            let content = &lines[i][len..];
+            trim_end(&lines[i][..len]) == trim_end(&prefix)
+                && !is_blank(content)
+                && !is_heading(content, markdown)
+        };
+        // List items end the paragraph before them
+        let starts_item = |i: usize| {
+            let len = fill_prefix_len(&lines, i, markdown);
+            bullet_len(&lines[i][len..]).is_some()
+        };
+
+        let mut first = line - 1;
+        while first > 0 && !starts_item(first) && in_paragraph(first - 1) {
+            first -= 1;
+        }
+
+        let mut last = line - 1;
+        while last + 1 < lines.len() && in_paragraph(last + 1) && !starts_item(last + 1) {
+            last += 1;
+        }
+
+        // The lines of a list item after the first are indented to its text
+        let first_len = fill_prefix_len(&lines, first, markdown);
+        let bullet = bullet_len(&lines[first][first_len..]).unwrap_or(0);
+        let first_prefix = lines[first][..first_len + bullet].to_vec();
+        let mut prefix = lines[first][..first_len].to_vec();
+        prefix.extend(std::iter::repeat_n(' ', bullet));
+
+        let start = lines[..first].iter().map(|l| l.len() + 1).sum::<usize>();
+        let end = lines[..last].iter().map(|l| l.len() + 1).sum::<usize>() + lines[last].len();
+
+        // Words keep their order, so the cursor is placed after the same number
+        // of non-whitespace characters it was after before filling.
+        let mut words_before_cursor = 0;
+        let mut words: Vec<&[char]> = vec![];
+        let mut line_start = start;
+
+        for (i, l) in lines.iter().enumerate().take(last + 1).skip(first) {
+            let len = match i == first {
+                true => first_prefix.len(),
+                false => fill_prefix_len(&lines, i, markdown),
+            };
+            words_before_cursor += l
+                .iter()
+                .enumerate()
+                .skip(len)
+                .filter(|(i, c)| line_start + i < view.cursor && !c.is_whitespace())
+                .count();
+            words.extend(
+                l[len..]
+                    .split(|c| c.is_whitespace())
+                    .filter(|w| !w.is_empty()),
+            );
+            line_start += l.len() + 1;
+        }
+
+        let (filled, cursor) = fill_words(
+            &words,
+            (&first_prefix, &prefix),
+            fill_column,
+            words_before_cursor,
+        );
+
+        self.replace_range(view, start, end, &filled);
+        view.cursor = start + cursor;
+    }
+
+    /// Breaks the cursor line at the last whitespace before `fill_column`,
+    /// continuing it with the same prefix. Called after typing a space while
+    /// auto-fill is on.
+    pub fn auto_fill(&mut self, view: &mut View, fill_column: usize) {
+        self.sync_view(view);
+        let markdown = self.is_markdown();
+
+        loop {
+            let lines = self.text_lines();
+            let (line, column) = self.cursor_position(view);
+            let current = &lines[line - 1];
+            let line_start = view.cursor - (column - 1);
+            let prefix_len = fill_prefix_len(&lines, line - 1, markdown);
+            let bullet = bullet_len(&current[prefix_len..]).unwrap_or(0);
+
+            let typed_len = trim_end(&current[..column - 1]).len();
+            if typed_len <= fill_column {
+                return;
+            }
+
+            // Prefer the last break that fits, otherwise the first one after the
+            // fill column so that long words are left whole. The whitespace just
+            // typed is no break, it would leave an empty line.
+            let breaks = (prefix_len + bullet + 1..typed_len)
+                .filter(|&i| current[i].is_whitespace() && !current[i - 1].is_whitespace())
+                .collect::<Vec<_>>();
+            let break_start = match breaks
+                .iter()
+                .rev()
+                .find(|&&i| i <= fill_column)
+                .or(breaks.first())
+            {
+                Some(&i) => i,
+                None => return,
+            };
+            let break_end = break_start
+                + current[break_start..]
+                    .iter()
+                    .take_while(|c| c.is_whitespace())
+                    .count();
+
+            let mut replacement = vec!['\n'];
+            replacement.extend(&current[..prefix_len]);
+            replacement.extend(std::iter::repeat_n(' ', bullet));
+
+            let cursor = if view.cursor >= line_start + break_end {
+                view.cursor - (break_end - break_start) + replacement.len()
+            } else {
+                line_start + break_start + replacement.len()
+            };
+
+            self.replace_range(
+                view,
+                line_start + break_start,
+                line_start + break_end,
+                &replacement,
+            );
+            view.cursor = cursor;
+        }
+    }
+
+    fn is_markdown(&self) -> bool {
+        let extension = self.path.as_deref().and_then(Path::extension);
+        matches!(extension.and_then(OsStr::to_str), Some("md" | "markdown"))
+    }
+}
+
+/// Length of the indentation and comment marker at the start of line `index`
+/// of `lines`.
+fn fill_prefix_len(lines: &[Box<[char]>], index: usize, markdown: bool) -> usize {
+    let line = &lines[index];
+    let indent = line.iter().take_while(|c| c.is_whitespace()).count();
+    let rest = &line[indent..];
+
+    let markers = COMMENT_MARKERS
+        .into_iter()
+        .filter(|marker| !(markdown && *marker == "#"))
+        .chain(in_block_comment(lines, index).then_some("*"));
+    for marker in markers {
+        let marker = marker.chars().collect::<Vec<_>>();
+
+        if rest.starts_with(&marker) {
+            let after = &rest[marker.len()..];
+
+            if after.first().is_none_or(|c| c.is_whitespace()) {
+                return indent
+                    + marker.len()
+                    + after.iter().take_while(|c| c.is_whitespace()).count();
+            }
+        }
+    }
+
+    indent
+}
+
+/// Whether line `index` of `lines` continues a `/* */` comment, starting
+/// with `*` below the line that opens it.
+fn in_block_comment(lines: &[Box<[char]>], index: usize) -> bool {
+    for (i, line) in lines[..=index].iter().enumerate().rev() {
+        let rest = trim_start(line);
+        if rest.starts_with(&['/', '*']) {
+            return i < index;
+        }
+        // Lines closing a comment end it for the lines below
+        if !rest.starts_with(&['*']) || (i < index && rest.starts_with(&['*', '/'])) {
+            return false;
+        }
+    }
+    false
+}
+
+/// Length of the list item marker at the start of `content`, like `* ` or
+/// `1. `, with the whitespace after it.
+fn bullet_len(content: &[char]) -> Option<usize> {
+    let digits = content.iter().take_while(|c| c.is_ascii_digit()).count();
+    let marker = match content.get(digits) {
+        Some(c) if digits == 0 && BULLETS.contains(c) => 1,
+        Some('.' | ')') if (1..=9).contains(&digits) => digits + 1,
+        _ => return None,
+    };
+
+    let spaces = content[marker..]
+        .iter()
+        .take_while(|c| c.is_whitespace())
+        .count();
+    (spaces > 0 && marker + spaces < content.len()).then_some(marker + spaces)
+}
+
+/// Whether `content` is a Markdown heading, which is never filled.
+fn is_heading(content: &[char], markdown: bool) -> bool {
+    markdown && content.first() == Some(&'#')
+}
+
+/// Lays `words` out in lines no longer than `fill_column`, the first one
+/// starting with the first of `prefixes` and the others with the second.
+/// Also returns the offset just after the first `cursor_chars` word
+/// characters.
+fn fill_words(
+    words: &[&[char]],
+    prefixes: (&[char], &[char]),
+    fill_column: usize,
+    cursor_chars: usize,
+) -> (Vec<char>, usize) {
+    let (first_prefix, prefix) = prefixes;
+    let mut filled = first_prefix.to_vec();
+    let mut line_len = first_prefix.len();
+    let mut chars = 0;
+    let mut cursor = first_prefix.len();
+
+    for (i, word) in words.iter().enumerate() {
+        if i > 0 && line_len + 1 + word.len() > fill_column {
+            filled.push('\n');
+            filled.extend(prefix);
+            line_len = prefix.len();
+        } else if i > 0 {
+            filled.push(' ');
+            line_len += 1;
+        }
+
+        for c in word.iter() {
+            filled.push(*c);
+            chars += 1;
+            if chars == cursor_chars {
+                cursor = filled.len();
+            }
+        }
+
+        line_len += word.len();
+    }
+
+    (filled, cursor)
+}
+
+fn is_blank(chars: &[char]) -> bool {
+    chars.iter().all(|c| c.is_whitespace())
+}
+
+fn trim_start(chars: &[char]) -> &[char] {
+    let len = chars.iter().take_while(|c| c.is_whitespace()).count();
+    &chars[len..]
+}
+
+fn trim_end(chars: &[char]) -> &[char] {
+    let len = chars.len() - chars.iter().rev().take_while(|c| c.is_whitespace()).count();
+    &chars[..len]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A buffer visiting `path` holding `text`, with the cursor at the `|`.
+    fn buffer(path: &str, text: &str) -> (Buffer, View) {
+        let mut buffer = Buffer::new();
+        buffer.path = Some(path.into());
+        buffer.set_text(&text.replace('|', ""));
+        let mut view = buffer.view();
+        view.cursor = text.chars().position(|c| c == '|').unwrap();
+        (buffer, view)
+    }
+
+    fn show(buffer: &Buffer, view: &View) -> String {
+        let mut text = buffer.text().to_vec();
+        text.insert(view.cursor, '|');
+        text.into_iter().collect()
+    }
+
+    fn fill_paragraph(path: &str, text: &str, fill_column: usize) -> String {
+        let (mut buffer, mut view) = buffer(path, text);
+        buffer.fill_paragraph(&mut view, fill_column);
+        show(&buffer, &view)
+    }
+
+    fn auto_fill(path: &str, text: &str, fill_column: usize) -> String {
+        let (mut buffer, mut view) = buffer(path, text);
+        buffer.auto_fill(&mut view, fill_column);
+        show(&buffer, &view)
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn fills_words() {
+        let words = ["a", "abcdefghijkl", "b", "c"].map(chars);
+        let words = words.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let fill = |cursor_chars| {
+            let (filled, cursor) =
+                fill_words(&words, (&chars("> "), &chars("  ")), 5, cursor_chars);
+            let mut filled = filled;
+            filled.insert(cursor, '|');
+            filled.into_iter().collect::<String>()
+        };
+
+        // Long words get a line of their own
+        assert_eq!(fill(2), "> a\n  a|bcdefghijkl\n  b c");
+        assert_eq!(fill(15), "> a\n  abcdefghijkl\n  b c|");
+        assert_eq!(fill(0), "> |a\n  abcdefghijkl\n  b c");
+    }
+
+    #[test]
+    fn finds_list_items() {
+        assert_eq!(bullet_len(&chars("* a")), Some(2));
+        assert_eq!(bullet_len(&chars("-  a")), Some(3));
+        assert_eq!(bullet_len(&chars("12. a")), Some(4));
+        assert_eq!(bullet_len(&chars("3) a")), Some(3));
+        assert_eq!(bullet_len(&chars("1234567890. a")), None);
+        assert_eq!(bullet_len(&chars("*a")), None);
+        assert_eq!(bullet_len(&chars("- ")), None);
+        assert_eq!(bullet_len(&chars("1.5 a")), None);
+        assert_eq!(bullet_len(&[]), None);
+    }
+
+    #[test]
+    fn finds_fill_prefixes() {
+        let lines = ["  /// a", "#b", "# c", "/* d", " * e", " */", "* f"]
+            .map(|l| chars(l).into_boxed_slice());
+        let prefix_len = |index, markdown| fill_prefix_len(&lines, index, markdown);
+
+        assert_eq!(prefix_len(0, false), 6);
+        assert_eq!(prefix_len(1, false), 0);
+        assert_eq!(prefix_len(2, false), 2);
+        assert_eq!(prefix_len(2, true), 0);
+        assert_eq!(prefix_len(4, false), 3);
+        assert_eq!(prefix_len(5, false), 1);
+        // `*` is a bullet outside of block comments
+        assert_eq!(prefix_len(6, false), 0);
+    }
+
+    #[test]
+    fn fills_paragraphs_with_their_prefix() {
+        assert_eq!(
+            fill_paragraph(
+                "a.rs",
+                "// one two three four five six seven\n// eig|ht nine\n\nnext",
+                20
+            ),
+            "// one two three\n// four five six\n// seven eig|ht nine\n\nnext"
+        );
+        assert_eq!(
+            fill_paragraph("a.txt", "  one\n  two three|\nfour\n\nfive", 20),
+            "  one two three| four\n\nfive"
+        );
+        assert_eq!(
+            fill_paragraph("A.java", "/**\n * one t|wo three four\n */", 12),
+            "/**\n * one t|wo\n * three\n * four\n */"
+        );
+        // Blank lines are left alone
+        assert_eq!(fill_paragraph("a.txt", "a\n|\nb", 20), "a\n|\nb");
+    }
+
+    #[test]
+    fn fills_list_items_apart() {
+        assert_eq!(
+            fill_paragraph(
+                "a.md",
+                "- one t|wo three four five\n- six\n# Heading\ntext",
+                20
+            ),
+            "- one t|wo three four\n  five\n- six\n# Heading\ntext"
+        );
+        assert_eq!(
+            fill_paragraph("a.md", "- one two\n  three fo|ur five six\n- next", 20),
+            "- one two three fo|ur\n  five six\n- next"
+        );
+        assert_eq!(
+            fill_paragraph("a.txt", "1. alpha beta gamma|", 12),
+            "1. alpha\n   beta\n   gamma|"
+        );
+    }
+
+    #[test]
+    fn leaves_markdown_headings_alone() {
+        assert_eq!(
+            fill_paragraph("a.md", "# one| two three\ntext", 5),
+            "# one| two three\ntext"
+        );
+        assert_eq!(
+            fill_paragraph("a.md", "# Heading\nte|xt", 20),
+            "# Heading\nte|xt"
+        );
+        // Outside of Markdown `#` starts comments
+        assert_eq!(
+            fill_paragraph("a.sh", "# one| two three", 9),
+            "# one| two\n# three"
+        );
+    }
+
+    #[test]
+    fn breaks_long_lines_while_typing() {
+        assert_eq!(
+            auto_fill("a.sh", "# one two three |", 10),
+            "# one two\n# three |"
+        );
+        assert_eq!(
+            auto_fill("a.md", "- one two three |", 10),
+            "- one two\n  three |"
+        );
+        assert_eq!(
+            auto_fill("a.txt", "one two three| four", 8),
+            "one two\nthree| four"
+        );
+        assert_eq!(
+            auto_fill("a.txt", "ab cdefghijklmno |", 5),
+            "ab\ncdefghijklmno |"
+        );
+        assert_eq!(auto_fill("a.txt", "one two |", 10), "one two |");
+    }
+}
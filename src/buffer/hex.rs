@@ -0,0 +1,217 @@
+use crate::{
+    buffer::{Buffer, Direction, TextObject},
+    editor::View,
+};
+
+pub const HEX_ROW_LEN: usize = 16;
+
+/// How many bytes are looked at to decide whether a file is binary.
+pub const BINARY_SAMPLE_LEN: usize = 8192;
+
+/// How many bytes are read at a time when searching.
+const SEARCH_CHUNK_LEN: usize = 1 << 20;
+
+impl Buffer {
+    // ---------- Hex editing ----------
+
+    /// Bytes in `start..end` of the buffer, which are the characters of
+    /// `text()` as files are read one byte per character.
+    pub fn bytes(&self, start: usize, end: usize) -> Vec<u8> {
+        let end = end.min(self.char_count());
+
+        match &self.large_file {
+            Some(table) => table.read(start, end),
+            None => (start..end)
+                .map(|i| {
+                    if i < self.gap_start {
+                        self.data[i] as u8
+                    } else {
+                        self.data[i + self.gap_len] as u8
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Overwrites half of the byte under the cursor with `digit`, high nibble
+    /// first, then moves on to the next byte.
+    pub fn hex_input(&mut self, view: &mut View, digit: u8) {
+        self.sync_view(view);
+
+        let offset = view.cursor;
+        if offset >= self.char_count() {
+            return;
+        }
+
+        let old = self.bytes(offset, offset + 1)[0];
+        let byte = if view.low_nibble {
+            (old & 0xf0) | digit
+        } else {
+            (digit << 4) | (old & 0x0f)
+        };
+
+        self.replace_range(view, offset, offset + 1, &[byte as char]);
+
+        if view.low_nibble {
+            view.cursor = (offset + 1).min(self.char_count() - 1);
+        } else {
+            view.cursor = offset;
+        }
+        view.low_nibble = !view.low_nibble;
+    }
+
+    /// Moves the cursor to the next occurrence of `pattern` after it, wrapping
+    /// around at the end. Returns false if there is none.
+    pub fn search_bytes(&self, view: &mut View, pattern: &[u8]) -> bool {
+        self.sync_view(view);
+
+        if pattern.is_empty() {
+            return false;
+        }
+
+        let len = self.char_count();
+        let from = (view.cursor + 1).min(len);
+        let found = self
+            .find_bytes(pattern, from, len)
+            .or_else(|| self.find_bytes(pattern, 0, (from + pattern.len() - 1).min(len)));
+
+        if let Some(offset) = found {
+            view.cursor = offset;
+            view.low_nibble = false;
+        }
+        found.is_some()
+    }
+
+    /// Where moving from `offset` in the hex view by `object` in `direction`
+    /// ends up. Lines are rows of `HEX_ROW_LEN` bytes.
+    pub(super) fn hex_object_offset(
+        &self,
+        offset: usize,
+        object: TextObject,
+        direction: Direction,
+    ) -> usize {
+        let last = self.char_count().saturating_sub(1);
+        let row_start = offset - offset % HEX_ROW_LEN;
+
+        match (object, direction) {
+            (TextObject::Line, Direction::Up) => offset.checked_sub(HEX_ROW_LEN).unwrap_or(offset),
+            (TextObject::Line, Direction::Down) if offset + HEX_ROW_LEN <= last => {
+                offset + HEX_ROW_LEN
+            }
+            (TextObject::Line, Direction::Left) => row_start,
+            (TextObject::Line, Direction::Right) => (row_start + HEX_ROW_LEN - 1).min(last),
+            (_, Direction::Left) => offset.saturating_sub(1),
+            (_, Direction::Right) => (offset + 1).min(last),
+            _ => offset,
+        }
+    }
+
+    fn find_bytes(&self, pattern: &[u8], start: usize, end: usize) -> Option<usize> {
+        let mut chunk_start = start;
+
+        while chunk_start + pattern.len() <= end {
+            // Chunks overlap so that matches across their borders are found
+            let chunk_end = (chunk_start + SEARCH_CHUNK_LEN + pattern.len() - 1).min(end);
+            let chunk = self.bytes(chunk_start, chunk_end);
+
+            if let Some(i) = chunk.windows(pattern.len()).position(|w| w == pattern) {
+                return Some(chunk_start + i);
+            }
+            chunk_start += SEARCH_CHUNK_LEN;
+        }
+
+        None
+    }
+}
+
+/// Whether the start of a file looks like binary rather than text.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(BINARY_SAMPLE_LEN)];
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+
+    sample.contains(&0) || control * 10 > sample.len()
+}
+
+/// Parses bytes written as hex digits, such as `de ad be ef`, `0xdeadbeef`
+/// or `0xde 0xad`. Each word can start with `0x`.
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .split_whitespace()
+        .flat_map(|word| word.strip_prefix("0x").unwrap_or(word).chars())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()?;
+
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return None;
+    }
+
+    Some(digits.chunks(2).map(|d| (d[0] << 4) | d[1]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_bytes() {
+        let bytes = Some(vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse_hex("de ad be ef"), bytes);
+        assert_eq!(parse_hex(" 0xdeadbeef "), bytes);
+        assert_eq!(parse_hex("de 0xad 0xBEEF"), bytes);
+        assert_eq!(parse_hex("d ead"), Some(vec![0xde, 0xad]));
+        assert_eq!(parse_hex("dea"), None);
+        assert_eq!(parse_hex("0xde0xad"), None);
+        assert_eq!(parse_hex("xy"), None);
+        assert_eq!(parse_hex(" "), None);
+    }
+
+    #[test]
+    fn tells_binary_from_text() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary(b"text\twith\r\ncontrol \x1b[1mcharacters\x0c"));
+        assert!(is_binary(b"a nul\0"));
+        assert!(is_binary(b"\x01\x02\x03 mostly control"));
+
+        // Only the start of a file is looked at
+        let mut bytes = vec![b'a'; BINARY_SAMPLE_LEN];
+        bytes.push(0);
+        assert!(!is_binary(&bytes));
+    }
+
+    #[test]
+    fn searches_bytes_around_the_end() {
+        let mut buffer = Buffer::new();
+        buffer.set_text("\x00ab\x00ab\x01");
+        let mut view = buffer.view();
+
+        assert!(buffer.search_bytes(&mut view, b"\0ab"));
+        assert_eq!(view.cursor, 3);
+        assert!(buffer.search_bytes(&mut view, b"\0ab"));
+        assert_eq!(view.cursor, 0);
+        assert!(buffer.search_bytes(&mut view, b"b\x01"));
+        assert_eq!(view.cursor, 5);
+        assert!(buffer.search_bytes(&mut view, b"b\x01"));
+        assert_eq!(view.cursor, 5);
+        assert!(!buffer.search_bytes(&mut view, b"ba"));
+        assert!(!buffer.search_bytes(&mut view, b""));
+        assert_eq!(view.cursor, 5);
+    }
+
+    #[test]
+    fn searches_bytes_across_chunks() {
+        // The search starts after the cursor, so its first chunk ends at
+        // `SEARCH_CHUNK_LEN + 1`
+        let mut text = "a".repeat(SEARCH_CHUNK_LEN - 1);
+        text.push_str("\x01\x02\x03\x04");
+        text.push_str(&"a".repeat(16));
+        let mut buffer = Buffer::new();
+        buffer.set_text(&text);
+        let mut view = buffer.view();
+
+        assert!(buffer.search_bytes(&mut view, &[1, 2, 3, 4]));
+        assert_eq!(view.cursor, SEARCH_CHUNK_LEN - 1);
+    }
+}
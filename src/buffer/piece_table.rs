@@ -0,0 +1,468 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::{metadata, File},
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use crate::buffer::{word_offset, Direction, TextObject};
+
+/// How many bytes are scanned for newlines at a time when indexing lines.
+const INDEX_CHUNK_LEN: usize = 1 << 20;
+
+/// How far around the cursor to look when moving by words.
+const WORD_WINDOW_LEN: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+enum Source {
+    Original,
+    Added,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Piece {
+    source: Source,
+    start: usize,
+    len: usize,
+}
+
+/// Text of a large file, kept as a list of pieces of the memory-mapped
+/// original and of an append-only buffer of inserted bytes. Lines are only
+/// indexed as far as they have been looked at.
+#[derive(Debug)]
+pub struct PieceTable {
+    original: Mmap,
+    /// Device and inode of the mapped file
+    file: (u64, u64),
+    added: Vec<u8>,
+    pieces: Vec<Piece>,
+    len: usize,
+    line_starts: RefCell<Vec<usize>>,
+    indexed_to: Cell<usize>,
+}
+
+impl PieceTable {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_metadata = file.metadata()?;
+        // SAFETY: The mapping is only read, and saves replace the file rather
+        // than writing into it. Other programs can still truncate or rewrite
+        // the file in place though, and reading the mapping can then fault
+        // with SIGBUS. The file watcher reports such changes and the file is
+        // mapped again, see `Buffer::reload`, but a read racing the change
+        // before that is not prevented.
+        let original = unsafe { Mmap::map(&file)? };
+        let len = original.len();
+
+        Ok(PieceTable {
+            original,
+            file: (file_metadata.dev(), file_metadata.ino()),
+            added: vec![],
+            pieces: vec![Piece {
+                source: Source::Original,
+                start: 0,
+                len,
+            }],
+            len,
+            line_starts: RefCell::new(vec![0]),
+            indexed_to: Cell::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether `path` is the mapped file itself rather than one that replaced
+    /// it, so that changes to it show in the mapping.
+    pub fn is_mapped(&self, path: &Path) -> bool {
+        metadata(path).is_ok_and(|m| (m.dev(), m.ino()) == self.file)
+    }
+
+    // ---------- Editing ----------
+
+    pub fn insert(&mut self, offset: usize, bytes: &[u8]) {
+        assert!(offset <= self.len, "Insert out of bounds");
+
+        let new_piece = Piece {
+            source: Source::Added,
+            start: self.added.len(),
+            len: bytes.len(),
+        };
+        self.added.extend_from_slice(bytes);
+
+        self.len += bytes.len();
+        self.invalidate_lines(offset);
+
+        let mut piece_start = 0;
+
+        for i in 0..self.pieces.len() {
+            let piece = self.pieces[i];
+            let piece_end = piece_start + piece.len;
+
+            // Typing extends the piece that was added last
+            if piece_end == offset
+                && matches!(piece.source, Source::Added)
+                && piece.start + piece.len == new_piece.start
+            {
+                self.pieces[i].len += new_piece.len;
+                return;
+            }
+
+            if offset < piece_end {
+                let split = offset - piece_start;
+
+                if split == 0 {
+                    self.pieces.insert(i, new_piece);
+                } else {
+                    self.pieces[i].len = split;
+                    self.pieces.insert(i + 1, new_piece);
+                    self.pieces.insert(
+                        i + 2,
+                        Piece {
+                            source: piece.source,
+                            start: piece.start + split,
+                            len: piece.len - split,
+                        },
+                    );
+                }
+                return;
+            }
+
+            piece_start = piece_end;
+        }
+
+        self.pieces.push(new_piece);
+    }
+
+    pub fn delete(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.len, "Delete out of bounds");
+
+        let mut pieces = vec![];
+        let mut piece_start = 0;
+
+        for piece in &self.pieces {
+            let piece_end = piece_start + piece.len;
+
+            if piece_end <= start || end <= piece_start {
+                pieces.push(*piece);
+            } else {
+                if piece_start < start {
+                    pieces.push(Piece {
+                        len: start - piece_start,
+                        ..*piece
+                    });
+                }
+                if end < piece_end {
+                    pieces.push(Piece {
+                        source: piece.source,
+                        start: piece.start + end - piece_start,
+                        len: piece_end - end,
+                    });
+                }
+            }
+
+            piece_start = piece_end;
+        }
+
+        self.pieces = pieces;
+        self.len -= end - start;
+        self.invalidate_lines(start);
+    }
+
+    // ---------- Accessing content ----------
+
+    pub fn read(&self, start: usize, end: usize) -> Vec<u8> {
+        let end = end.min(self.len);
+        let mut bytes = Vec::with_capacity(end.saturating_sub(start));
+        let mut piece_start = 0;
+
+        for piece in &self.pieces {
+            let piece_end = piece_start + piece.len;
+
+            if start < piece_end && piece_start < end {
+                let from = start.max(piece_start) - piece_start + piece.start;
+                let to = end.min(piece_end) - piece_start + piece.start;
+                bytes.extend_from_slice(&self.source(piece.source)[from..to]);
+            }
+
+            if end <= piece_end {
+                break;
+            }
+            piece_start = piece_end;
+        }
+
+        bytes
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        for piece in &self.pieces {
+            writer.write_all(&self.source(piece.source)[piece.start..piece.start + piece.len])?;
+        }
+        Ok(())
+    }
+
+    /// Offset of the start of `line`, counting from 0, if the file has that
+    /// many lines.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        while self.line_starts.borrow().len() <= line && self.index_chunk() {}
+        self.line_starts.borrow().get(line).copied()
+    }
+
+    /// Offset of the newline ending `line`, or the end of the file.
+    pub fn line_end(&self, line: usize) -> usize {
+        self.line_start(line + 1)
+            .map_or(self.len, |start| start - 1)
+    }
+
+    /// The line containing `offset`, counting from 0.
+    pub fn line_of(&self, offset: usize) -> usize {
+        while self.indexed_to.get() < offset && self.index_chunk() {}
+
+        let line_starts = self.line_starts.borrow();
+        match line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    /// Number of newlines found so far. This only covers the whole file once
+    /// something past its last line has been looked at.
+    pub fn indexed_newline_count(&self) -> usize {
+        self.line_starts.borrow().len() - 1
+    }
+
+    /// Offset that moving from `cursor` by `object` in `direction` ends up at.
+    pub fn object_offset(&self, cursor: usize, object: TextObject, direction: Direction) -> usize {
+        match object {
+            TextObject::Char => match direction {
+                Direction::Left => cursor.saturating_sub(1),
+                Direction::Right => (cursor + 1).min(self.len),
+                _ => cursor,
+            },
+            TextObject::Word => {
+                let window_start = cursor.saturating_sub(WORD_WINDOW_LEN);
+                let window = self
+                    .read(window_start, cursor + WORD_WINDOW_LEN)
+                    .iter()
+                    .map(|&byte| byte as char)
+                    .collect::<Vec<_>>();
+                let offset = word_offset(&window, cursor - window_start, direction);
+                (cursor as i64 + offset as i64).clamp(0, self.len as i64) as usize
+            }
+            TextObject::Line => {
+                let line = self.line_of(cursor);
+                let column = cursor - self.line_start(line).unwrap_or(0);
+
+                let target = match direction {
+                    Direction::Up if line > 0 => line - 1,
+                    Direction::Down if self.line_start(line + 1).is_some() => line + 1,
+                    Direction::Left => return cursor - column,
+                    Direction::Right => return self.line_end(line),
+                    _ => return cursor,
+                };
+
+                let target_start = self.line_start(target).unwrap_or(0);
+                target_start + column.min(self.line_end(target) - target_start)
+            }
+        }
+    }
+
+    // ---------- Helper functions ----------
+
+    fn source(&self, source: Source) -> &[u8] {
+        match source {
+            Source::Original => &self.original,
+            Source::Added => &self.added,
+        }
+    }
+
+    /// Records the line starts in the next chunk of unindexed text. Returns
+    /// false once the whole file has been indexed.
+    fn index_chunk(&self) -> bool {
+        let start = self.indexed_to.get();
+        if start >= self.len {
+            return false;
+        }
+
+        let end = (start + INDEX_CHUNK_LEN).min(self.len);
+        let mut line_starts = self.line_starts.borrow_mut();
+
+        for (i, byte) in self.read(start, end).iter().enumerate() {
+            if *byte == b'\n' {
+                line_starts.push(start + i + 1);
+            }
+        }
+
+        self.indexed_to.set(end);
+        true
+    }
+
+    /// Forgets the line starts after `offset`, which an edit has moved.
+    fn invalidate_lines(&mut self, offset: usize) {
+        let line_starts = self.line_starts.get_mut();
+        let kept = line_starts.partition_point(|&start| start <= offset);
+        line_starts.truncate(kept);
+        self.indexed_to.set(self.indexed_to.get().min(offset));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::buffer::Buffer;
+
+    /// A piece table of a temporary file holding `text`.
+    fn table(name: &str, text: &str) -> PieceTable {
+        let path = env::temp_dir().join(format!("piece-table-{}-{}", process::id(), name));
+        fs::write(&path, text).unwrap();
+        let table = PieceTable::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        table
+    }
+
+    fn text(table: &PieceTable) -> String {
+        let mut written = vec![];
+        table.write_to(&mut written).unwrap();
+        assert_eq!(written, table.read(0, table.len()));
+        assert_eq!(written.len(), table.len());
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn inserts_across_pieces() {
+        let mut table = table("insert", "hello world");
+
+        table.insert(5, b",");
+        table.insert(0, b"> ");
+        table.insert(table.len(), b"!");
+        assert_eq!(text(&table), "> hello, world!");
+
+        // Between two pieces, and inside an inserted one
+        table.insert(8, b"_");
+        table.insert(3, b"H");
+        table.insert(1, b">");
+        assert_eq!(text(&table), ">> hHello,_ world!");
+
+        // Typing extends the piece added last
+        let pieces = table.pieces.len();
+        table.insert(12, b"a");
+        table.insert(13, b"b");
+        assert_eq!(table.pieces.len(), pieces + 2);
+        assert_eq!(text(&table), ">> hHello,_ abworld!");
+    }
+
+    #[test]
+    fn deletes_across_pieces() {
+        let mut table = table("delete", "0123456789");
+        table.insert(5, b"abc");
+        table.insert(0, b"xy");
+        assert_eq!(text(&table), "xy01234abc56789");
+
+        table.delete(3, 10);
+        assert_eq!(text(&table), "xy056789");
+        table.delete(0, 4);
+        assert_eq!(text(&table), "6789");
+        table.delete(2, 2);
+        assert_eq!(text(&table), "6789");
+        table.delete(0, table.len());
+        assert_eq!(text(&table), "");
+
+        table.insert(0, b"new");
+        assert_eq!(text(&table), "new");
+    }
+
+    #[test]
+    fn reads_ranges() {
+        let mut table = table("read", "abcdef");
+        table.insert(3, b"XYZ");
+
+        assert_eq!(table.read(2, 7), b"cXYZd");
+        assert_eq!(table.read(3, 6), b"XYZ");
+        assert_eq!(table.read(7, 100), b"ef");
+        assert_eq!(table.read(9, 100), b"");
+    }
+
+    #[test]
+    fn finds_lines() {
+        let mut table = table("lines", "one\ntwo\n\nfour");
+
+        assert_eq!(table.line_start(1), Some(4));
+        assert_eq!(table.line_end(1), 7);
+        assert_eq!(table.line_start(3), Some(9));
+        assert_eq!(table.line_end(3), 13);
+        assert_eq!(table.line_start(4), None);
+        assert_eq!(table.line_of(0), 0);
+        assert_eq!(table.line_of(7), 1);
+        assert_eq!(table.line_of(8), 2);
+        assert_eq!(table.line_of(13), 3);
+        assert_eq!(table.indexed_newline_count(), 3);
+
+        // Edits move the lines after them
+        table.insert(2, b"e\nlin");
+        assert_eq!(table.line_start(1), Some(4));
+        assert_eq!(table.line_start(2), Some(9));
+        assert_eq!(table.line_of(8), 1);
+        assert_eq!(table.line_of(17), 4);
+        table.delete(3, 10);
+        assert_eq!(table.line_start(1), Some(6));
+        assert_eq!(table.line_of(5), 0);
+        assert_eq!(table.line_of(7), 2);
+        assert_eq!(table.indexed_newline_count(), 2);
+    }
+
+    #[test]
+    fn reads_lines_of_large_files() {
+        let mut buffer = Buffer::new();
+        buffer.large_file = Some(table("buffer-lines", "a\nbb\n\nccc\n"));
+        let lines = |first, count| {
+            buffer
+                .lines(first, count)
+                .iter()
+                .map(|line| line.iter().collect::<String>())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lines(0, 2), ["a", "bb"]);
+        assert_eq!(lines(1, 3), ["bb", "", "ccc"]);
+        // The line after the last newline is empty
+        assert_eq!(lines(3, 10), ["ccc", ""]);
+        assert!(lines(5, 1).is_empty());
+        assert!(lines(2, 0).is_empty());
+    }
+
+    #[test]
+    fn maps_files_again_when_reloading() {
+        let path = env::temp_dir().join(format!("piece-table-{}-reload", process::id()));
+        fs::write(&path, "old text").unwrap();
+        let mut buffer = Buffer::new();
+        buffer.path = Some(path.clone());
+        buffer.large_file = Some(PieceTable::open(&path).unwrap());
+        let mut view = buffer.view();
+        view.cursor = 8;
+        buffer.insert(&mut view, '!');
+        assert!(buffer.maps_file());
+
+        // Written over in place, like by `> file`
+        fs::write(&path, "new").unwrap();
+        assert!(buffer.maps_file());
+        buffer.reload().unwrap();
+        assert_eq!(buffer.text().iter().collect::<String>(), "new");
+        assert!(!buffer.modified);
+        buffer.sync_view(&mut view);
+        assert_eq!(view.cursor, 3);
+
+        // Replaced, which leaves the mapped file as it was
+        let other = path.with_extension("new");
+        fs::write(&other, "newer").unwrap();
+        fs::rename(&other, &path).unwrap();
+        assert!(!buffer.maps_file());
+        assert_eq!(buffer.text().iter().collect::<String>(), "new");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+use std::{cmp::Ordering, collections::HashSet};
+
+use regex::Regex;
+
+use crate::{buffer::Buffer, editor::View};
+
+#[derive(Clone, Copy)]
+pub enum SortKey {
+    Lexical,
+    CaseInsensitive,
+    Numeric,
+    /// Whitespace separated field, counting from 1
+    Field(usize),
+}
+
+impl Buffer {
+    // ---------- Region ----------
+
+    pub fn sort_lines(&mut self, view: &mut View, key: SortKey) {
+        self.edit_region_lines(view, |lines| match key {
+            SortKey::Lexical => lines.sort(),
+            SortKey::CaseInsensitive => lines.sort_by_key(|l| l.to_lowercase()),
+            SortKey::Numeric => lines.sort_by(|a, b| compare_numbers(a, b)),
+            SortKey::Field(n) => {
+                lines.sort_by_key(|l| l.split_whitespace().nth(n.max(1) - 1).map(str::to_owned))
+            }
+        });
+    }
+
+    pub fn reverse_lines(&mut self, view: &mut View) {
+        self.edit_region_lines(view, |lines| lines.reverse());
+    }
+
+    /// Removes every line that is equal to an earlier line in the region.
+    pub fn delete_duplicate_lines(&mut self, view: &mut View) {
+        self.edit_region_lines(view, |lines| {
+            let mut seen = HashSet::new();
+            lines.retain(|l| seen.insert(l.clone()));
+        });
+    }
+
+    /// Pads the whitespace before the first match of `pattern` on each line so
+    /// that all of the matches start in the same column.
+    pub fn align_regex(&mut self, view: &mut View, pattern: &Regex) {
+        self.edit_region_lines(view, |lines| {
+            // (end of the text before the match, start of the match) in chars
+            let matches = lines
+                .iter()
+                .map(|l| {
+                    pattern.find(l).map(|m| {
+                        let before = &l[..m.start()];
+                        (before.trim_end().chars().count(), before.chars().count())
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let column = match matches.iter().flatten().map(|(end, _)| end).max() {
+                Some(end) => end + 1,
+                None => return,
+            };
+
+            for (line, m) in lines.iter_mut().zip(matches) {
+                if let Some((end, start)) = m {
+                    let chars = line.chars().collect::<Vec<_>>();
+                    *line = chars[..end]
+                        .iter()
+                        .chain(std::iter::repeat_n(&' ', column - end))
+                        .chain(chars[start..].iter())
+                        .collect();
+                }
+            }
+        });
+    }
+
+    // ---------- Whitespace ----------
+
+    /// Removes the spaces and tabs at the end of every line.
+    pub fn delete_trailing_whitespace(&mut self, view: &mut View) {
+        self.sync_view(view);
+        if self.read_only {
+            return;
+        }
+
+        let text = self.text();
+        let mut trailing = vec![];
+        let mut start = 0;
+        for line in text.split(|c| c == &'\n') {
+            let end = start + line.len();
+            let len = line
+                .iter()
+                .rposition(|c| !matches!(c, ' ' | '\t'))
+                .map_or(0, |i| i + 1);
+            if start + len < end {
+                trailing.push((start + len, end));
+            }
+            start = end + 1;
+        }
+
+        // From the end, so that the offsets before stay the same
+        for (start, end) in trailing.into_iter().rev() {
+            self.splice(start, end, &[]);
+        }
+        self.sync_view(view);
+    }
+
+    /// Replaces the lines touched by the selection with the result of `edit`
+    /// in a single edit, then clears the selection.
+    fn edit_region_lines<F>(&mut self, view: &mut View, edit: F)
+    where
+        F: FnOnce(&mut Vec<String>),
+    {
+        self.sync_view(view);
+
+        let Some((start, end)) = self.get_selection(view) else {
+            return;
+        };
+
+        let text = self.text();
+        let (start_line, end_line) = match end {
+            // A region ending at the start of a line does not include it
+            (line, 1) if line > start.0 => (start.0, line - 1),
+            (line, _) => (start.0, line),
+        };
+        let start = line_offset(&text, start_line);
+        let end = line_offset(&text, end_line)
+            + text[line_offset(&text, end_line)..]
+                .iter()
+                .take_while(|c| c != &&'\n')
+                .count();
+
+        let mut lines = text[start..end]
+            .split(|c| c == &'\n')
+            .map(|l| l.iter().collect::<String>())
+            .collect::<Vec<_>>();
+        edit(&mut lines);
+        let replacement = lines.join("\n").chars().collect::<Vec<_>>();
+
+        self.replace_range(view, start, end, &replacement);
+        view.cursor = start;
+        view.mark = None;
+    }
+}
+
+/// Offset in `text` of the first character of `line`, counting from 1.
+fn line_offset(text: &[char], line: usize) -> usize {
+    text.split(|c| c == &'\n')
+        .take(line - 1)
+        .map(|l| l.len() + 1)
+        .sum()
+}
+
+/// Orders lines by the first number in them, lines without one first.
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    let number = |l: &str| {
+        let digit = l.find(|c: char| c.is_ascii_digit())?;
+        let start = if l[..digit].ends_with('-') {
+            digit - 1
+        } else {
+            digit
+        };
+        let end = l[digit..]
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .map_or(l.len(), |len| digit + len);
+        l[start..end].parse::<f64>().ok()
+    };
+
+    match (number(a), number(b)) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (x, y) => x.is_some().cmp(&y.is_some()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `edit` on a buffer holding `lines`, with the selection from
+    /// `mark` to `cursor`, and gives the lines it ends up with.
+    fn edit_lines(
+        lines: &[&str],
+        (mark, cursor): (usize, usize),
+        edit: impl FnOnce(&mut Buffer, &mut View),
+    ) -> Vec<String> {
+        let mut buffer = Buffer::new();
+        buffer.set_text(&lines.join("\n"));
+        let mut view = buffer.view();
+        view.mark = Some(mark);
+        view.cursor = cursor;
+
+        edit(&mut buffer, &mut view);
+        assert_eq!(view.mark, None);
+        buffer
+            .text_lines()
+            .iter()
+            .map(|line| line.iter().collect())
+            .collect()
+    }
+
+    /// Runs `edit` with all of `lines` selected.
+    fn edit_all(lines: &[&str], edit: impl FnOnce(&mut Buffer, &mut View)) -> Vec<String> {
+        let len = lines.join("\n").chars().count();
+        edit_lines(lines, (0, len), edit)
+    }
+
+    fn sort(lines: &[&str], key: SortKey) -> Vec<String> {
+        edit_all(lines, |buffer, view| buffer.sort_lines(view, key))
+    }
+
+    #[test]
+    fn sorts_lines_by_key() {
+        assert_eq!(
+            sort(&["b", "A", "a", "C"], SortKey::Lexical),
+            ["A", "C", "a", "b"]
+        );
+        // Lines that only differ in case keep their order
+        assert_eq!(
+            sort(&["b", "A", "a", "C"], SortKey::CaseInsensitive),
+            ["A", "a", "b", "C"]
+        );
+        assert_eq!(
+            sort(&["10 a", "2 b", "x", "-3 c", "1.5 d"], SortKey::Numeric),
+            ["x", "-3 c", "1.5 d", "2 b", "10 a"]
+        );
+        // Lines without the field come first
+        assert_eq!(
+            sort(&["a  3", "b 1", "c", " d 2"], SortKey::Field(2)),
+            ["c", "b 1", " d 2", "a  3"]
+        );
+    }
+
+    #[test]
+    fn deletes_later_duplicates() {
+        let lines = ["b", "a", "b", "c", "a", "b "];
+        let deduplicated = edit_all(&lines, |buffer, view| {
+            buffer.delete_duplicate_lines(view);
+        });
+        assert_eq!(deduplicated, ["b", "a", "c", "b "]);
+    }
+
+    #[test]
+    fn aligns_lines_that_match() {
+        let lines = ["a = 1", "long_name = 2", "nothing here", "b=3"];
+        let align = |pattern| {
+            let pattern = Regex::new(pattern).unwrap();
+            edit_all(&lines, |buffer, view| buffer.align_regex(view, &pattern))
+        };
+
+        assert_eq!(
+            align("="),
+            [
+                "a         = 1",
+                "long_name = 2",
+                "nothing here",
+                "b         =3"
+            ]
+        );
+        // Nothing changes without any match
+        assert_eq!(align(":"), lines);
+    }
+
+    #[test]
+    fn edits_whole_lines_of_the_region() {
+        let lines = ["cc", "bb", "aa", "zz"];
+        let reverse = |mark, cursor| {
+            edit_lines(&lines, (mark, cursor), |buffer, view| {
+                buffer.reverse_lines(view)
+            })
+        };
+
+        // Ending in the middle of a line takes all of it
+        assert_eq!(reverse(1, 7), ["aa", "bb", "cc", "zz"]);
+        // Ending at the start of a line leaves it out
+        assert_eq!(reverse(1, 6), ["bb", "cc", "aa", "zz"]);
+        // The cursor can come before the mark
+        assert_eq!(reverse(10, 4), ["cc", "zz", "aa", "bb"]);
+    }
+}
@@ -1,4 +1,9 @@
+use std::path::PathBuf;
+
 use clap::Parser;
+use log::LevelFilter;
+
+use crate::{files::Backup, keymap::KeymapStyle};
 
 #[derive(Parser, Default, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -9,4 +14,47 @@ pub struct CLIArgs {
     /// Turn on debug mode
     #[arg(short, long, default_value_t = false)]
     pub debug: bool,
+
+    /// Column that filling wraps text at
+    #[arg(long, default_value_t = 70)]
+    pub fill_column: usize,
+
+    /// Wrap lines automatically while typing
+    #[arg(long, default_value_t = false)]
+    pub auto_fill: bool,
+
+    /// Draw a ruler at the fill column
+    #[arg(long, default_value_t = false)]
+    pub ruler: bool,
+
+    /// Open the file read-only, paging through it with Space, Backspace and q
+    #[arg(long, default_value_t = false)]
+    pub read_only: bool,
+
+    /// Keep the previous contents of saved files as backups
+    #[arg(long, value_enum, default_value_t = Backup::None)]
+    pub backup: Backup,
+
+    /// Configuration file to read instead of config.toml in the config
+    /// directory
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Keys to edit with, instead of the keymap of the configuration
+    #[arg(long, value_enum)]
+    pub keymap: Option<KeymapStyle>,
+
+    /// File to write the log to, instead of edit.log in the state directory
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Level of the records written to the log file: off, error, warn, info,
+    /// debug or trace [default: info]
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Restore the session with this name, saving it again on quitting.
+    /// Without a name, the session of the working directory is used
+    #[arg(long, value_name = "NAME", require_equals = true)]
+    pub session: Option<Option<String>>,
 }
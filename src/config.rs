@@ -0,0 +1,217 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fs::read_to_string,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{
+    controller::find_command,
+    editor::Editor,
+    error::EditorError,
+    files::config_directory,
+    keymap::{KeymapStyle, Keymaps},
+    renderer::theme::{Theme, THEMES},
+};
+
+/// The user's configuration, read from `config.toml` in the config
+/// directory or the file given with `--config`.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Columns between tab stops
+    tab_width: usize,
+    line_numbers: bool,
+    /// Wrap long lines at the edge of the window instead of cutting them off
+    soft_wrap: bool,
+    /// Remove the whitespace at the end of lines when saving
+    trim_whitespace_on_save: bool,
+    /// Name of one of the `THEMES`
+    theme: String,
+    /// Seconds without keys after which changes are autosaved
+    autosave_interval: u64,
+    /// Emacs or Vim keys
+    keymap: KeymapStyle,
+    /// Key sequences like `C-x C-s` and the commands they run, or tables of
+    /// them for the keymap overlays
+    keys: BTreeMap<String, KeyBinding>,
+    /// Settings for the files of a language, by the name of the language
+    languages: BTreeMap<String, Language>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum KeyBinding {
+    Command(String),
+    Overlay(BTreeMap<String, String>),
+}
+
+/// Settings for the files with one of the `extensions`, taking precedence
+/// over the ones for all files.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Language {
+    extensions: Vec<String>,
+    tab_width: Option<usize>,
+    line_numbers: Option<bool>,
+    soft_wrap: Option<bool>,
+    trim_whitespace_on_save: Option<bool>,
+}
+
+/// The settings that apply to a buffer, which can differ between languages.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub tab_width: usize,
+    pub line_numbers: bool,
+    pub soft_wrap: bool,
+    pub trim_whitespace_on_save: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            tab_width: 4,
+            line_numbers: true,
+            soft_wrap: false,
+            trim_whitespace_on_save: false,
+            theme: "default".to_owned(),
+            autosave_interval: 30,
+            keymap: KeymapStyle::Emacs,
+            keys: BTreeMap::new(),
+            languages: BTreeMap::new(),
+        }
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_directory()?.join("config.toml"))
+}
+
+impl Config {
+    /// Reads the configuration at `path`, which is empty if there is no file.
+    /// Errors name the file and the line they are on.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+
+        let config = toml::from_str::<Config>(&text).map_err(|e| {
+            let message = e.message();
+            match e.span() {
+                Some(span) => {
+                    let line = text[..span.start].matches('\n').count() + 1;
+                    format!("{}:{}: {}", path.display(), line, message)
+                }
+                None => format!("{}: {}", path.display(), message),
+            }
+        })?;
+        config
+            .validate()
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    /// Checks the values the types of the fields allow but the editor
+    /// doesn't.
+    fn validate(&self) -> Result<(), String> {
+        let tab_widths = self.languages.values().filter_map(|l| l.tab_width);
+        let mut tab_widths = [self.tab_width].into_iter().chain(tab_widths);
+        if let Some(width) = tab_widths.find(|width| !(1..=16).contains(width)) {
+            return Err(format!("tab-width must be between 1 and 16, not {}", width));
+        }
+        if self.autosave_interval == 0 {
+            return Err("autosave-interval must be at least 1 second".to_owned());
+        }
+        if Theme::named(&self.theme).is_none() {
+            let names = THEMES.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            return Err(format!(
+                "unknown theme: {} (the themes are {})",
+                self.theme,
+                names.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Makes this the configuration of `editor`, replacing its key bindings
+    /// theme and keymap style. Nothing changes if a binding is invalid.
+    pub fn apply(self, editor: &mut Editor) -> Result<(), String> {
+        let mut keymaps = Keymaps::default();
+        for (keys, binding) in &self.keys {
+            match binding {
+                KeyBinding::Command(command) => bind(&mut keymaps, None, keys, command)?,
+                KeyBinding::Overlay(bindings) => {
+                    for (overlay_keys, command) in bindings {
+                        bind(&mut keymaps, Some(keys), overlay_keys, command)?;
+                    }
+                }
+            }
+        }
+
+        editor.keymaps = keymaps;
+        editor.theme = Theme::named(&self.theme).unwrap_or_default();
+        editor.autosave_idle = Duration::from_secs(self.autosave_interval);
+        // The registers of the Vim keymap are kept across reloads
+        editor.vim = match editor.keymap_style.unwrap_or(self.keymap) {
+            KeymapStyle::Vim => Some(editor.vim.take().unwrap_or_default()),
+            KeymapStyle::Emacs => None,
+        };
+        editor.config = self;
+        Ok(())
+    }
+
+    /// The settings for the file at `path`, with the ones of its language if
+    /// there are any.
+    pub fn settings(&self, path: Option<&Path>) -> Settings {
+        let mut settings = Settings {
+            tab_width: self.tab_width,
+            line_numbers: self.line_numbers,
+            soft_wrap: self.soft_wrap,
+            trim_whitespace_on_save: self.trim_whitespace_on_save,
+        };
+
+        let extension = path.and_then(Path::extension).and_then(OsStr::to_str);
+        let languages = self.languages.values().filter(|language| {
+            let mut extensions = language.extensions.iter();
+            extension.is_some_and(|extension| extensions.any(|e| e == extension))
+        });
+        for language in languages {
+            settings.tab_width = language.tab_width.unwrap_or(settings.tab_width);
+            settings.line_numbers = language.line_numbers.unwrap_or(settings.line_numbers);
+            settings.soft_wrap = language.soft_wrap.unwrap_or(settings.soft_wrap);
+            settings.trim_whitespace_on_save = language
+                .trim_whitespace_on_save
+                .unwrap_or(settings.trim_whitespace_on_save);
+        }
+        settings
+    }
+}
+
+/// Reads the configuration file of `editor` again and applies it.
+pub fn reload(editor: &mut Editor) -> crate::error::Result<()> {
+    let Some(path) = editor.config_path.clone() else {
+        return Ok(());
+    };
+    let config = Config::load(&path).map_err(EditorError::Config)?;
+    config
+        .apply(editor)
+        .map_err(|e| EditorError::Config(format!("{}: {}", path.display(), e)))
+}
+
+fn bind(
+    keymaps: &mut Keymaps,
+    overlay: Option<&str>,
+    keys: &str,
+    command: &str,
+) -> Result<(), String> {
+    if find_command(command).is_none() {
+        return Err(format!("unknown command for {}: {}", keys, command));
+    }
+    keymaps.bind(overlay, keys, command)
+}
@@ -1,22 +1,47 @@
 use std::{
-    io::stdin,
-    path::PathBuf,
+    backtrace::{Backtrace, BacktraceStatus},
+    cmp::Reverse,
+    io::{stderr, stdin, stdout, Write},
+    mem,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use clap::Parser;
+use log::{error, warn, Level, LevelFilter};
 use termion::{event::Key, input::TermRead};
 
 use crate::{
-    buffer::{Buffer, Direction, TextObject},
+    buffer::{Buffer, Mode},
     cli::CLIArgs,
-    editor::Editor,
+    config::{self, config_path},
+    editor::{session_path, BufferId, Echo, Editor, RecentFiles, Session, DIFF_NAME, ECHO_TIMEOUT},
+    error::{EditorError, Result},
+    files::{complete_path, default_directory, FileWatcher},
+    keymap::{keys_name, KeymapStyle, Lookup},
+    logger::{self, default_log_path},
     renderer::{
         debug_terminal_renderer::DebugTerminalRenderer, terminal_renderer::TerminalRenderer,
         Renderer,
     },
 };
 
+mod commands;
+mod vim;
+
+pub use commands::find_command;
+pub use vim::{vim_mode, visual_selection, Vim, VimMode};
+
+use commands::self_insert;
+
 #[derive(Clone, Copy)]
 pub enum EditorState {
     Editing,
@@ -24,28 +49,21 @@ pub enum EditorState {
     Selecting,
 }
 
-fn setup_logger() -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{} {}] {}",
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Debug)
-        .chain(fern::log_file("output.log")?)
-        .apply()?;
-    Ok(())
-}
-
-pub fn run() {
-    setup_logger().unwrap();
-
+/// Runs the editor until it quits, returning the exit status.
+pub fn run() -> i32 {
     // Parsing CLI args
     let args = CLIArgs::parse();
 
+    let log_file = args.log_file.clone().or_else(default_log_path);
+    let log_level = args.log_level.unwrap_or(LevelFilter::Info);
+    // Shown in the echo area, the logger keeps messages even without the file
+    if let Err(e) = logger::setup(log_file.as_deref(), log_level) {
+        match &log_file {
+            Some(path) => warn!("Unable to write the log to {}: {}", path.display(), e),
+            None => warn!("Unable to set up logging: {}", e),
+        }
+    }
+
     let debug = args.debug;
     let path = args
         .path
@@ -59,213 +77,671 @@ pub fn run() {
     };
 
     let mut editor = Editor::new();
-    editor.open_file(path);
-
-    // Main Loop
-    renderer.render_all(&editor);
-    let mut it = stdin().keys();
+    editor.fill_column = args.fill_column;
+    editor.auto_fill = args.auto_fill;
+    editor.fill_column_indicator = args.ruler;
+    editor.backup = args.backup;
+    editor.recent_files = RecentFiles::load();
 
-    loop {
-        let key = it.next().unwrap().unwrap();
-        handle_key(&mut editor, &mut renderer, key);
+    editor.keymap_style = args.keymap;
+    if args.keymap == Some(KeymapStyle::Vim) {
+        editor.vim = Some(Vim::default());
+    }
+    editor.config_path = args.config.or_else(config_path);
+    if let Err(e) = config::reload(&mut editor) {
+        warn!("{}", e);
     }
-}
 
-pub fn handle_key(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, key: Key) {
-    let buffer: &mut Buffer;
-    let state = &mut editor.state;
+    let session = args
+        .session
+        .map(|name| session_path(name.as_deref()).ok_or("no state directory"));
 
-    match state {
-        EditorState::PromptResponse => buffer = &mut editor.minibuffer,
-        _ => buffer = editor.get_focused_buffer_mut(),
-    }
+    // Panics are reported once the terminal is back to normal
+    panic::set_hook(Box::new(|info| {
+        let backtrace = Backtrace::capture();
+        let message = match backtrace.status() {
+            BacktraceStatus::Captured => format!("{}\n{}", info, backtrace),
+            _ => info.to_string(),
+        };
+
+        error!("{}", message);
+        *PANIC_MESSAGE.lock().unwrap() = Some(message);
+    }));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let input = start_reading_keys();
+        match FileWatcher::start(move |path| {
+            let _ = input.send(Input::FileChanged(path));
+        }) {
+            Ok(watcher) => editor.watcher = Some(watcher),
+            Err(e) => warn!("Unable to watch files for changes: {}", e),
+        }
 
-    match key {
-        Key::Char(c) => {
-            buffer.insert(c);
-            match c {
-                '\n' => renderer.render_all(&editor),
-                _ => {
-                    renderer.render_status_line(&editor);
-                    renderer.render_line(&editor)
-                },
+        if let Some(Ok(session)) = &session {
+            match Session::load(session) {
+                Ok(session) => editor.restore_session(session),
+                Err(e) => warn!("Unable to restore the session from {:?}: {}", session, e),
+            }
+        }
+        if path.is_some() || editor.buffer_ids().is_empty() {
+            // The buffer of `path` is focused, even if the session had it open
+            let read_only = args.read_only && path.is_some();
+            match editor.open_file(path) {
+                Ok(()) if read_only => {
+                    let buffer = editor.get_focused_buffer_mut();
+                    buffer.read_only = true;
+                    buffer.view_mode = true;
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    editor.message(Level::Error, &e.to_string());
+                    editor.add_buffer(Buffer::new());
+                }
             }
         }
-        Key::Left => {
-            buffer.go(TextObject::Char, Direction::Left);
-            renderer.render_status_line(&editor);
-            renderer.render_cursor(&editor);
+        offer_recovery(&mut editor, &mut renderer);
+        edit(&mut editor, &mut renderer);
+    }));
+
+    // Leaving raw mode and the alternate screen, whose escape code is still
+    // buffered after that
+    drop(renderer);
+    let _ = stdout().flush();
+
+    let (status, rescue) = match result {
+        Ok(()) => {
+            let saved = match &session {
+                Some(Ok(session)) => editor.session().save(session).map_err(|e| e.to_string()),
+                Some(Err(e)) => Err(e.to_string()),
+                None => Ok(()),
+            };
+            if let Err(e) = saved {
+                eprintln!("Unable to save the session: {}", e);
+            }
+
+            editor.remember_positions();
+            if let Err(e) = editor.recent_files.save() {
+                warn!("Unable to save the recent files: {}", e);
+            }
+            (editor.exit_status.unwrap_or(0), editor.rescue_on_exit)
         }
-        Key::Right => {
-            buffer.go(TextObject::Char, Direction::Right);
-            renderer.render_status_line(&editor);
-            renderer.render_cursor(&editor);
+        Err(_) => {
+            let message = PANIC_MESSAGE.lock().unwrap().take();
+            let message = message.unwrap_or_default();
+            let _ = writeln!(stderr(), "The editor crashed, {}", message);
+            (101, true)
         }
-        Key::Up => {
-            buffer.go(TextObject::Line, Direction::Up);
-            renderer.render_status_line(&editor);
-            renderer.render_cursor(&editor);
+    };
+    if !rescue {
+        return status;
+    }
+
+    // The terminal may be gone, so failing to tell is ignored
+    for (name, result) in editor.rescue_buffers() {
+        let message = match result {
+            Ok(path) => format!(
+                "Unsaved changes to {} were written to {}",
+                name,
+                path.display()
+            ),
+            Err(e) => format!("Unsaved changes to {} were lost: {}", name, e),
+        };
+        warn!("{}", message);
+        let _ = writeln!(stderr(), "{}", message);
+    }
+    status
+}
+
+/// Message of the last panic, set by the panic hook.
+static PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Handles keys until the editor is told to quit.
+fn edit(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) {
+    renderer.render_all(editor);
+    check_file(editor, renderer, editor.focused_buffer());
+    let mut keys_since_autosave = 0;
+    let mut last_key = Instant::now();
+    // When the message in the echo area was shown
+    let mut echoed = None;
+
+    while editor.exit_status.is_none() {
+        for path in mem::take(&mut *CHANGED_FILES.lock().unwrap()) {
+            file_changed(editor, renderer, &path);
         }
-        Key::Down => {
-            buffer.go(TextObject::Line, Direction::Down);
-            renderer.render_status_line(&editor);
-            renderer.render_cursor(&editor);
+        editor.log_messages();
+        let shown = editor.echo.as_ref().map(|echo| echo.shown);
+        if shown != echoed {
+            renderer.render_echo(editor);
+            echoed = shown;
         }
-        Key::Backspace => {
-            let old_line_count = buffer.line_count();
-            buffer.delete(TextObject::Char, Direction::Left);
-
-            if old_line_count == buffer.line_count() {
-                renderer.render_status_line(&editor);
-                renderer.render_line(&editor);
-            } else {
-                renderer.render_all(&editor);
+
+        // Changes are autosaved once typing pauses, or every so many keys.
+        // Messages go away after a while too.
+        let autosave = (keys_since_autosave > 0)
+            .then(|| editor.autosave_idle.saturating_sub(last_key.elapsed()));
+        let echo = shown.map(|shown| ECHO_TIMEOUT.saturating_sub(shown.elapsed()));
+        let timeout = match (autosave, echo) {
+            (Some(autosave), Some(echo)) => Some(autosave.min(echo)),
+            (autosave, echo) => autosave.or(echo),
+        };
+
+        let key = match read_input(timeout) {
+            Some(Input::Key(key)) => key,
+            Some(Input::FileChanged(path)) => {
+                file_changed(editor, renderer, &path);
+                continue;
             }
-        }
-        Key::Ctrl(c) => match c {
-            'd' => {
-                let old_line_count = buffer.line_count();
-                buffer.delete(TextObject::Char, Direction::Right);
-
-                if old_line_count == buffer.line_count() {
-                    renderer.render_status_line(&editor);
-                    renderer.render_line(&editor);
-                } else {
-                    renderer.render_all(&editor);
+            Some(Input::Closed) => {
+                warn!("No more keys can be read, quitting");
+                editor.exit_status = Some(1);
+                editor.rescue_on_exit = true;
+                break;
+            }
+            None => {
+                if shown.is_some_and(|shown| shown.elapsed() >= ECHO_TIMEOUT) {
+                    editor.echo = None;
                 }
+                if keys_since_autosave > 0 && last_key.elapsed() >= editor.autosave_idle {
+                    editor.autosave();
+                    keys_since_autosave = 0;
+                }
+                continue;
             }
-            'b' => {
-                buffer.go(TextObject::Char, Direction::Left);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
+        };
+        last_key = Instant::now();
+
+        // Messages are gone with the next key
+        if editor.echo.take().is_some() {
+            renderer.render_echo(editor);
+            echoed = None;
+        }
+
+        let focused = editor.focused_buffer();
+        let result = handle_key(editor, renderer, key);
+        report(editor, result);
+        editor.sync_views();
+
+        // Files may have changed while their buffers were out of sight
+        if editor.exit_status.is_none() && editor.focused_buffer() != focused {
+            check_file(editor, renderer, editor.focused_buffer());
+        }
+
+        keys_since_autosave += 1;
+        if keys_since_autosave >= editor.autosave_interval {
+            editor.autosave();
+            keys_since_autosave = 0;
+        }
+    }
+}
+
+/// Shows why a command failed in the echo area.
+fn report(editor: &mut Editor, result: Result<()>) {
+    if let Err(e) = result {
+        editor.message(Level::Error, &e.to_string());
+    }
+}
+
+/// What the main loop waits for.
+enum Input {
+    Key(Key),
+    /// A file was written by another program, see `FileWatcher`
+    FileChanged(PathBuf),
+    /// No more keys can be read, like when the terminal is gone
+    Closed,
+}
+
+/// Input from the thread started in `start_reading_keys` and the file watcher.
+static INPUT: OnceLock<Mutex<Receiver<Input>>> = OnceLock::new();
+
+/// Files reported changed while a command was waiting for a key, which are
+/// checked once it is done.
+static CHANGED_FILES: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// Set once `Input::Closed` has been received, since the file watcher keeps
+/// the channel open after the keys stop.
+static INPUT_CLOSED: AtomicBool = AtomicBool::new(false);
+
+/// Reads keys on a thread of their own, so that waiting for one can time out.
+/// Gives a sender for other input to wait for along with the keys.
+fn start_reading_keys() -> Sender<Input> {
+    let (sender, receiver) = channel();
+    let keys = sender.clone();
+
+    thread::spawn(move || {
+        for key in stdin().keys() {
+            let key = match key {
+                Ok(key) => key,
+                Err(e) => {
+                    error!("Unable to read keys: {}", e);
+                    break;
+                }
+            };
+            if keys.send(Input::Key(key)).is_err() {
+                return;
             }
-            'f' => {
-                buffer.go(TextObject::Char, Direction::Right);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
+        }
+        let _ = keys.send(Input::Closed);
+    });
+    INPUT.set(Mutex::new(receiver)).unwrap();
+    sender
+}
+
+/// The next input, unless there is none within `timeout`. Once the keys
+/// have stopped, this is always `Input::Closed`.
+fn read_input(timeout: Option<Duration>) -> Option<Input> {
+    if INPUT_CLOSED.load(Ordering::Relaxed) {
+        return Some(Input::Closed);
+    }
+
+    let input = INPUT.get().unwrap().lock().unwrap();
+    let input = match timeout {
+        Some(timeout) => match input.recv_timeout(timeout) {
+            Ok(input) => input,
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => Input::Closed,
+        },
+        None => input.recv().unwrap_or(Input::Closed),
+    };
+    if let Input::Closed = input {
+        INPUT_CLOSED.store(true, Ordering::Relaxed);
+    }
+    Some(input)
+}
+
+/// The next key. Once the keys have stopped, this is `C-g` so that whatever
+/// waits for a key is cancelled, and the main loop then quits.
+fn read_key() -> Key {
+    loop {
+        match read_input(None) {
+            Some(Input::Key(key)) => return key,
+            Some(Input::FileChanged(path)) => CHANGED_FILES.lock().unwrap().push(path),
+            Some(Input::Closed) | None => return Key::Ctrl('g'),
+        }
+    }
+}
+
+/// Runs the command `key` is bound to, reading more keys while they make up
+/// a prefix like `C-x`. Characters that aren't bound are typed.
+pub fn handle_key(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, key: Key) -> Result<()> {
+    if let Some(result) = vim::handle_key(editor, renderer, key) {
+        return result;
+    }
+
+    let mut keys = vec![key];
+    let lookup = loop {
+        match editor.keymaps.lookup(&overlays(editor), &keys) {
+            Lookup::Prefix => {
+                show_prefix(editor, renderer, &keys);
+                keys.push(read_key());
             }
-            'n' => {
-                buffer.go(TextObject::Line, Direction::Down);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
+            lookup => break lookup,
+        }
+    };
+    if keys.len() > 1 && !prompting(editor) && editor.echo.take().is_some() {
+        renderer.render_echo(editor);
+    }
+
+    match (lookup, keys.as_slice()) {
+        (Lookup::Command(name), _) => run_command(editor, renderer, &name),
+        (_, [Key::Char(c)]) => {
+            if !prompting(editor) && refuse_read_only(editor) {
+                return Ok(());
             }
-            'p' => {
-                buffer.go(TextObject::Line, Direction::Up);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
+            self_insert(editor, renderer, *c)
+        }
+        // Keys that were started by mistake are cancelled with C-g
+        (_, [.., Key::Ctrl('g')]) => Ok(()),
+        _ => Err(EditorError::Unsupported(keys_name(&keys))),
+    }
+}
+
+/// Runs the command named `name`.
+pub fn run_command(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    name: &str,
+) -> Result<()> {
+    let Some(command) = find_command(name) else {
+        return Err(EditorError::Unsupported(name.to_owned()));
+    };
+    if command.edits && !prompting(editor) && refuse_read_only(editor) {
+        return Ok(());
+    }
+    (command.run)(editor, renderer)
+}
+
+/// Names of the keymap overlays that apply to the buffer keys go to, see
+/// `Keymaps`.
+fn overlays(editor: &Editor) -> Vec<&'static str> {
+    if prompting(editor) {
+        return vec!["minibuffer"];
+    }
+
+    let buffer = editor.get_focused_buffer();
+    let mut overlays = vec![];
+    if let Some(mode) = vim_mode(editor) {
+        overlays.push(mode.overlay());
+    }
+    if buffer.view_mode {
+        overlays.push("view");
+    }
+    overlays.push(buffer.mode.name());
+    overlays
+}
+
+/// Shows the keys of a pending prefix in the echo area, like `C-x-`.
+fn show_prefix(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, keys: &[Key]) {
+    if prompting(editor) {
+        return;
+    }
+    editor.echo = Some(Echo {
+        level: Level::Info,
+        text: format!("{}-", keys_name(keys)),
+        shown: Instant::now(),
+    });
+    renderer.render_echo(editor);
+}
+
+fn prompting(editor: &Editor) -> bool {
+    matches!(editor.state, EditorState::PromptResponse)
+}
+
+/// Offers to recover the focused buffer from the changes autosaved by an
+/// editor that exited without saving them.
+fn offer_recovery(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) {
+    let id = editor.focused_buffer();
+    let Some(recovery) = editor.recovery_file(id) else {
+        return;
+    };
+
+    let message = format!(
+        "{} has unsaved changes in {} (recover, diff or discard)",
+        editor.get_focused_buffer().name,
+        recovery.display()
+    );
+    let choices = ["recover", "diff", "discard"].map(str::to_owned);
+
+    loop {
+        renderer.render_all(editor);
+        editor.state = EditorState::PromptResponse;
+        let response = prompt_with_completion(editor, renderer, &message, &|input| {
+            complete_from(&choices, input)
+        });
+        editor.state = EditorState::Editing;
+
+        let result = match response.as_deref().map(str::trim) {
+            Some("recover") => editor.recover(id),
+            Some("diff") => match editor.show_recovery_diff(id) {
+                Ok(()) => continue,
+                Err(e) => Err(e),
+            },
+            Some("discard") => editor.discard_recovery(id),
+            // Keeping the file for later
+            _ => Ok(()),
+        };
+
+        if let Some(diff) = editor.find_buffer(DIFF_NAME) {
+            editor.kill_buffer(diff);
+        }
+        editor.focus_buffer(id);
+        renderer.render_all(editor);
+
+        if let Err(e) = result {
+            editor.message(Level::Error, &format!("Unable to recover: {}", e));
+        }
+        return;
+    }
+}
+
+/// Checks the buffers visiting `path`, which another program wrote. Modified
+/// buffers out of sight are checked once they are focused, unless they are
+/// large files mapped from `path`.
+fn file_changed(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, path: &Path) {
+    for id in editor.buffers_visiting(path) {
+        let check = editor
+            .get_buffer(id)
+            .is_some_and(|b| !b.modified || b.maps_file());
+        if id == editor.focused_buffer() || check {
+            check_file(editor, renderer, id);
+        }
+    }
+}
+
+/// Reloads buffer `id` if another program changed its file, asking first if
+/// the buffer has changes of its own. Large files changed in place are
+/// always reloaded, since their mapped text can't be read anymore.
+fn check_file(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, id: BufferId) {
+    if !editor.file_changed(id) {
+        return;
+    }
+    let buffer = editor.get_buffer(id).unwrap();
+    let name = buffer.name.clone();
+
+    if !buffer.modified || buffer.maps_file() {
+        let modified = buffer.modified;
+        match editor.reload(id) {
+            Ok(()) if modified => {
+                let message = format!("{} was changed in place on disk, its edits were lost", name);
+                editor.message(Level::Warn, &message);
             }
-            'e' => {
-                buffer.go(TextObject::Line, Direction::Right);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
+            Ok(()) => {
+                let message = format!("Reverted {}, which changed on disk", name);
+                editor.message(Level::Info, &message);
             }
-            'a' => {
-                buffer.go(TextObject::Line, Direction::Left);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
+            Err(e) => editor.message(Level::Error, &format!("Unable to reload {}: {}", name, e)),
+        }
+        renderer.render_all(editor);
+        return;
+    }
+
+    let message = format!("{} changed on disk (reload, keep or diff)", name);
+    let choices = ["reload", "keep", "diff"].map(str::to_owned);
+
+    loop {
+        renderer.render_all(editor);
+        editor.state = EditorState::PromptResponse;
+        let response = prompt_with_completion(editor, renderer, &message, &|input| {
+            complete_from(&choices, input)
+        });
+        editor.state = EditorState::Editing;
+
+        let result = match response.as_deref().map(str::trim) {
+            Some("reload") => editor.reload(id),
+            Some("keep") => {
+                editor.keep_changes(id);
+                Ok(())
             }
-            'k' => {
-                buffer.delete(TextObject::Line, Direction::Right);
-                renderer.render_all(&editor);
+            Some("diff") => match editor.show_disk_diff(id) {
+                Ok(()) => continue,
+                Err(e) => Err(e),
+            },
+            // Asking again when the buffer is next focused
+            _ => Ok(()),
+        };
+
+        if let Some(diff) = editor.find_buffer(DIFF_NAME) {
+            editor.kill_buffer(diff);
+        }
+        editor.focus_buffer(id);
+        renderer.render_all(editor);
+
+        if let Err(e) = result {
+            editor.message(Level::Error, &format!("Unable to reload {}: {}", name, e));
+        }
+        return;
+    }
+}
+
+/// Saves the focused buffer, asking for a file name if it has none. Returns
+/// whether it was saved.
+fn save_buffer(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> bool {
+    let new_path = match editor.get_focused_buffer().path {
+        Some(_) => None,
+        None => {
+            editor.state = EditorState::PromptResponse;
+            let response = prompt(editor, renderer, "Enter a file name");
+            editor.state = EditorState::Editing;
+
+            match response {
+                Some(new_path) => Some(new_path),
+                None => return false,
             }
-            's' => {
-                match buffer.path {
-                    Some(_) => editor.save_buffer(None),
-                    None => {
-                        editor.state = EditorState::PromptResponse;
-                        match prompt(editor, renderer, "Enter a file name") {
-                            Some(new_path) => {
-                                editor.save_buffer(Some(new_path));
-                            }
-                            None => {
-                                editor.state = EditorState::Editing;
-                            }
+        }
+    };
+
+    let id = editor.focused_buffer();
+    if new_path.is_none()
+        && editor.file_changed(id)
+        && !yes_or_no(editor, renderer, "The file changed on disk; save anyway?")
+    {
+        return false;
+    }
+
+    match editor.save_buffer(new_path) {
+        Ok(()) => true,
+        Err(e) => {
+            let name = editor.get_focused_buffer().name.clone();
+            editor.message(Level::Error, &format!("Unable to save {}: {}", name, e));
+            false
+        }
+    }
+}
+
+/// Asks whether to save each modified buffer before quitting. Quitting is
+/// called off if the user cancels, and exits with a failure status if a
+/// save did not go through.
+fn quit(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) {
+    let modified = editor
+        .buffer_ids()
+        .into_iter()
+        .filter(|&id| {
+            editor.get_buffer(id).is_some_and(|b| {
+                b.modified && matches!(b.mode, Mode::Text | Mode::Hex | Mode::Wdired(_))
+            })
+        })
+        .collect::<Vec<_>>();
+    let choices = ["save", "don't save", "cancel"].map(str::to_owned);
+    let mut failed = false;
+
+    for id in modified {
+        editor.focus_buffer(id);
+        let name = editor.get_focused_buffer().name.clone();
+        let mut message = format!("Save {}? (save, don't save or cancel)", name);
+
+        loop {
+            renderer.render_all(editor);
+            editor.state = EditorState::PromptResponse;
+            let response = prompt_with_completion(editor, renderer, &message, &|input| {
+                complete_from(&choices, input)
+            });
+            editor.state = EditorState::Editing;
+
+            match response.as_deref().map(str::trim) {
+                Some("save") => {
+                    // Saving a Wdired buffer renames the files
+                    let saved = match editor.get_focused_buffer().mode {
+                        Mode::Wdired(_) => {
+                            editor.wdired_finish();
+                            !editor.get_focused_buffer().modified
                         }
+                        _ => save_buffer(editor, renderer),
+                    };
+                    if saved {
+                        break;
                     }
+                    failed = true;
+                    message = format!("{} was not saved (save, don't save or cancel)", name);
                 }
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
-            }
-            't' => {
-                buffer.toggle_selection();
-                editor.state = EditorState::Selecting;
-                handle_key_selection(editor, renderer);
-            }
-            'c' => {
-                panic!("not sure how to implement exit")
-            }
-            'y' => {
-                buffer.paste_from_clipboard();
-                renderer.render_all(&editor);
-            }
-            _ => {
-                todo!("Ctrl-{} not implemented", c);
-            }
-        },
-        Key::Alt(c) => match c {
-            'w' => {
-                buffer.copy_to_clipboard();
-            }
-            'f' => {
-                buffer.go(TextObject::Word, Direction::Right);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
-            }
-            'b' => {
-                buffer.go(TextObject::Word, Direction::Left);
-                renderer.render_status_line(&editor);
-                renderer.render_cursor(&editor);
-            }
-            'd' => {
-                let old_line_count = buffer.line_count();
-                buffer.delete(TextObject::Word, Direction::Right);
-
-                if old_line_count == buffer.line_count() {
-                    renderer.render_status_line(&editor);
-                    renderer.render_line(&editor);
-                } else {
-                    renderer.render_all(&editor);
+                Some("don't save") => {
+                    if let Err(e) = editor.discard_recovery(id) {
+                        warn!("Unable to remove the recovery file: {}", e);
+                    }
+                    editor.get_focused_buffer_mut().modified = false;
+                    break;
                 }
-            }
-            '\u{7f}' => {
-                let old_line_count = buffer.line_count();
-                buffer.delete(TextObject::Word, Direction::Left);
-
-                if old_line_count == buffer.line_count() {
-                    renderer.render_status_line(&editor);
-                    renderer.render_line(&editor);
-                } else {
-                    renderer.render_all(&editor);
+                Some("cancel") | None => {
+                    renderer.render_all(editor);
+                    return;
                 }
+                _ => {}
             }
-            '<' => {
-                buffer.go_to_start();
-                renderer.render_all(&editor);
-            }
-            '>' => {
-                buffer.go_to_end();
-                renderer.render_all(&editor);
-            }
-            _ => {}
-        },
-        _ => {}
+        }
     }
+
+    editor.exit_status = Some(if failed { 1 } else { 0 });
+}
+
+/// Tells that the focused buffer is read-only, if it is.
+fn refuse_read_only(editor: &mut Editor) -> bool {
+    let buffer = editor.get_focused_buffer();
+    if !buffer.read_only {
+        return false;
+    }
+
+    let message = format!("{} is read-only", buffer.name);
+    editor.message(Level::Warn, &message);
+    true
 }
 
 fn prompt(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, message: &str) -> Option<String> {
+    prompt_with_completion(editor, renderer, message, &|_| vec![])
+}
+
+/// Prompts like `prompt`, completing the response with Tab. `complete` gives
+/// the candidates for the current input.
+fn prompt_with_completion(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    message: &str,
+    complete: &dyn Fn(&str) -> Vec<String>,
+) -> Option<String> {
     assert!(matches!(editor.state, EditorState::PromptResponse));
+    // Up and Down go through the earlier responses
+    let mut history_index = editor.minibuffer_history.len();
 
-    let mut it = stdin().keys();
     loop {
         renderer.render_minibuffer_prompt(editor, message);
-        let key = it.next().unwrap().unwrap();
+        let key = read_key();
+
+        if key != Key::Char('\t') {
+            editor.completions.clear();
+        }
+
         match key {
+            Key::Up | Key::Down if !editor.minibuffer_history.is_empty() => {
+                history_index = match key {
+                    Key::Up => history_index.saturating_sub(1),
+                    _ => (history_index + 1).min(editor.minibuffer_history.len()),
+                };
+                let response = editor.minibuffer_history.get(history_index);
+                let response = response.map_or("", String::as_str);
+                editor.minibuffer.set_text(response);
+                editor.minibuffer.go_to_end(&mut editor.minibuffer_view);
+            }
             Key::Char(c) => match c {
+                '\t' => {
+                    let input = editor.minibuffer.text().iter().collect::<String>();
+                    let candidates = complete(&input);
+                    let prefix = common_prefix(&candidates);
+
+                    // Candidates may match the input anywhere, not just at
+                    // their start
+                    let extends = candidates.len() == 1 || prefix.starts_with(&input);
+                    if extends && prefix.len() > input.len() {
+                        editor.minibuffer.set_text(&prefix);
+                        editor.minibuffer.go_to_end(&mut editor.minibuffer_view);
+                    }
+                    editor.completions = if candidates.len() > 1 {
+                        candidates.iter().map(|c| candidate_label(c)).collect()
+                    } else {
+                        vec![]
+                    };
+                }
                 '\n' => {
                     editor.state = EditorState::Editing;
                     break;
                 }
-                _ => editor.minibuffer.insert(c),
+                _ => editor.minibuffer.insert(&mut editor.minibuffer_view, c),
             },
             Key::Ctrl(c) => {
                 match c {
@@ -273,55 +749,181 @@ fn prompt(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, message: &str)
                         // Don't allow saves in minibuffer
                     }
                     'g' => {
+                        editor.minibuffer.clear();
+                        editor.state = EditorState::Editing;
                         renderer.clear_minibuffer(&editor);
                         return None;
                     }
-                    _ => handle_key(editor, renderer, key),
+                    _ => {
+                        let result = handle_key(editor, renderer, key);
+                        report(editor, result);
+                    }
                 }
             }
-            _ => handle_key(editor, renderer, key),
+            _ => {
+                let result = handle_key(editor, renderer, key);
+                report(editor, result);
+            }
         }
     }
 
     let response = editor.minibuffer.text().iter().collect::<String>();
+    editor.add_to_history(&response);
     editor.minibuffer.clear();
     renderer.clear_minibuffer(&editor);
     Some(response)
 }
 
+/// Prompts for a file name, starting from the directory of the focused
+/// buffer and completing paths.
+fn prompt_file_name(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    message: &str,
+) -> Option<String> {
+    let directory = default_directory(editor.get_focused_buffer().directory());
+    editor.minibuffer.set_text(&directory);
+    editor.minibuffer.go_to_end(&mut editor.minibuffer_view);
+
+    editor.state = EditorState::PromptResponse;
+    let response = prompt_with_completion(editor, renderer, message, &complete_path);
+    editor.state = EditorState::Editing;
+    response
+}
+
+/// Asks a yes or no question in the minibuffer, anything but "yes" or "y"
+/// counts as no.
+fn yes_or_no(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, message: &str) -> bool {
+    let state = editor.state;
+    editor.state = EditorState::PromptResponse;
+    let response = prompt(editor, renderer, &format!("{} (yes or no)", message));
+    editor.state = state;
+
+    matches!(response.as_deref().map(str::trim), Some("yes" | "y"))
+}
+
+/// The candidates starting with `input`.
+fn complete_from(candidates: &[String], input: &str) -> Vec<String> {
+    candidates
+        .iter()
+        .filter(|candidate| candidate.starts_with(input))
+        .cloned()
+        .collect()
+}
+
+/// The candidates that have the characters of `input` in order, though not
+/// necessarily next to each other, best matches first. Runs of characters
+/// and matches at the start of words, after a `-`, count for more, so `eob`
+/// finds `end-of-buffer`.
+fn complete_fuzzy(candidates: &[String], input: &str) -> Vec<String> {
+    let mut matches = candidates
+        .iter()
+        .filter_map(|candidate| Some((fuzzy_score(candidate, input)?, candidate)))
+        .collect::<Vec<_>>();
+    // Shorter candidates first among equal matches
+    matches.sort_by_key(|(score, candidate)| (Reverse(*score), candidate.len()));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate.clone())
+        .collect()
+}
+
+/// How well `input` matches `candidate`, if it does at all.
+fn fuzzy_score(candidate: &str, input: &str) -> Option<usize> {
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+    let mut chars = input.chars().peekable();
+
+    for c in candidate.chars() {
+        let Some(&wanted) = chars.peek() else {
+            break;
+        };
+        if c.eq_ignore_ascii_case(&wanted) {
+            chars.next();
+            score += 1;
+            if consecutive {
+                score += 2;
+            }
+            if matches!(previous, None | Some('-' | '_' | ' ' | '/')) {
+                score += 3;
+            }
+            consecutive = true;
+        } else {
+            consecutive = false;
+        }
+        previous = Some(c);
+    }
+
+    chars.peek().is_none().then_some(score)
+}
+
+/// The last component of a path, which is what tells candidates apart. Other
+/// candidates are shown whole.
+fn candidate_label(candidate: &str) -> String {
+    match candidate.trim_end_matches('/').rfind('/') {
+        Some(i) => candidate[i + 1..].to_owned(),
+        None => candidate.to_owned(),
+    }
+}
+
+/// The longest prefix that all of `candidates` share.
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(candidate.len()), |((i, _), _)| i.min(len));
+    }
+    first[..len].to_owned()
+}
+
 fn handle_key_selection(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) {
     assert!(matches!(editor.state, EditorState::Selecting));
 
-    let mut it = stdin().keys();
     loop {
         renderer.render_all(editor);
-        let key = it.next().unwrap().unwrap();
+        let key = read_key();
+        // Outside of insert mode, Vim's keys move the cursor rather than type
+        let typing = matches!(vim_mode(editor), None | Some(VimMode::Insert));
 
         match key {
-            Key::Char(_) => {
-                handle_key(editor, renderer, key);
-                editor.get_focused_buffer_mut().delete_selection();
+            Key::Char(_) if typing => {
+                // Typed text replaces the selection
+                let (buffer, view) = editor.focused_mut();
+                buffer.delete_selection(view);
+                let result = handle_key(editor, renderer, key);
+                report(editor, result);
                 renderer.render_all(editor);
                 break;
             }
-            Key::Backspace => {
-                editor.get_focused_buffer_mut().delete_selection();
+            Key::Backspace if typing => {
+                let (buffer, view) = editor.focused_mut();
+                buffer.delete_selection(view);
                 renderer.render_all(editor);
                 break;
             }
-            Key::Ctrl(c) => {
-                match c {
-                    't' | 'g' => {
-                        editor.get_focused_buffer_mut().toggle_selection();
-                        renderer.render_all(editor);
-                        break;
-                    }
-                    _ => {
-                        handle_key(editor, renderer, key);
-                    }
-                }
+            Key::Ctrl('t' | 'g') => {
+                let (buffer, view) = editor.focused_mut();
+                buffer.toggle_selection(view);
+                renderer.render_all(editor);
+                break;
             }
-            _ => handle_key(editor, renderer, key),
+            _ => {
+                let result = handle_key(editor, renderer, key);
+                report(editor, result);
+            }
+        }
+
+        // Commands that act on the selection also end it
+        if editor.get_focused_view().mark.is_none() {
+            break;
         }
     }
     editor.state = EditorState::Editing;
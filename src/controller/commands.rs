@@ -0,0 +1,1044 @@
+use log::{warn, Level};
+use regex::Regex;
+
+use crate::{
+    buffer::{parse_hex, Buffer, Direction, Mode, SortKey, TextObject},
+    config,
+    controller::{
+        complete_from, complete_fuzzy, handle_key_selection, offer_recovery, prompt,
+        prompt_file_name, prompt_with_completion, quit, run_command, save_buffer, vim, yes_or_no,
+        EditorState,
+    },
+    editor::{Axis, Editor, View},
+    error::{EditorError, Result},
+    files::{abbreviate_home, expand_tilde},
+    renderer::{terminal_renderer::window_area, Renderer},
+};
+
+/// A command keys can be bound to.
+pub struct Command {
+    pub name: &'static str,
+    /// What it does, in a sentence
+    pub description: &'static str,
+    /// Whether it changes the text, which read-only buffers refuse
+    pub edits: bool,
+    pub run: fn(&mut Editor, &mut Box<dyn Renderer>) -> Result<()>,
+}
+
+/// Every command, the ones keymaps, M-x and the configuration refer to by
+/// name.
+pub fn commands() -> &'static [Command] {
+    COMMANDS
+}
+
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+const COMMANDS: &[Command] = &[
+    Command {
+        name: "forward-char",
+        description: "Move forward one character.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Char, Direction::Right),
+    },
+    Command {
+        name: "backward-char",
+        description: "Move back one character.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Char, Direction::Left),
+    },
+    Command {
+        name: "next-line",
+        description: "Move down one line.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Line, Direction::Down),
+    },
+    Command {
+        name: "previous-line",
+        description: "Move up one line.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Line, Direction::Up),
+    },
+    Command {
+        name: "beginning-of-line",
+        description: "Move to the start of the line.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Line, Direction::Left),
+    },
+    Command {
+        name: "end-of-line",
+        description: "Move to the end of the line.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Line, Direction::Right),
+    },
+    Command {
+        name: "forward-word",
+        description: "Move forward one word.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Word, Direction::Right),
+    },
+    Command {
+        name: "backward-word",
+        description: "Move back one word.",
+        edits: false,
+        run: |editor, renderer| go(editor, renderer, TextObject::Word, Direction::Left),
+    },
+    Command {
+        name: "beginning-of-buffer",
+        description: "Move to the start of the buffer.",
+        edits: false,
+        run: |editor, renderer| {
+            let (buffer, view) = current(editor);
+            buffer.go_to_start(view);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "end-of-buffer",
+        description: "Move to the end of the buffer.",
+        edits: false,
+        run: |editor, renderer| {
+            let (buffer, view) = current(editor);
+            buffer.go_to_end(view);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "delete-backward-char",
+        description: "Delete the character before the cursor.",
+        edits: true,
+        run: |editor, renderer| delete(editor, renderer, TextObject::Char, Direction::Left),
+    },
+    Command {
+        name: "delete-char",
+        description: "Delete the character at the cursor.",
+        edits: true,
+        run: |editor, renderer| delete(editor, renderer, TextObject::Char, Direction::Right),
+    },
+    Command {
+        name: "kill-word",
+        description: "Delete up to the end of the word.",
+        edits: true,
+        run: |editor, renderer| delete(editor, renderer, TextObject::Word, Direction::Right),
+    },
+    Command {
+        name: "backward-kill-word",
+        description: "Delete back to the start of the word.",
+        edits: true,
+        run: |editor, renderer| delete(editor, renderer, TextObject::Word, Direction::Left),
+    },
+    Command {
+        name: "kill-line",
+        description: "Delete up to the end of the line.",
+        edits: true,
+        run: |editor, renderer| {
+            let (buffer, view) = current(editor);
+            buffer.delete(view, TextObject::Line, Direction::Right);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "yank",
+        description: "Paste the text copied last.",
+        edits: true,
+        run: |editor, renderer| {
+            editor.yank();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "copy-region",
+        description: "Copy the selected text.",
+        edits: false,
+        run: |editor, _| {
+            let (buffer, view) = current(editor);
+            if let Some(text) = buffer.copy_selection(view) {
+                editor.kill(text);
+            }
+            Ok(())
+        },
+    },
+    Command {
+        name: "set-mark",
+        description: "Start selecting text from the cursor.",
+        edits: false,
+        run: |editor, renderer| {
+            let (buffer, view) = current(editor);
+            text_command(buffer, "set-mark")?;
+            buffer.toggle_selection(view);
+            editor.state = EditorState::Selecting;
+            handle_key_selection(editor, renderer);
+            Ok(())
+        },
+    },
+    Command {
+        name: "fill-paragraph",
+        description: "Break the lines of the paragraph at the fill column.",
+        edits: true,
+        run: |editor, renderer| {
+            let fill_column = editor.fill_column;
+            let (buffer, view) = current(editor);
+            text_command(buffer, "fill-paragraph")?;
+            buffer.fill_paragraph(view, fill_column);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "save-buffer",
+        description: "Save the buffer to its file, asking for a name if it has none.",
+        edits: false,
+        run: |editor, renderer| {
+            save_buffer(editor, renderer);
+            renderer.render_status_line(editor);
+            renderer.render_cursor(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "exit",
+        description: "Quit at once, keeping unsaved changes in recovery files.",
+        edits: false,
+        run: |editor, _| {
+            editor.exit_status = Some(0);
+            editor.rescue_on_exit = true;
+            Ok(())
+        },
+    },
+    Command {
+        name: "save-buffers-kill-editor",
+        description: "Offer to save each modified buffer, then quit.",
+        edits: false,
+        run: |editor, renderer| {
+            quit(editor, renderer);
+            Ok(())
+        },
+    },
+    Command {
+        name: "execute-command",
+        description: "Run a command by name.",
+        edits: false,
+        run: execute_command,
+    },
+    Command {
+        name: "describe-command",
+        description: "Show what a command does and the keys it is bound to.",
+        edits: false,
+        run: describe_command,
+    },
+    Command {
+        name: "reload-config",
+        description: "Read the configuration file again.",
+        edits: false,
+        run: |editor, renderer| {
+            config::reload(editor)?;
+            if let Some(path) = &editor.config_path {
+                let message = format!("Reloaded {}", abbreviate_home(path));
+                editor.message(Level::Info, &message);
+            }
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "set-fill-column",
+        description: "Set the column lines are filled to.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.state = EditorState::PromptResponse;
+            let response = prompt(editor, renderer, "Set fill column");
+            editor.state = EditorState::Editing;
+            renderer.render_all(editor);
+
+            if let Some(response) = response {
+                match response.trim().parse::<usize>() {
+                    Ok(fill_column) => editor.fill_column = fill_column,
+                    Err(_) => {
+                        let input = format!("fill column: {}", response);
+                        return Err(EditorError::InvalidInput(input));
+                    }
+                }
+            }
+            Ok(())
+        },
+    },
+    Command {
+        name: "sort-lines",
+        description: "Sort the selected lines.",
+        edits: true,
+        run: |editor, renderer| sort_lines(editor, renderer, "sort-lines", SortKey::Lexical),
+    },
+    Command {
+        name: "sort-lines-case-insensitive",
+        description: "Sort the selected lines, ignoring case.",
+        edits: true,
+        run: |editor, renderer| {
+            sort_lines(
+                editor,
+                renderer,
+                "sort-lines-case-insensitive",
+                SortKey::CaseInsensitive,
+            )
+        },
+    },
+    Command {
+        name: "sort-lines-numerically",
+        description: "Sort the selected lines by the number they start with.",
+        edits: true,
+        run: |editor, renderer| {
+            sort_lines(editor, renderer, "sort-lines-numerically", SortKey::Numeric)
+        },
+    },
+    Command {
+        name: "sort-lines-by-field",
+        description: "Sort the selected lines by a whitespace-separated field.",
+        edits: true,
+        run: |editor, renderer| {
+            text_command(editor.get_focused_buffer(), "sort-lines-by-field")?;
+            editor.state = EditorState::PromptResponse;
+            let response = prompt(editor, renderer, "Sort by field");
+            editor.state = EditorState::Editing;
+
+            renderer.render_all(editor);
+
+            if let Some(response) = response {
+                match response.trim().parse::<usize>() {
+                    Ok(field) => {
+                        let key = SortKey::Field(field);
+                        return sort_lines(editor, renderer, "sort-lines-by-field", key);
+                    }
+                    Err(_) => {
+                        let input = format!("field: {}", response);
+                        return Err(EditorError::InvalidInput(input));
+                    }
+                }
+            }
+            Ok(())
+        },
+    },
+    Command {
+        name: "reverse-lines",
+        description: "Reverse the order of the selected lines.",
+        edits: true,
+        run: |editor, renderer| {
+            let (buffer, view) = editor.focused_mut();
+            text_command(buffer, "reverse-lines")?;
+            buffer.reverse_lines(view);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "delete-duplicate-lines",
+        description: "Delete repeated lines from the selection.",
+        edits: true,
+        run: |editor, renderer| {
+            let (buffer, view) = editor.focused_mut();
+            text_command(buffer, "delete-duplicate-lines")?;
+            buffer.delete_duplicate_lines(view);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "align-regexp",
+        description: "Align the selected lines on a regexp.",
+        edits: true,
+        run: |editor, renderer| {
+            text_command(editor.get_focused_buffer(), "align-regexp")?;
+            editor.state = EditorState::PromptResponse;
+            let response = prompt(editor, renderer, "Align regexp");
+            editor.state = EditorState::Editing;
+
+            if let Some(response) = response {
+                match Regex::new(&response) {
+                    Ok(pattern) => {
+                        let (buffer, view) = editor.focused_mut();
+                        buffer.align_regex(view, &pattern);
+                    }
+                    Err(e) => {
+                        renderer.render_all(editor);
+                        let input = format!("regexp {}: {}", response, e);
+                        return Err(EditorError::InvalidInput(input));
+                    }
+                }
+            }
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "switch-to-buffer",
+        description: "Show another buffer in the window.",
+        edits: false,
+        run: switch_to_buffer,
+    },
+    Command {
+        name: "kill-buffer",
+        description: "Close a buffer.",
+        edits: false,
+        run: kill_buffer,
+    },
+    Command {
+        name: "list-buffers",
+        description: "List the open buffers.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.show_buffer_list();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "buffer-list-select",
+        description: "Show the buffer on the cursor's line of the buffer list.",
+        edits: false,
+        run: |editor, renderer| {
+            let buffer = editor.get_focused_buffer();
+            if let Mode::BufferList(ids) = &buffer.mode {
+                let line = buffer.cursor_position(editor.get_focused_view()).0;
+                match line.checked_sub(2).and_then(|i| ids.get(i)) {
+                    Some(&id) => editor.focus_buffer(id),
+                    None => return Err(EditorError::InvalidPosition(line)),
+                }
+            }
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "split-window-below",
+        description: "Split the window into two, one above the other.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.split_window(Axis::Vertical);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "split-window-right",
+        description: "Split the window into two, side by side.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.split_window(Axis::Horizontal);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "delete-window",
+        description: "Close the window.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.delete_window();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "delete-other-windows",
+        description: "Close every window but this one.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.delete_other_windows();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "other-window",
+        description: "Move to the next window.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.other_window();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "enlarge-window",
+        description: "Make the window a line taller.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.resize_window(Axis::Vertical, 1, window_area());
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "shrink-window",
+        description: "Make the window a line shorter.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.resize_window(Axis::Vertical, -1, window_area());
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "enlarge-window-horizontally",
+        description: "Make the window a column wider.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.resize_window(Axis::Horizontal, 1, window_area());
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "shrink-window-horizontally",
+        description: "Make the window a column narrower.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.resize_window(Axis::Horizontal, -1, window_area());
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "scroll-page-down",
+        description: "Show the next page.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.scroll_page(Direction::Down, window_area());
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "scroll-page-up",
+        description: "Show the previous page.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.scroll_page(Direction::Up, window_area());
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "quit-window",
+        description: "Close the buffer, or quit if it is the last one.",
+        edits: false,
+        run: |editor, renderer| {
+            if editor.buffer_ids().len() > 1 {
+                editor.kill_buffer(editor.focused_buffer());
+                renderer.render_all(editor);
+            } else {
+                quit(editor, renderer);
+            }
+            Ok(())
+        },
+    },
+    Command {
+        name: "find-file",
+        description: "Open a file or directory.",
+        edits: false,
+        run: |editor, renderer| {
+            if let Some(response) = prompt_file_name(editor, renderer, "Find file") {
+                open_file(editor, renderer, &response)?;
+            }
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "recent-files",
+        description: "Open a recently opened file.",
+        edits: false,
+        run: recent_files,
+    },
+    Command {
+        name: "toggle-read-only",
+        description: "Make the buffer read-only and page through it, or editable again.",
+        edits: false,
+        run: |editor, renderer| {
+            let buffer = editor.get_focused_buffer_mut();
+            if !matches!(buffer.mode, Mode::Text | Mode::Hex) {
+                return Err(EditorError::Unsupported("toggle-read-only".to_owned()));
+            }
+            buffer.read_only = !buffer.read_only;
+            buffer.view_mode = buffer.read_only;
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "search-hex",
+        description: "Search for hex bytes.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.state = EditorState::PromptResponse;
+            let response = prompt(editor, renderer, "Search hex");
+            editor.state = EditorState::Editing;
+
+            if let Some(response) = response {
+                match parse_hex(&response) {
+                    Some(pattern) => {
+                        let (buffer, view) = editor.focused_mut();
+                        if !buffer.search_bytes(view, &pattern) {
+                            warn!("Not found: {}", response);
+                        }
+                    }
+                    None => {
+                        renderer.render_all(editor);
+                        let input = format!("hex pattern: {}", response);
+                        return Err(EditorError::InvalidInput(input));
+                    }
+                }
+            }
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-visit",
+        description: "Open the entry on the cursor's line.",
+        edits: false,
+        run: |editor, renderer| {
+            let result = editor.dired_visit();
+            if result.is_ok() {
+                offer_recovery(editor, renderer);
+            }
+            renderer.render_all(editor);
+            result
+        },
+    },
+    Command {
+        name: "dired-up",
+        description: "List the parent directory.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.dired_up();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-refresh",
+        description: "Read the directory again.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.refresh_directory();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-mark",
+        description: "Mark the entry on the cursor's line.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.dired_mark(true);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-unmark",
+        description: "Unmark the entry on the cursor's line.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.dired_mark(false);
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-unmark-all",
+        description: "Unmark every entry.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.dired_unmark_all();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-create-directory",
+        description: "Create a directory.",
+        edits: false,
+        run: |editor, renderer| dired_create(editor, renderer, true),
+    },
+    Command {
+        name: "dired-create-file",
+        description: "Create an empty file.",
+        edits: false,
+        run: |editor, renderer| dired_create(editor, renderer, false),
+    },
+    Command {
+        name: "dired-delete",
+        description: "Delete the marked entries.",
+        edits: false,
+        run: |editor, renderer| {
+            let targets = editor.dired_targets();
+            let message = match targets.as_slice() {
+                [] => return Ok(()),
+                [path] => format!("Delete {}?", path.display()),
+                _ => format!("Delete {} files?", targets.len()),
+            };
+            if yes_or_no(editor, renderer, &message) {
+                editor.dired_delete();
+            }
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "dired-rename",
+        description: "Rename or move the marked entries.",
+        edits: false,
+        run: |editor, renderer| dired_transfer(editor, renderer, false),
+    },
+    Command {
+        name: "dired-copy",
+        description: "Copy the marked entries.",
+        edits: false,
+        run: |editor, renderer| dired_transfer(editor, renderer, true),
+    },
+    Command {
+        name: "wdired-start",
+        description: "Edit the file names of the listing as text.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.wdired_start();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "wdired-finish",
+        description: "Rename the files whose names were edited.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.wdired_finish();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "wdired-abort",
+        description: "Drop the edits to the file names.",
+        edits: false,
+        run: |editor, renderer| {
+            editor.wdired_abort();
+            renderer.render_all(editor);
+            Ok(())
+        },
+    },
+    Command {
+        name: "vim-normal-mode",
+        description: "Leave Vim's insert or visual mode.",
+        edits: false,
+        run: vim::normal_mode,
+    },
+];
+
+/// The buffer and view keys go to, the minibuffer's while prompting.
+fn current(editor: &mut Editor) -> (&mut Buffer, &mut View) {
+    match editor.state {
+        EditorState::PromptResponse => (&mut editor.minibuffer, &mut editor.minibuffer_view),
+        _ => editor.focused_mut(),
+    }
+}
+
+/// Types `c`, which in hex buffers is a hex digit. Listings ignore it.
+pub fn self_insert(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, c: char) -> Result<()> {
+    let auto_fill = editor.auto_fill && matches!(editor.state, EditorState::Editing);
+    let fill_column = editor.fill_column;
+    let (buffer, view) = current(editor);
+
+    match buffer.mode {
+        Mode::Hex => {
+            if let Some(digit) = c.to_digit(16) {
+                buffer.hex_input(view, digit as u8);
+            }
+            renderer.render_status_line(editor);
+            renderer.render_line(editor);
+        }
+        Mode::Dired(_) => {}
+        _ => {
+            buffer.insert(view, c);
+            let old_line_count = buffer.line_count();
+            if auto_fill && c == ' ' && !buffer.is_large() {
+                buffer.auto_fill(view, fill_column);
+            }
+            match c {
+                '\n' => renderer.render_all(editor),
+                _ if old_line_count != buffer.line_count() => renderer.render_all(editor),
+                _ => {
+                    renderer.render_status_line(editor);
+                    renderer.render_line(editor)
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn go(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    object: TextObject,
+    direction: Direction,
+) -> Result<()> {
+    let (buffer, view) = current(editor);
+    buffer.go(view, object, direction);
+    renderer.render_status_line(editor);
+    renderer.render_cursor(editor);
+    Ok(())
+}
+
+fn delete(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    object: TextObject,
+    direction: Direction,
+) -> Result<()> {
+    let (buffer, view) = current(editor);
+    let old_line_count = buffer.line_count();
+    buffer.delete(view, object, direction);
+
+    if old_line_count == buffer.line_count() {
+        renderer.render_status_line(editor);
+        renderer.render_line(editor);
+    } else {
+        renderer.render_all(editor);
+    }
+    Ok(())
+}
+
+/// Commands that need the whole text at once, or that only make sense for
+/// text, are turned off for large files, hex buffers and listings.
+fn text_command(buffer: &Buffer, command: &str) -> Result<()> {
+    if buffer.is_large() || !buffer.mode.is_text() {
+        return Err(EditorError::Unsupported(command.to_owned()));
+    }
+    Ok(())
+}
+
+/// Sorts the lines of the selected region, for the command named `command`.
+fn sort_lines(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    command: &str,
+    key: SortKey,
+) -> Result<()> {
+    let (buffer, view) = editor.focused_mut();
+    text_command(buffer, command)?;
+    buffer.sort_lines(view, key);
+    renderer.render_all(editor);
+    Ok(())
+}
+
+fn open_file(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, path: &str) -> Result<()> {
+    let result = editor.open_file(Some(expand_tilde(path.trim())));
+    renderer.render_all(editor);
+    result?;
+    offer_recovery(editor, renderer);
+    Ok(())
+}
+
+/// Prompts for a command name, matching the names fuzzily. Only an exact
+/// name is returned: other input is completed to the only match, or the
+/// matches are shown, and the prompt goes on. Nothing is returned when the
+/// prompt is cancelled or left empty.
+fn prompt_command(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    message: &str,
+) -> Result<Option<&'static Command>> {
+    let names = commands()
+        .iter()
+        .map(|command| command.name.to_owned())
+        .collect::<Vec<_>>();
+
+    loop {
+        editor.state = EditorState::PromptResponse;
+        let response = prompt_with_completion(editor, renderer, message, &|input| {
+            complete_fuzzy(&names, input)
+        });
+        editor.state = EditorState::Editing;
+        renderer.render_all(editor);
+
+        let Some(response) = response.filter(|r| !r.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let name = response.trim();
+        if let Some(command) = find_command(name) {
+            return Ok(Some(command));
+        }
+
+        let candidates = complete_fuzzy(&names, name);
+        match candidates.as_slice() {
+            [] => return Err(EditorError::Unsupported(name.to_owned())),
+            [only] => editor.minibuffer.set_text(only),
+            _ => {
+                editor.minibuffer.set_text(name);
+                editor.completions = candidates;
+            }
+        }
+        editor.minibuffer.go_to_end(&mut editor.minibuffer_view);
+    }
+}
+
+fn execute_command(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> Result<()> {
+    match prompt_command(editor, renderer, "M-x")? {
+        Some(command) => run_command(editor, renderer, command.name),
+        None => Ok(()),
+    }
+}
+
+fn describe_command(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> Result<()> {
+    let Some(command) = prompt_command(editor, renderer, "Describe command")? else {
+        return Ok(());
+    };
+
+    let keys = editor.keymaps.keys_for(command.name);
+    let name = if keys.is_empty() {
+        command.name.to_owned()
+    } else {
+        format!("{} ({})", command.name, keys.join(", "))
+    };
+    let text = format!("{}: {}", name, command.description);
+    editor.message(Level::Info, &text);
+    Ok(())
+}
+
+fn switch_to_buffer(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> Result<()> {
+    let default = editor
+        .other_buffer()
+        .and_then(|id| editor.get_buffer(id))
+        .map(|buffer| buffer.name.clone());
+    let message = match &default {
+        Some(name) => format!("Switch to buffer (default {})", name),
+        None => "Switch to buffer".to_owned(),
+    };
+
+    let names = editor.buffer_names();
+    editor.state = EditorState::PromptResponse;
+    if let Some(response) = prompt_with_completion(editor, renderer, &message, &|input| {
+        complete_from(&names, input)
+    }) {
+        match (response.as_str(), &default) {
+            ("", Some(name)) => {
+                if let Some(id) = editor.find_buffer(name) {
+                    editor.focus_buffer(id);
+                }
+            }
+            ("", None) => {}
+            (name, _) => match editor.find_buffer(name) {
+                Some(id) => editor.focus_buffer(id),
+                None => {
+                    let mut buffer = Buffer::new();
+                    buffer.name = name.to_owned();
+                    editor.add_buffer(buffer);
+                }
+            },
+        }
+    }
+    editor.state = EditorState::Editing;
+    renderer.render_all(editor);
+    Ok(())
+}
+
+fn kill_buffer(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> Result<()> {
+    let current = editor.get_focused_buffer().name.clone();
+    let message = format!("Kill buffer (default {})", current);
+
+    let names = editor.buffer_names();
+    editor.state = EditorState::PromptResponse;
+    let response = prompt_with_completion(editor, renderer, &message, &|input| {
+        complete_from(&names, input)
+    });
+    editor.state = EditorState::Editing;
+
+    if let Some(response) = response {
+        let name = if response.is_empty() {
+            current
+        } else {
+            response
+        };
+
+        match editor.find_buffer(&name) {
+            Some(id) => {
+                let modified = editor.get_buffer(id).is_some_and(|b| b.modified);
+                let message = format!("Buffer {} modified; kill anyway?", name);
+                if !modified || yes_or_no(editor, renderer, &message) {
+                    editor.kill_buffer(id);
+                }
+            }
+            None => {
+                renderer.render_all(editor);
+                return Err(EditorError::NoSuchBuffer(name));
+            }
+        }
+    }
+    renderer.render_all(editor);
+    Ok(())
+}
+
+fn recent_files(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> Result<()> {
+    let paths = editor
+        .recent_files
+        .paths()
+        .into_iter()
+        .map(abbreviate_home)
+        .collect::<Vec<_>>();
+
+    editor.state = EditorState::PromptResponse;
+    let response = prompt_with_completion(editor, renderer, "Recent file", &|input| {
+        paths
+            .iter()
+            .filter(|path| path.contains(input))
+            .cloned()
+            .collect()
+    });
+    editor.state = EditorState::Editing;
+
+    if let Some(response) = response.filter(|r| !r.trim().is_empty()) {
+        open_file(editor, renderer, &response)?;
+    }
+    renderer.render_all(editor);
+    Ok(())
+}
+
+fn dired_create(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    directory: bool,
+) -> Result<()> {
+    let message = if directory {
+        "Create directory"
+    } else {
+        "Create file"
+    };
+
+    editor.state = EditorState::PromptResponse;
+    if let Some(response) = prompt(editor, renderer, message) {
+        editor.dired_create(&expand_tilde(response.trim()), directory);
+    }
+    editor.state = EditorState::Editing;
+    renderer.render_all(editor);
+    Ok(())
+}
+
+fn dired_transfer(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, copy: bool) -> Result<()> {
+    let message = match (copy, editor.dired_targets().len()) {
+        (_, 0) => return Ok(()),
+        (true, 1) => "Copy to".to_owned(),
+        (false, 1) => "Rename to".to_owned(),
+        (true, n) => format!("Copy {} files to", n),
+        (false, n) => format!("Move {} files to", n),
+    };
+    if let Some(response) = prompt_file_name(editor, renderer, &message) {
+        editor.dired_transfer(&expand_tilde(response.trim()), copy);
+    }
+    renderer.render_all(editor);
+    Ok(())
+}
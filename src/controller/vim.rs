@@ -0,0 +1,1536 @@
+use std::{collections::HashMap, iter::Peekable, mem};
+
+use termion::event::Key;
+
+use crate::{
+    buffer::{Buffer, Direction, Selection, TextObject},
+    controller::{handle_key as dispatch_key, prompting, refuse_read_only, show_prefix},
+    editor::{Editor, View},
+    error::{EditorError, Result},
+    keymap::keys_name,
+    renderer::Renderer,
+};
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum VimMode {
+    #[default]
+    Normal,
+    Insert,
+    Visual,
+}
+
+impl VimMode {
+    /// Name of the keymap overlay that applies in the mode, see `Keymaps`.
+    pub fn overlay(self) -> &'static str {
+        match self {
+            VimMode::Normal => "vim-normal",
+            VimMode::Insert => "vim-insert",
+            VimMode::Visual => "vim-visual",
+        }
+    }
+
+    /// How the mode is shown in the status line.
+    pub fn tag(self) -> &'static str {
+        match self {
+            VimMode::Normal => "<N>",
+            VimMode::Insert => "<I>",
+            VimMode::Visual => "<V>",
+        }
+    }
+}
+
+/// State of the Vim keymap, which replaces the Emacs keys for text. Keys
+/// typed in normal and visual mode make up commands like `d2w` or `"ayiw`,
+/// keys typed in insert mode go through the keymaps like without it.
+#[derive(Default, Debug)]
+pub struct Vim {
+    pub mode: VimMode,
+    /// Keys of the command being typed
+    pending: Vec<Key>,
+    registers: HashMap<char, Register>,
+    /// The last change and the keys typed in insert mode after it, which `.`
+    /// repeats
+    last_change: Option<(Command, Vec<Key>)>,
+    /// The change that started insert mode and the keys typed since
+    inserting: Option<(Command, Vec<Key>)>,
+    /// Whether `.` is repeating the last change, which isn't recorded again
+    repeating: bool,
+}
+
+/// Text in a register, which is pasted as whole lines if `linewise`.
+#[derive(Clone, Debug)]
+struct Register {
+    text: String,
+    linewise: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Command {
+    register: Option<char>,
+    count: Option<usize>,
+    action: Action,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Action {
+    Move(Motion),
+    /// An operator on the text from the cursor to where a motion goes
+    Operate(Operator, Motion),
+    /// An operator on a text object, like `diw`
+    OperateObject(Operator, Object),
+    /// An operator on whole lines, like `dd`
+    OperateLines(Operator),
+    /// An operator on the visual selection
+    OperateSelection(Operator),
+    Insert(InsertAt),
+    Paste {
+        before: bool,
+    },
+    /// Replaces the characters under the cursor, like `r`
+    Replace(char),
+    Visual,
+    /// Selects a text object in visual mode
+    SelectObject(Object),
+    /// Moves the cursor to the other end of the selection
+    SwapEnds,
+    Escape,
+    Repeat,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordStart,
+    WordEnd,
+    WordBack,
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+}
+
+/// How much of the text an operator with a motion acts on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum MotionKind {
+    /// Up to the character the motion goes to
+    Exclusive,
+    /// Up to and with the character the motion goes to
+    Inclusive,
+    /// The lines from the cursor to where the motion goes
+    Linewise,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Object {
+    Word {
+        around: bool,
+    },
+    Pair {
+        open: char,
+        close: char,
+        around: bool,
+    },
+    Quote {
+        quote: char,
+        around: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+/// Largest count that repeats a command, so that a mistyped count can't keep
+/// the editor busy or paste more text than fits in memory. Counts for `G`
+/// and `gg` are line numbers rather than repetitions and aren't limited.
+const MAX_COUNT: usize = 10_000;
+
+/// Stands for Backspace among the characters of a command.
+const BACKSPACE: char = '\x7f';
+
+/// How far from the cursor commands look in large files, which are never
+/// read whole.
+const LARGE_WINDOW_LEN: usize = 4096;
+
+enum ParseError {
+    /// More keys are needed
+    Incomplete,
+    Invalid,
+}
+
+impl Motion {
+    fn kind(self) -> MotionKind {
+        match self {
+            Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine => {
+                MotionKind::Linewise
+            }
+            Motion::WordEnd | Motion::LineEnd => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+}
+
+impl Action {
+    /// Whether the action changes the text, and is repeated by `.`.
+    fn changes(self) -> bool {
+        match self {
+            Action::Operate(operator, _)
+            | Action::OperateObject(operator, _)
+            | Action::OperateLines(operator)
+            | Action::OperateSelection(operator) => operator != Operator::Yank,
+            Action::Insert(_) | Action::Paste { .. } | Action::Replace(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the action is available in large files. The others need more
+    /// of the text than is read around the cursor.
+    fn in_large_files(self) -> bool {
+        matches!(
+            self,
+            Action::Move(_)
+                | Action::Insert(_)
+                | Action::SwapEnds
+                | Action::Escape
+                | Action::Repeat
+        )
+    }
+}
+
+/// The Vim mode keys go to, if the Vim keymap is on and the focused buffer
+/// is text.
+pub fn vim_mode(editor: &Editor) -> Option<VimMode> {
+    let vim = editor.vim.as_ref()?;
+    let text = editor.get_focused_buffer().mode.is_text();
+    (text && !prompting(editor)).then_some(vim.mode)
+}
+
+/// The selection of `view` in visual mode, which unlike other selections
+/// has the character under the cursor.
+pub fn visual_selection(editor: &Editor, view: &View) -> Option<Selection> {
+    if vim_mode(editor) != Some(VimMode::Visual) {
+        return None;
+    }
+    let buffer = editor.get_focused_buffer();
+    let (start, end) = visual_range(buffer.char_count(), view)?;
+    Some((buffer.position_of(start), buffer.position_of(end)))
+}
+
+/// Handles `key` as part of a Vim command, or returns nothing for the keys
+/// the keymaps handle. Those are the keys typed in insert mode and the ones
+/// with modifiers, like `C-x`.
+pub fn handle_key(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    key: Key,
+) -> Option<Result<()>> {
+    let mode = vim_mode(editor)?;
+    let vim = editor.vim.as_mut()?;
+
+    if mode == VimMode::Insert {
+        if let Some((_, keys)) = &mut vim.inserting {
+            keys.push(key);
+        }
+        return None;
+    }
+    if vim.pending.is_empty() && !matches!(key, Key::Char(_) | Key::Esc | Key::Backspace) {
+        return None;
+    }
+
+    vim.pending.push(key);
+    let command = match parse(&vim.pending, mode == VimMode::Visual) {
+        Ok(command) => command,
+        Err(ParseError::Incomplete) => {
+            let keys = vim.pending.clone();
+            show_prefix(editor, renderer, &keys);
+            return Some(Ok(()));
+        }
+        Err(ParseError::Invalid) => {
+            let keys = mem::take(&mut vim.pending);
+            clear_prefix(editor, renderer, &keys);
+            return Some(Err(EditorError::Unsupported(keys_name(&keys))));
+        }
+    };
+
+    let keys = mem::take(&mut vim.pending);
+    clear_prefix(editor, renderer, &keys);
+    if editor.get_focused_buffer().is_large() && !command.action.in_large_files() {
+        return Some(Err(EditorError::Unsupported(keys_name(&keys))));
+    }
+    Some(execute(editor, renderer, command))
+}
+
+/// Leaves insert or visual mode, finishing the change that was being typed.
+pub fn normal_mode(editor: &mut Editor, renderer: &mut Box<dyn Renderer>) -> Result<()> {
+    let Some(vim) = editor.vim.as_mut() else {
+        return Err(EditorError::Unsupported("vim-normal-mode".to_owned()));
+    };
+    if let Some(change) = vim.inserting.take() {
+        vim.last_change = Some(change);
+    }
+    let mode = mem::take(&mut vim.mode);
+    vim.pending.clear();
+
+    let (buffer, view) = editor.focused_mut();
+    match mode {
+        // Like in Vim, the cursor goes back onto the last character typed
+        VimMode::Insert => {
+            let (start, text) = text_around(buffer, view.cursor);
+            if view.cursor > start + line_start(&text, view.cursor - start) {
+                view.cursor -= 1;
+            }
+        }
+        VimMode::Visual => view.mark = None,
+        VimMode::Normal => {}
+    }
+    renderer.render_all(editor);
+    Ok(())
+}
+
+fn clear_prefix(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, keys: &[Key]) {
+    if keys.len() > 1 && editor.echo.take().is_some() {
+        renderer.render_echo(editor);
+    }
+}
+
+// ---------- Parsing ----------
+
+/// Parses the keys of a command: an optional register like `"a`, a count,
+/// then an operator with a motion or text object, or another command.
+fn parse(keys: &[Key], visual: bool) -> std::result::Result<Command, ParseError> {
+    if keys.last() == Some(&Key::Esc) {
+        return Ok(Command {
+            register: None,
+            count: None,
+            action: Action::Escape,
+        });
+    }
+
+    // Backspace is parsed as the DEL character, which is a motion like `h`
+    let mut chars = vec![];
+    for key in keys {
+        chars.push(match key {
+            Key::Char(c) => *c,
+            Key::Backspace => BACKSPACE,
+            _ => return Err(ParseError::Invalid),
+        });
+    }
+    let mut chars = chars.into_iter().peekable();
+
+    let mut register = None;
+    if chars.next_if_eq(&'"').is_some() {
+        match chars.next() {
+            Some(c) if c.is_ascii_alphanumeric() || c == '"' || c == '_' => register = Some(c),
+            Some(_) => return Err(ParseError::Invalid),
+            None => return Err(ParseError::Incomplete),
+        }
+    }
+    let mut count = parse_count(&mut chars);
+    let c = chars.next().ok_or(ParseError::Incomplete)?;
+
+    let action = match c {
+        'd' | 'c' | 'y' => {
+            let operator = match c {
+                'd' => Operator::Delete,
+                'c' => Operator::Change,
+                _ => Operator::Yank,
+            };
+            if visual {
+                Action::OperateSelection(operator)
+            } else {
+                // Both counts multiply, as in `2d3w`
+                if let Some(inner) = parse_count(&mut chars) {
+                    count = Some(count.unwrap_or(1).saturating_mul(inner));
+                }
+                match chars.next().ok_or(ParseError::Incomplete)? {
+                    next if next == c => Action::OperateLines(operator),
+                    next @ ('i' | 'a') => {
+                        Action::OperateObject(operator, parse_object(next, &mut chars)?)
+                    }
+                    next => Action::Operate(operator, parse_motion(next, &mut chars)?),
+                }
+            }
+        }
+        'x' if visual => Action::OperateSelection(Operator::Delete),
+        's' if visual => Action::OperateSelection(Operator::Change),
+        'x' => Action::Operate(Operator::Delete, Motion::Right),
+        'X' => Action::Operate(Operator::Delete, Motion::Left),
+        's' => Action::Operate(Operator::Change, Motion::Right),
+        'D' => Action::Operate(Operator::Delete, Motion::LineEnd),
+        'C' => Action::Operate(Operator::Change, Motion::LineEnd),
+        'S' => Action::OperateLines(Operator::Change),
+        'Y' => Action::OperateLines(Operator::Yank),
+        'i' | 'a' if visual => Action::SelectObject(parse_object(c, &mut chars)?),
+        'o' if visual => Action::SwapEnds,
+        'i' => Action::Insert(InsertAt::Cursor),
+        'a' => Action::Insert(InsertAt::After),
+        'I' => Action::Insert(InsertAt::LineStart),
+        'A' => Action::Insert(InsertAt::LineEnd),
+        'o' => Action::Insert(InsertAt::LineBelow),
+        'O' => Action::Insert(InsertAt::LineAbove),
+        'p' => Action::Paste { before: false },
+        'P' => Action::Paste { before: true },
+        'r' => match chars.next().ok_or(ParseError::Incomplete)? {
+            BACKSPACE => return Err(ParseError::Invalid),
+            c => Action::Replace(c),
+        },
+        'v' if visual => Action::Escape,
+        'v' => Action::Visual,
+        '.' => Action::Repeat,
+        c => Action::Move(parse_motion(c, &mut chars)?),
+    };
+
+    let line_number = matches!(
+        action,
+        Action::Move(Motion::FirstLine | Motion::LastLine)
+            | Action::Operate(_, Motion::FirstLine | Motion::LastLine)
+    );
+    if !line_number {
+        count = count.map(|count| count.min(MAX_COUNT));
+    }
+
+    Ok(Command {
+        register,
+        count,
+        action,
+    })
+}
+
+/// A count like `12`. A leading `0` is the motion to the start of the line.
+fn parse_count(chars: &mut Peekable<impl Iterator<Item = char>>) -> Option<usize> {
+    let mut count: Option<usize> = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        if digit == 0 && count.is_none() {
+            break;
+        }
+        chars.next();
+        count = Some(
+            count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit as usize),
+        );
+    }
+    count
+}
+
+fn parse_motion(
+    c: char,
+    chars: &mut impl Iterator<Item = char>,
+) -> std::result::Result<Motion, ParseError> {
+    Ok(match c {
+        'h' | BACKSPACE => Motion::Left,
+        'l' | ' ' => Motion::Right,
+        'k' => Motion::Up,
+        'j' | '\n' => Motion::Down,
+        'w' => Motion::WordStart,
+        'e' => Motion::WordEnd,
+        'b' => Motion::WordBack,
+        '0' => Motion::LineStart,
+        '^' => Motion::FirstNonBlank,
+        '$' => Motion::LineEnd,
+        'G' => Motion::LastLine,
+        'g' => match chars.next() {
+            Some('g') => Motion::FirstLine,
+            Some(_) => return Err(ParseError::Invalid),
+            None => return Err(ParseError::Incomplete),
+        },
+        _ => return Err(ParseError::Invalid),
+    })
+}
+
+/// A text object like `iw` or `a(`, `c` being the `i` or `a`.
+fn parse_object(
+    c: char,
+    chars: &mut impl Iterator<Item = char>,
+) -> std::result::Result<Object, ParseError> {
+    let around = c == 'a';
+    let pair = |open, close| Object::Pair {
+        open,
+        close,
+        around,
+    };
+
+    Ok(match chars.next().ok_or(ParseError::Incomplete)? {
+        'w' => Object::Word { around },
+        '(' | ')' | 'b' => pair('(', ')'),
+        '[' | ']' => pair('[', ']'),
+        '{' | '}' | 'B' => pair('{', '}'),
+        '<' | '>' => pair('<', '>'),
+        quote @ ('"' | '\'' | '`') => Object::Quote { quote, around },
+        _ => return Err(ParseError::Invalid),
+    })
+}
+
+// ---------- Commands ----------
+
+fn execute(editor: &mut Editor, renderer: &mut Box<dyn Renderer>, command: Command) -> Result<()> {
+    let action = command.action;
+    if action.changes() && refuse_read_only(editor) {
+        return Ok(());
+    }
+
+    let count = command.count.unwrap_or(1);
+    match action {
+        Action::Move(motion) if editor.get_focused_buffer().is_large() => {
+            let (buffer, view) = editor.focused_mut();
+            view.cursor = large_motion_target(buffer, view.cursor, motion, command.count);
+        }
+        Action::Move(motion) => {
+            let (buffer, view) = editor.focused_mut();
+            let text = buffer.text();
+            view.cursor = motion_target(&text, view.cursor, motion, command.count, |cursor| {
+                let mut moved = view.clone();
+                moved.cursor = cursor;
+                let direction = match motion {
+                    Motion::Up => Direction::Up,
+                    _ => Direction::Down,
+                };
+                buffer.go(&mut moved, TextObject::Line, direction);
+                moved.cursor
+            });
+        }
+        Action::Operate(operator, motion) => {
+            let (buffer, view) = editor.focused_mut();
+            let text = buffer.text();
+            let cursor = view.cursor;
+
+            // `cw` on a word changes to its end, like `ce`
+            let on_word = text.get(cursor).is_some_and(|c| !c.is_whitespace());
+            let motion = match motion {
+                Motion::WordStart if operator == Operator::Change && on_word => Motion::WordEnd,
+                motion => motion,
+            };
+            let mut target = motion_target(&text, cursor, motion, command.count, |cursor| {
+                let mut moved = view.clone();
+                moved.cursor = cursor;
+                let direction = match motion {
+                    Motion::Up => Direction::Up,
+                    _ => Direction::Down,
+                };
+                buffer.go(&mut moved, TextObject::Line, direction);
+                moved.cursor
+            });
+            // `dw` on the last word of a line stops at the end of the line
+            if motion == Motion::WordStart && target > line_end(&text, cursor) {
+                let start = line_start(&text, target);
+                if target == first_non_blank(&text, start) && start > 0 {
+                    target = line_end(&text, start - 1).max(cursor);
+                }
+            }
+
+            let (start, end) = (cursor.min(target), cursor.max(target));
+            match motion.kind() {
+                MotionKind::Linewise => {
+                    operate_lines(editor, command.register, operator, start, end)?
+                }
+                MotionKind::Inclusive => {
+                    // The newline at the end of the line is never included
+                    let end = if text.get(end).is_some_and(|c| c != &'\n') {
+                        end + 1
+                    } else {
+                        end
+                    };
+                    operate(editor, command.register, operator, start, end)?
+                }
+                MotionKind::Exclusive => operate(editor, command.register, operator, start, end)?,
+            }
+        }
+        Action::OperateObject(operator, object) => {
+            let (buffer, view) = editor.focused_mut();
+            let text = buffer.text();
+            let Some((start, end)) = object_range(&text, view.cursor, object, count) else {
+                let (line, _) = buffer.cursor_position(view);
+                return Err(EditorError::InvalidPosition(line));
+            };
+            operate(editor, command.register, operator, start, end)?
+        }
+        Action::OperateLines(operator) => {
+            let (buffer, view) = editor.focused_mut();
+            let text = buffer.text();
+            let start = view.cursor;
+            let next_line = |offset| (line_end(&text, offset) + 1).min(text.len());
+            let end = advance(start, count - 1, next_line);
+            operate_lines(editor, command.register, operator, start, end)?
+        }
+        Action::OperateSelection(operator) => {
+            let (buffer, view) = editor.focused_mut();
+            let range = visual_range(buffer.char_count(), view);
+            view.mark = None;
+            set_mode(editor, VimMode::Normal);
+            if let Some((start, end)) = range {
+                operate(editor, command.register, operator, start, end)?
+            }
+        }
+        Action::Insert(at) => insert(editor, at),
+        Action::Paste { before } => paste(editor, command.register, count, before),
+        Action::Replace(c) => {
+            let (buffer, view) = editor.focused_mut();
+            let text = buffer.text();
+            if view.cursor + count <= line_end(&text, view.cursor) {
+                let start = view.cursor;
+                buffer.replace_range(view, start, start + count, &vec![c; count]);
+                view.cursor -= 1;
+            }
+        }
+        Action::Visual => {
+            let (_, view) = editor.focused_mut();
+            view.mark = Some(view.cursor);
+            set_mode(editor, VimMode::Visual);
+        }
+        Action::SelectObject(object) => {
+            let (buffer, view) = editor.focused_mut();
+            let text = buffer.text();
+            if let Some((start, end)) = object_range(&text, view.cursor, object, count) {
+                view.mark = Some(start);
+                view.cursor = end.saturating_sub(1).max(start);
+            }
+        }
+        Action::SwapEnds => {
+            let (_, view) = editor.focused_mut();
+            if let Some(mark) = view.mark {
+                view.mark = Some(view.cursor);
+                view.cursor = mark;
+            }
+        }
+        Action::Escape => return normal_mode(editor, renderer),
+        Action::Repeat => return repeat(editor, renderer, command.count),
+    }
+
+    let Some(vim) = editor.vim.as_mut() else {
+        return Ok(());
+    };
+    if action.changes() && !vim.repeating {
+        match vim.mode {
+            VimMode::Insert => vim.inserting = Some((command, vec![])),
+            _ => vim.last_change = Some((command, vec![])),
+        }
+    }
+    if vim.mode == VimMode::Normal {
+        let (buffer, view) = editor.focused_mut();
+        let (start, text) = text_around(buffer, view.cursor);
+        view.cursor = start + normal_cursor(&text, view.cursor - start);
+    }
+
+    renderer.render_all(editor);
+    Ok(())
+}
+
+/// Repeats the last change, with `count` instead of its own if given.
+fn repeat(
+    editor: &mut Editor,
+    renderer: &mut Box<dyn Renderer>,
+    count: Option<usize>,
+) -> Result<()> {
+    let large = editor.get_focused_buffer().is_large();
+    let Some(vim) = editor.vim.as_mut() else {
+        return Ok(());
+    };
+    let Some((mut command, keys)) = vim.last_change.clone() else {
+        return Ok(());
+    };
+    if count.is_some() {
+        command.count = count;
+        vim.last_change = Some((command, keys.clone()));
+    }
+
+    if large && !command.action.in_large_files() {
+        return Err(EditorError::Unsupported(".".to_owned()));
+    }
+
+    vim.repeating = true;
+    let mut result = execute(editor, renderer, command);
+    if result.is_ok() {
+        for key in keys {
+            if vim_mode(editor) != Some(VimMode::Insert) {
+                break;
+            }
+            result = dispatch_key(editor, renderer, key);
+            if result.is_err() {
+                break;
+            }
+        }
+    }
+    if vim_mode(editor) == Some(VimMode::Insert) {
+        normal_mode(editor, renderer)?;
+    }
+
+    if let Some(vim) = editor.vim.as_mut() {
+        vim.repeating = false;
+    }
+    result
+}
+
+fn set_mode(editor: &mut Editor, mode: VimMode) {
+    if let Some(vim) = editor.vim.as_mut() {
+        vim.mode = mode;
+    }
+}
+
+/// Deletes, changes or yanks `start..end` of the text of the focused buffer.
+fn operate(
+    editor: &mut Editor,
+    register: Option<char>,
+    operator: Operator,
+    start: usize,
+    end: usize,
+) -> Result<()> {
+    let (buffer, view) = editor.focused_mut();
+    let text = buffer.text()[start..end].iter().collect::<String>();
+
+    match operator {
+        Operator::Yank => view.cursor = start,
+        Operator::Delete | Operator::Change => buffer.replace_range(view, start, end, &[]),
+    }
+    if operator == Operator::Change {
+        set_mode(editor, VimMode::Insert);
+    }
+
+    store(editor, register, operator, text, false);
+    Ok(())
+}
+
+/// Deletes, changes or yanks the lines from the one at offset `start` to the
+/// one at `end`. Changed lines are left empty.
+fn operate_lines(
+    editor: &mut Editor,
+    register: Option<char>,
+    operator: Operator,
+    start: usize,
+    end: usize,
+) -> Result<()> {
+    let (buffer, view) = editor.focused_mut();
+    let text = buffer.text();
+    let (first, last) = (line_start(&text, start), line_end(&text, end));
+    let lines = text[first..last].iter().collect::<String>() + "\n";
+
+    match operator {
+        Operator::Yank => view.cursor = start.min(view.cursor),
+        Operator::Change => {
+            let indent = first_non_blank(&text, first);
+            buffer.replace_range(view, indent, last, &[]);
+            set_mode(editor, VimMode::Insert);
+        }
+        Operator::Delete => {
+            // With the newline after the lines, or before them on the last line
+            let (from, to) = if last < text.len() {
+                (first, last + 1)
+            } else {
+                (first.saturating_sub(1), last)
+            };
+            buffer.replace_range(view, from, to, &[]);
+            let text = buffer.text();
+            view.cursor = first_non_blank(&text, line_start(&text, from.min(text.len())));
+        }
+    }
+
+    store(editor, register, operator, lines, true);
+    Ok(())
+}
+
+/// Keeps text that was deleted or yanked in `register`, and in the unnamed
+/// register and the kill ring unless the register is `_`. Upper case
+/// registers add to the lower case ones.
+fn store(
+    editor: &mut Editor,
+    register: Option<char>,
+    operator: Operator,
+    text: String,
+    linewise: bool,
+) {
+    let Some(vim) = editor.vim.as_mut() else {
+        return;
+    };
+    if register == Some('_') {
+        return;
+    }
+
+    let value = Register { text, linewise };
+    match register {
+        Some(name) if name.is_ascii_uppercase() => {
+            let name = name.to_ascii_lowercase();
+            let register = vim.registers.entry(name).or_insert_with(|| Register {
+                text: String::new(),
+                linewise,
+            });
+            register.text.push_str(&value.text);
+        }
+        Some(name) if name != '"' => {
+            vim.registers.insert(name, value.clone());
+        }
+        // Yanks without a register name are also kept in `0`
+        _ if operator == Operator::Yank => {
+            vim.registers.insert('0', value.clone());
+        }
+        _ => {}
+    }
+
+    editor.kill_ring.push(value.text.clone());
+    vim.registers.insert('"', value);
+}
+
+/// Pastes the text of `register` after the cursor, or before it, `count`
+/// times. Without any register, the text copied last with `M-w` is pasted.
+fn paste(editor: &mut Editor, register: Option<char>, count: usize, before: bool) {
+    let Some(vim) = editor.vim.as_ref() else {
+        return;
+    };
+    let name = register.map_or('"', |name| name.to_ascii_lowercase());
+    let register = match vim.registers.get(&name) {
+        Some(register) => register.clone(),
+        None if name == '"' => match editor.kill_ring.last() {
+            Some(text) => Register {
+                text: text.clone(),
+                linewise: false,
+            },
+            None => return,
+        },
+        None => return,
+    };
+
+    let (buffer, view) = editor.focused_mut();
+    let text = buffer.text();
+    let cursor = view.cursor.min(text.len());
+    let pasted = register.text.repeat(count);
+
+    if register.linewise {
+        let line = if before {
+            line_start(&text, cursor)
+        } else {
+            line_end(&text, cursor) + 1
+        };
+        // The last line has no newline to paste after
+        let (at, pasted) = if line > text.len() {
+            (text.len(), format!("\n{}", pasted.trim_end_matches('\n')))
+        } else {
+            (line, pasted)
+        };
+
+        buffer.replace_range(view, at, at, &pasted.chars().collect::<Vec<_>>());
+        let text = buffer.text();
+        let start = if at == line { at } else { at + 1 };
+        view.cursor = first_non_blank(&text, start.min(text.len()));
+    } else {
+        let at = match text.get(cursor) {
+            Some(c) if !before && c != &'\n' => cursor + 1,
+            _ => cursor,
+        };
+        let pasted = pasted.chars().collect::<Vec<_>>();
+        buffer.replace_range(view, at, at, &pasted);
+        view.cursor = (at + pasted.len()).saturating_sub(1);
+    }
+}
+
+fn insert(editor: &mut Editor, at: InsertAt) {
+    let (buffer, view) = editor.focused_mut();
+    let (offset, text) = text_around(buffer, view.cursor);
+    let cursor = view.cursor.min(offset + text.len()) - offset;
+
+    match at {
+        InsertAt::Cursor => {}
+        InsertAt::After => {
+            if text.get(cursor).is_some_and(|c| c != &'\n') {
+                view.cursor = offset + cursor + 1;
+            }
+        }
+        InsertAt::LineStart => {
+            view.cursor = offset + first_non_blank(&text, line_start(&text, cursor))
+        }
+        InsertAt::LineEnd => view.cursor = offset + line_end(&text, cursor),
+        InsertAt::LineBelow => {
+            let end = offset + line_end(&text, cursor);
+            buffer.replace_range(view, end, end, &['\n']);
+        }
+        InsertAt::LineAbove => {
+            let start = offset + line_start(&text, cursor);
+            buffer.replace_range(view, start, start, &['\n']);
+            view.cursor = start;
+        }
+    }
+    set_mode(editor, VimMode::Insert);
+}
+
+// ---------- Motions and text objects ----------
+
+/// Where `motion` goes from `cursor`, `count` times. `line` moves an offset
+/// a line up or down, keeping its column.
+fn motion_target(
+    text: &[char],
+    cursor: usize,
+    motion: Motion,
+    count: Option<usize>,
+    line: impl Fn(usize) -> usize,
+) -> usize {
+    let n = count.unwrap_or(1);
+    let cursor = cursor.min(text.len());
+
+    match motion {
+        Motion::Left => cursor - n.min(cursor - line_start(text, cursor)),
+        Motion::Right => cursor + n.min(line_end(text, cursor) - cursor),
+        Motion::Up | Motion::Down => advance(cursor, n, line),
+        Motion::WordStart => advance(cursor, n, |offset| word_start(text, offset)),
+        Motion::WordEnd => advance(cursor, n, |offset| word_end(text, offset)),
+        Motion::WordBack => advance(cursor, n, |offset| word_back(text, offset)),
+        Motion::LineStart => line_start(text, cursor),
+        Motion::FirstNonBlank => first_non_blank(text, line_start(text, cursor)),
+        Motion::LineEnd => {
+            // The count moves to the end of a later line
+            let line = advance(cursor, n - 1, |offset| {
+                (line_end(text, offset) + 1).min(text.len())
+            });
+            let (start, end) = (line_start(text, line), line_end(text, line));
+            end.saturating_sub(1).max(start)
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            let line = match (motion, count) {
+                (_, Some(n)) => n,
+                (Motion::FirstLine, None) => 1,
+                _ => usize::MAX,
+            };
+            first_non_blank(text, nth_line_start(text, line))
+        }
+    }
+}
+
+/// Where `motion` goes in a large file, like `motion_target` but reading only
+/// the text around the offsets it goes through. Lines are found through
+/// `Buffer::object_offset`, like the Emacs keys find them.
+fn large_motion_target(
+    buffer: &Buffer,
+    cursor: usize,
+    motion: Motion,
+    count: Option<usize>,
+) -> usize {
+    let n = count.unwrap_or(1);
+    let line = |offset, direction| buffer.object_offset(offset, TextObject::Line, direction);
+    let next_line = |offset| line(line(offset, Direction::Left), Direction::Down);
+    let around = |offset: usize, step: fn(&[char], usize) -> usize| {
+        let start = offset.saturating_sub(LARGE_WINDOW_LEN);
+        let text = buffer.text_range(start, offset + LARGE_WINDOW_LEN);
+        start + step(&text, offset - start)
+    };
+
+    match motion {
+        Motion::Left | Motion::Right => {
+            let (start, text) = text_around(buffer, cursor);
+            start + motion_target(&text, cursor - start, motion, count, |offset| offset)
+        }
+        Motion::Up => advance(cursor, n, |offset| line(offset, Direction::Up)),
+        Motion::Down => advance(cursor, n, |offset| line(offset, Direction::Down)),
+        Motion::WordStart => advance(cursor, n, |offset| around(offset, word_start)),
+        Motion::WordEnd => advance(cursor, n, |offset| around(offset, word_end)),
+        Motion::WordBack => advance(cursor, n, |offset| around(offset, word_back)),
+        Motion::LineStart => line(cursor, Direction::Left),
+        Motion::FirstNonBlank => around(line(cursor, Direction::Left), first_non_blank),
+        Motion::LineEnd => {
+            let offset = advance(cursor, n - 1, next_line);
+            let (start, end) = (
+                line(offset, Direction::Left),
+                line(offset, Direction::Right),
+            );
+            end.saturating_sub(1).max(start)
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            // The newline at the end of the text ends the last line, see
+            // `nth_line_start`
+            let len = buffer.char_count();
+            let end = match buffer.text_range(len.saturating_sub(1), len).first() {
+                Some('\n') => len - 1,
+                _ => len,
+            };
+            let last = line(end, Direction::Left);
+
+            let start = match (motion, count) {
+                (_, Some(n)) => buffer
+                    .line_offset(n.saturating_sub(1))
+                    .map_or(last, |start| start.min(last)),
+                (Motion::FirstLine, None) => 0,
+                _ => last,
+            };
+            around(start, first_non_blank)
+        }
+    }
+}
+
+/// The text that commands within the cursor's line look at, and the offset
+/// it starts at: all of it, or in large files the part of the line within
+/// `LARGE_WINDOW_LEN` of the cursor.
+fn text_around(buffer: &Buffer, cursor: usize) -> (usize, Box<[char]>) {
+    if !buffer.is_large() {
+        return (0, buffer.text());
+    }
+
+    let start = buffer.object_offset(cursor, TextObject::Line, Direction::Left);
+    let end = buffer.object_offset(cursor, TextObject::Line, Direction::Right);
+    let start = start.max(cursor.saturating_sub(LARGE_WINDOW_LEN));
+    let end = end.min(cursor + LARGE_WINDOW_LEN);
+    (start, buffer.text_range(start, end))
+}
+
+/// Applies `step` to `offset` `n` times, stopping early once it no longer
+/// moves, like at the end of the text.
+fn advance(offset: usize, n: usize, step: impl Fn(usize) -> usize) -> usize {
+    let mut offset = offset;
+    for _ in 0..n {
+        let next = step(offset);
+        if next == offset {
+            break;
+        }
+        offset = next;
+    }
+    offset
+}
+
+/// The start and end of `object` around `cursor`, `count` words long for
+/// words.
+fn object_range(
+    text: &[char],
+    cursor: usize,
+    object: Object,
+    count: usize,
+) -> Option<(usize, usize)> {
+    let cursor = cursor.min(text.len().checked_sub(1)?);
+
+    match object {
+        Object::Word { around } => {
+            if text[cursor] == '\n' {
+                return None;
+            }
+            let same =
+                |offset: usize, class| text[offset] != '\n' && char_class(text[offset]) == class;
+
+            let class = char_class(text[cursor]);
+            let mut start = cursor;
+            while start > 0 && same(start - 1, class) {
+                start -= 1;
+            }
+            let mut end = cursor + 1;
+            while end < text.len() && same(end, class) {
+                end += 1;
+            }
+            end = advance(end, count - 1, |mut end| {
+                if end < text.len() && text[end] != '\n' {
+                    let class = char_class(text[end]);
+                    while end < text.len() && same(end, class) {
+                        end += 1;
+                    }
+                }
+                end
+            });
+
+            // With the blanks after the word, or before it if there are none
+            if around {
+                let blank = |offset: usize| matches!(text[offset], ' ' | '\t');
+                if end < text.len() && blank(end) {
+                    while end < text.len() && blank(end) {
+                        end += 1;
+                    }
+                } else {
+                    while start > 0 && blank(start - 1) {
+                        start -= 1;
+                    }
+                }
+            }
+            Some((start, end))
+        }
+        Object::Pair {
+            open,
+            close,
+            around,
+        } => {
+            // The opening one before the cursor that isn't closed before it
+            let mut depth = 0;
+            let mut start = None;
+            for offset in (0..=cursor).rev() {
+                if text[offset] == close && offset != cursor {
+                    depth += 1;
+                } else if text[offset] == open {
+                    if depth == 0 {
+                        start = Some(offset);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let start = start?;
+
+            let mut depth = 0;
+            let mut end = None;
+            for (offset, &c) in text.iter().enumerate().skip(start + 1) {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    if depth == 0 {
+                        end = Some(offset);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            let end = end?;
+
+            match around {
+                true => Some((start, end + 1)),
+                false => Some((start + 1, end)),
+            }
+        }
+        Object::Quote { quote, around } => {
+            // Quotes pair up from the start of the line
+            let (line, line_end) = (line_start(text, cursor), line_end(text, cursor));
+            let quotes = (line..line_end)
+                .filter(|&offset| text[offset] == quote)
+                .collect::<Vec<_>>();
+            let (start, end) = quotes
+                .chunks_exact(2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|&(_, end)| end >= cursor)?;
+
+            match around {
+                true => Some((start, end + 1)),
+                false => Some((start + 1, end)),
+            }
+        }
+    }
+}
+
+/// The selection of `view` in visual mode, from the mark to the cursor with
+/// the character under both.
+fn visual_range(len: usize, view: &View) -> Option<(usize, usize)> {
+    let mark = view.mark?;
+    let (start, end) = (mark.min(view.cursor), mark.max(view.cursor));
+    Some((start, (end + 1).min(len)))
+}
+
+/// Kinds of characters that words are made of, words being runs of one kind.
+#[derive(PartialEq, Clone, Copy)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Start of the next word after `offset`. Empty lines count as words.
+fn word_start(text: &[char], offset: usize) -> usize {
+    let mut offset = offset;
+    let class = text.get(offset).map(|&c| char_class(c));
+    if class.is_some_and(|class| class != CharClass::Blank) {
+        while offset < text.len() && Some(char_class(text[offset])) == class {
+            offset += 1;
+        }
+    }
+    while offset < text.len() && text[offset].is_whitespace() {
+        if text[offset] == '\n' && text.get(offset + 1) == Some(&'\n') {
+            return offset + 1;
+        }
+        offset += 1;
+    }
+    offset
+}
+
+/// Last character of the word after `offset`, or of the one it is in.
+fn word_end(text: &[char], offset: usize) -> usize {
+    let mut offset = offset + 1;
+    while offset < text.len() && text[offset].is_whitespace() {
+        offset += 1;
+    }
+    let Some(&c) = text.get(offset) else {
+        return text.len().saturating_sub(1);
+    };
+    while offset + 1 < text.len() && char_class(text[offset + 1]) == char_class(c) {
+        offset += 1;
+    }
+    offset
+}
+
+/// Start of the word before `offset`, or of the one it is in.
+fn word_back(text: &[char], offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while offset > 0 && text[offset - 1].is_whitespace() {
+        offset -= 1;
+    }
+    let Some(&c) = offset.checked_sub(1).map(|previous| &text[previous]) else {
+        return 0;
+    };
+    while offset > 0 && char_class(text[offset - 1]) == char_class(c) {
+        offset -= 1;
+    }
+    offset
+}
+
+fn line_start(text: &[char], offset: usize) -> usize {
+    text[..offset.min(text.len())]
+        .iter()
+        .rposition(|c| c == &'\n')
+        .map_or(0, |newline| newline + 1)
+}
+
+/// Offset of the newline at the end of the line, or of the end of the text.
+fn line_end(text: &[char], offset: usize) -> usize {
+    let offset = offset.min(text.len());
+    text[offset..]
+        .iter()
+        .position(|c| c == &'\n')
+        .map_or(text.len(), |newline| offset + newline)
+}
+
+fn first_non_blank(text: &[char], line_start: usize) -> usize {
+    let end = line_end(text, line_start);
+    (line_start..end)
+        .find(|&offset| !matches!(text[offset], ' ' | '\t'))
+        .unwrap_or(end)
+}
+
+/// Start of line `line`, counting from 1, or of the last line if there are
+/// fewer. Like in Vim, the newline at the end of the text ends the last line
+/// rather than starting an empty one.
+fn nth_line_start(text: &[char], line: usize) -> usize {
+    let mut start = 0;
+    for _ in 1..line {
+        let end = line_end(text, start);
+        if end + 1 >= text.len() {
+            break;
+        }
+        start = end + 1;
+    }
+    start
+}
+
+/// Where the cursor can be in normal mode, which is on a character rather
+/// than after the last one of a line.
+fn normal_cursor(text: &[char], cursor: usize) -> usize {
+    let cursor = cursor.min(text.len());
+    if cursor > line_start(text, cursor) && text.get(cursor).is_none_or(|c| c == &'\n') {
+        cursor - 1
+    } else {
+        cursor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    fn parse_normal(text: &str) -> std::result::Result<Command, ParseError> {
+        parse(&keys(text), false)
+    }
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn parses_counts() {
+        let command = parse_normal("3j").ok().unwrap();
+        assert_eq!(command.count, Some(3));
+        assert!(matches!(command.action, Action::Move(Motion::Down)));
+
+        let command = parse_normal("2d3w").ok().unwrap();
+        assert_eq!(command.count, Some(6));
+        assert!(matches!(
+            command.action,
+            Action::Operate(Operator::Delete, Motion::WordStart)
+        ));
+
+        // A leading 0 is a motion rather than a count
+        let command = parse_normal("0").ok().unwrap();
+        assert_eq!(command.count, None);
+        assert!(matches!(command.action, Action::Move(Motion::LineStart)));
+        assert_eq!(parse_normal("10l").ok().unwrap().count, Some(10));
+    }
+
+    #[test]
+    fn limits_counts_except_for_line_numbers() {
+        let command = parse_normal("99999999999999999999999x").ok().unwrap();
+        assert_eq!(command.count, Some(MAX_COUNT));
+        let command = parse_normal("99999d99999999w").ok().unwrap();
+        assert_eq!(command.count, Some(MAX_COUNT));
+
+        let command = parse_normal("123456G").ok().unwrap();
+        assert_eq!(command.count, Some(123456));
+    }
+
+    #[test]
+    fn parses_registers_and_objects() {
+        let command = parse_normal("\"ayiw").ok().unwrap();
+        assert_eq!(command.register, Some('a'));
+        assert!(matches!(
+            command.action,
+            Action::OperateObject(Operator::Yank, Object::Word { around: false })
+        ));
+
+        let command = parse_normal("ci(").ok().unwrap();
+        assert!(matches!(
+            command.action,
+            Action::OperateObject(
+                Operator::Change,
+                Object::Pair {
+                    open: '(',
+                    close: ')',
+                    around: false
+                }
+            )
+        ));
+        let command = parse_normal("da\"").ok().unwrap();
+        assert!(matches!(
+            command.action,
+            Action::OperateObject(
+                Operator::Delete,
+                Object::Quote {
+                    quote: '"',
+                    around: true
+                }
+            )
+        ));
+        assert!(matches!(
+            parse_normal("dd").ok().unwrap().action,
+            Action::OperateLines(Operator::Delete)
+        ));
+    }
+
+    #[test]
+    fn parses_incomplete_and_invalid_keys() {
+        for text in ["d", "2", "\"", "\"a", "g", "di", "r", "2d3"] {
+            assert!(
+                matches!(parse_normal(text), Err(ParseError::Incomplete)),
+                "{}",
+                text
+            );
+        }
+        for text in ["Q", "gx", "dq", "diz", "\" x", "\"!p"] {
+            assert!(
+                matches!(parse_normal(text), Err(ParseError::Invalid)),
+                "{}",
+                text
+            );
+        }
+        assert!(matches!(
+            parse(&[Key::Ctrl('x')], false),
+            Err(ParseError::Invalid)
+        ));
+        assert!(matches!(
+            parse(&[Key::Char('d'), Key::Esc], false)
+                .ok()
+                .unwrap()
+                .action,
+            Action::Escape
+        ));
+    }
+
+    #[test]
+    fn keeps_special_keys_literal_outside_of_motions() {
+        assert!(matches!(
+            parse_normal("r ").ok().unwrap().action,
+            Action::Replace(' ')
+        ));
+        assert!(matches!(
+            parse_normal("r\n").ok().unwrap().action,
+            Action::Replace('\n')
+        ));
+        assert!(matches!(
+            parse(&[Key::Char('r'), Key::Backspace], false),
+            Err(ParseError::Invalid)
+        ));
+
+        assert!(matches!(
+            parse_normal(" ").ok().unwrap().action,
+            Action::Move(Motion::Right)
+        ));
+        assert!(matches!(
+            parse(&[Key::Backspace], false).ok().unwrap().action,
+            Action::Move(Motion::Left)
+        ));
+    }
+
+    #[test]
+    fn parses_visual_commands() {
+        let visual = |text: &str| parse(&keys(text), true).ok().unwrap().action;
+        assert!(matches!(
+            visual("d"),
+            Action::OperateSelection(Operator::Delete)
+        ));
+        assert!(matches!(
+            visual("x"),
+            Action::OperateSelection(Operator::Delete)
+        ));
+        assert!(matches!(visual("o"), Action::SwapEnds));
+        assert!(matches!(visual("iw"), Action::SelectObject(_)));
+        assert!(matches!(visual("v"), Action::Escape));
+    }
+
+    #[test]
+    fn moves_over_words() {
+        let text = chars("foo.bar  baz\n\nqux");
+        let target = |cursor, motion, count| motion_target(&text, cursor, motion, count, |o| o);
+
+        assert_eq!(target(0, Motion::WordStart, None), 3);
+        assert_eq!(target(3, Motion::WordStart, None), 4);
+        assert_eq!(target(4, Motion::WordStart, None), 9);
+        // The empty line counts as a word
+        assert_eq!(target(9, Motion::WordStart, None), 13);
+        assert_eq!(target(0, Motion::WordStart, Some(3)), 9);
+        assert_eq!(target(0, Motion::WordEnd, None), 2);
+        assert_eq!(target(2, Motion::WordEnd, None), 3);
+        assert_eq!(target(11, Motion::WordBack, None), 9);
+        assert_eq!(target(9, Motion::WordBack, Some(2)), 3);
+    }
+
+    #[test]
+    fn stops_motions_at_the_ends_of_the_text() {
+        let text = chars("a b\nc");
+        let target = |cursor, motion, count| motion_target(&text, cursor, motion, count, |o| o);
+
+        assert_eq!(target(0, Motion::WordStart, Some(MAX_COUNT)), 5);
+        assert_eq!(target(5, Motion::WordBack, Some(MAX_COUNT)), 0);
+        assert_eq!(target(0, Motion::Right, Some(MAX_COUNT)), 3);
+        assert_eq!(target(2, Motion::Left, Some(MAX_COUNT)), 0);
+        assert_eq!(target(0, Motion::Down, Some(MAX_COUNT)), 0);
+    }
+
+    #[test]
+    fn moves_within_and_between_lines() {
+        let text = chars("  one\ntwo\nthree\n");
+        let target = |cursor, motion, count| motion_target(&text, cursor, motion, count, |o| o);
+
+        assert_eq!(target(4, Motion::LineStart, None), 0);
+        assert_eq!(target(4, Motion::FirstNonBlank, None), 2);
+        assert_eq!(target(0, Motion::LineEnd, None), 4);
+        assert_eq!(target(0, Motion::LineEnd, Some(2)), 8);
+        assert_eq!(target(8, Motion::FirstLine, None), 2);
+        assert_eq!(target(0, Motion::FirstLine, Some(2)), 6);
+        // The newline at the end doesn't start a line of its own
+        assert_eq!(target(0, Motion::LastLine, None), 10);
+        assert_eq!(target(0, Motion::LastLine, Some(100)), 10);
+    }
+
+    #[test]
+    fn finds_words() {
+        let text = chars("foo  bar.baz\n");
+        let word =
+            |cursor, around, count| object_range(&text, cursor, Object::Word { around }, count);
+
+        assert_eq!(word(1, false, 1), Some((0, 3)));
+        assert_eq!(word(1, true, 1), Some((0, 5)));
+        assert_eq!(word(6, false, 1), Some((5, 8)));
+        assert_eq!(word(6, false, 2), Some((5, 9)));
+        // Without blanks after it, the ones before are taken
+        assert_eq!(word(10, true, 1), Some((9, 12)));
+        assert_eq!(word(12, false, 1), None);
+    }
+
+    #[test]
+    fn finds_pairs() {
+        let text = chars("f(a, (b), c) [d]\n");
+        let pair = |cursor, open, close, around| {
+            object_range(
+                &text,
+                cursor,
+                Object::Pair {
+                    open,
+                    close,
+                    around,
+                },
+                1,
+            )
+        };
+
+        assert_eq!(pair(2, '(', ')', false), Some((2, 11)));
+        assert_eq!(pair(2, '(', ')', true), Some((1, 12)));
+        // The innermost pair around the cursor, also when it is on a paren
+        assert_eq!(pair(6, '(', ')', false), Some((6, 7)));
+        assert_eq!(pair(7, '(', ')', false), Some((6, 7)));
+        assert_eq!(pair(9, '(', ')', false), Some((2, 11)));
+        assert_eq!(pair(14, '[', ']', false), Some((14, 15)));
+        assert_eq!(pair(14, '(', ')', false), None);
+        assert_eq!(pair(2, '{', '}', false), None);
+    }
+
+    #[test]
+    fn finds_quotes() {
+        let text = chars("x = 'a' + 'bc'\n'd\n");
+        let quote = |cursor, around| {
+            object_range(
+                &text,
+                cursor,
+                Object::Quote {
+                    quote: '\'',
+                    around,
+                },
+                1,
+            )
+        };
+
+        assert_eq!(quote(5, false), Some((5, 6)));
+        assert_eq!(quote(5, true), Some((4, 7)));
+        // Before a pair, the next one on the line
+        assert_eq!(quote(0, false), Some((5, 6)));
+        assert_eq!(quote(12, false), Some((11, 13)));
+        // Quotes don't pair up across lines
+        assert_eq!(quote(16, false), None);
+    }
+
+    #[test]
+    fn moves_in_large_files() {
+        // Large enough to be mapped rather than read
+        let path = std::env::temp_dir().join(format!("vim-large-{}", std::process::id()));
+        std::fs::write(&path, "  one two\nthree\n".repeat(3 << 21)).unwrap();
+        let buffer = Buffer::open(path.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(buffer.is_large());
+
+        let len = buffer.char_count();
+        let target = |cursor, motion, count| large_motion_target(&buffer, cursor, motion, count);
+
+        assert_eq!(target(0, Motion::WordStart, None), 2);
+        assert_eq!(target(2, Motion::WordStart, Some(3)), 18);
+        assert_eq!(target(10, Motion::WordBack, None), 6);
+        assert_eq!(target(2, Motion::WordEnd, None), 4);
+        assert_eq!(target(12, Motion::Left, Some(5)), 10);
+        assert_eq!(target(10, Motion::Right, Some(100)), 15);
+        assert_eq!(target(2, Motion::Down, None), 12);
+        assert_eq!(target(12, Motion::Up, Some(5)), 2);
+        assert_eq!(target(14, Motion::LineStart, None), 10);
+        assert_eq!(target(20, Motion::FirstNonBlank, None), 18);
+        assert_eq!(target(0, Motion::LineEnd, None), 8);
+        assert_eq!(target(0, Motion::LineEnd, Some(2)), 14);
+        assert_eq!(target(len - 3, Motion::FirstLine, None), 2);
+        assert_eq!(target(0, Motion::FirstLine, Some(3)), 18);
+        assert_eq!(target(0, Motion::LastLine, None), len - 6);
+        assert_eq!(target(0, Motion::LastLine, Some(usize::MAX)), len - 6);
+        assert_eq!(target(len - 6, Motion::WordStart, None), len);
+        assert_eq!(
+            text_around(&buffer, len - 3),
+            (len - 6, "three".chars().collect())
+        );
+    }
+}
@@ -1,75 +1,745 @@
-use crate::buffer::Buffer;
-use crate::controller::EditorState;
+mod autosave;
+mod dired;
+mod recent;
+mod revert;
+mod session;
+mod window;
+
+pub use dired::Dired;
+pub use recent::RecentFiles;
+pub use session::{session_path, Session};
+pub use window::{Axis, Rect, View, Window, WindowId};
+
+use window::Layout;
+
+use crate::buffer::{buffer_name, Buffer, Direction, Mode, TextObject, HEX_ROW_LEN};
+use crate::config::{Config, Settings};
+use crate::controller::{EditorState, Vim};
+use crate::error::Result;
+use crate::files::{recovery_path, same_file, write_atomically, Backup, FileStamp, FileWatcher};
+use crate::keymap::{KeymapStyle, Keymaps};
+use crate::logger::{self, ECHO_TARGET};
+use crate::renderer::theme::Theme;
+use log::{log, warn, Level};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::remove_file;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 pub type BufferId = usize;
 
+pub const BUFFER_LIST_NAME: &str = "*Buffer List*";
+pub const DIFF_NAME: &str = "*Diff*";
+pub const MESSAGES_NAME: &str = "*Messages*";
+
+/// How long a message stays in the echo area if no key is pressed.
+pub const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of kills the kill ring keeps.
+const KILL_RING_MAX: usize = 60;
+/// Number of responses the minibuffer history keeps.
+const HISTORY_MAX: usize = 100;
+/// Panic message for the focused window showing a buffer that was removed.
+const FOCUSED_BUFFER_MISSING: &str = "the focused window shows a buffer that doesn't exist";
+
+/// A message in the echo area, the last line of the screen.
+pub struct Echo {
+    pub level: Level,
+    pub text: String,
+    pub shown: Instant,
+}
+
 pub struct Editor {
     buffers: HashMap<BufferId, Buffer>,
     next_buffer_id: BufferId,
-    focused: BufferId,
+    windows: HashMap<WindowId, Window>,
+    layout: Layout,
+    next_window_id: WindowId,
+    focused_window: WindowId,
+    /// Buffers in the order they were focused, most recent last
+    focus_history: Vec<BufferId>,
     pub minibuffer: Buffer,
+    pub minibuffer_view: View,
+    /// Candidates from the last completion, shown after the minibuffer
+    pub completions: Vec<String>,
+    /// Responses given in the minibuffer, most recent last
+    pub minibuffer_history: Vec<String>,
+    /// Text copied with `M-w`, most recent last
+    pub kill_ring: Vec<String>,
+    pub recent_files: RecentFiles,
     pub state: EditorState,
+    pub fill_column: usize,
+    pub auto_fill: bool,
+    pub fill_column_indicator: bool,
+    pub backup: Backup,
+    /// Number of keys after which changes are autosaved
+    pub autosave_interval: usize,
+    /// Time without keys after which changes are autosaved
+    pub autosave_idle: Duration,
+    pub keymaps: Keymaps,
+    /// Keymap style given on the command line, which the configuration
+    /// doesn't override
+    pub keymap_style: Option<KeymapStyle>,
+    /// State of the Vim keymap, if it is used
+    pub vim: Option<Vim>,
+    pub config: Config,
+    /// File the configuration was read from, and is reloaded from
+    pub config_path: Option<PathBuf>,
+    pub theme: Theme,
+    /// Message shown until the next key
+    pub echo: Option<Echo>,
+    /// Set to quit the editor with this exit status
+    pub exit_status: Option<i32>,
+    /// Whether unsaved changes are written to recovery files on the way out,
+    /// when quitting without asking what to do with them
+    pub rescue_on_exit: bool,
+    /// Watches the open files for changes made by other programs
+    pub watcher: Option<FileWatcher>,
 }
 
 impl Editor {
     pub fn new() -> Self {
+        let minibuffer = Buffer::new();
+
         Editor {
             buffers: HashMap::new(),
             next_buffer_id: 1,
-            focused: 0,
-            minibuffer: Buffer::new(None),
+            windows: HashMap::from([(0, Window::new(0, View::default()))]),
+            layout: Layout::Window(0),
+            next_window_id: 1,
+            focused_window: 0,
+            focus_history: vec![],
+            minibuffer_view: minibuffer.view(),
+            minibuffer,
+            completions: vec![],
+            minibuffer_history: vec![],
+            kill_ring: vec![],
+            recent_files: RecentFiles::default(),
             state: EditorState::Editing,
+            fill_column: 70,
+            auto_fill: false,
+            fill_column_indicator: false,
+            backup: Backup::None,
+            autosave_interval: 300,
+            autosave_idle: Duration::from_secs(30),
+            keymaps: Keymaps::default(),
+            keymap_style: None,
+            vim: None,
+            config: Config::default(),
+            config_path: None,
+            theme: Theme::default(),
+            echo: None,
+            exit_status: None,
+            rescue_on_exit: false,
+            watcher: None,
         }
     }
 
+    // The focused buffer always exists once the first one has been opened,
+    // which `run` does before reading any key: killing a buffer shows another
+    // one in its windows, and killing the last one replaces it with an empty
+    // buffer. A missing buffer is a bug, not something commands can recover
+    // from.
     pub fn get_focused_buffer(&self) -> &Buffer {
-        return self.buffers.get(&self.focused).unwrap();
+        self.buffers
+            .get(&self.focused_buffer())
+            .expect(FOCUSED_BUFFER_MISSING)
     }
 
     pub fn get_focused_buffer_mut(&mut self) -> &mut Buffer {
-        return self.buffers.get_mut(&self.focused).unwrap();
+        self.buffers
+            .get_mut(&self.focused_buffer())
+            .expect(FOCUSED_BUFFER_MISSING)
+    }
+
+    pub fn get_focused_view(&self) -> &View {
+        &self.windows[&self.focused_window].view
     }
 
-    pub fn open_file(&mut self, path: Option<PathBuf>) {
-        self.buffers.insert(self.next_buffer_id, Buffer::new(path));
-        self.focused = self.next_buffer_id;
+    /// The focused buffer and the view of it in the focused window.
+    pub fn focused_mut(&mut self) -> (&mut Buffer, &mut View) {
+        let window = self.windows.get_mut(&self.focused_window).unwrap();
+        let buffer = self
+            .buffers
+            .get_mut(&window.buffer)
+            .expect(FOCUSED_BUFFER_MISSING);
+
+        buffer.sync_view(&mut window.view);
+        (buffer, &mut window.view)
+    }
+
+    /// The settings of the configuration that apply to `buffer`.
+    pub fn settings(&self, buffer: &Buffer) -> Settings {
+        self.config.settings(buffer.path.as_deref())
+    }
+
+    pub fn get_buffer(&self, id: BufferId) -> Option<&Buffer> {
+        self.buffers.get(&id)
+    }
+
+    pub fn buffer_ids(&self) -> Vec<BufferId> {
+        let mut ids = self.buffers.keys().copied().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    pub fn buffer_names(&self) -> Vec<String> {
+        self.buffer_ids()
+            .iter()
+            .map(|id| self.buffers[id].name.clone())
+            .collect()
+    }
+
+    pub fn find_buffer(&self, name: &str) -> Option<BufferId> {
+        self.buffer_ids()
+            .into_iter()
+            .find(|id| self.buffers[id].name == name)
+    }
+
+    /// Buffers visiting the file at `path`.
+    pub fn buffers_visiting(&self, path: &Path) -> Vec<BufferId> {
+        self.buffer_ids()
+            .into_iter()
+            .filter(|id| {
+                self.buffers[id]
+                    .path
+                    .as_ref()
+                    .is_some_and(|p| same_file(p, path))
+            })
+            .collect()
+    }
+
+    /// Opens `path` in a new buffer, or focuses the buffer visiting it if there
+    /// already is one. Directories are listed with Dired.
+    pub fn open_file(&mut self, path: Option<PathBuf>) -> Result<()> {
+        let Some(path) = path else {
+            self.add_buffer(Buffer::new());
+            return Ok(());
+        };
+        if path.is_dir() {
+            self.open_directory(&path);
+            return Ok(());
+        }
+
+        if let Some(&id) = self.buffers_visiting(&path).first() {
+            self.focus_buffer(id);
+            return Ok(());
+        }
+
+        let buffer = Buffer::open(path.clone()).map_err(|e| {
+            let message = format!("Unable to read {}: {}", path.display(), e);
+            io::Error::new(e.kind(), message)
+        })?;
+        self.watch(&path);
+        let id = self.add_buffer(buffer);
+        self.restore_position(id);
+        self.remember_position(id);
+        Ok(())
+    }
+
+    /// Adds `buffer` under a name no other buffer has and focuses it.
+    pub fn add_buffer(&mut self, buffer: Buffer) -> BufferId {
+        let id = self.insert_buffer(buffer);
+        self.focus_buffer(id);
+        id
+    }
+
+    /// Adds `buffer` under a name no other buffer has, leaving the focus where
+    /// it is.
+    fn insert_buffer(&mut self, mut buffer: Buffer) -> BufferId {
+        buffer.name = self.unique_name(&buffer.name);
+
+        let id = self.next_buffer_id;
+        self.buffers.insert(id, buffer);
         self.next_buffer_id += 1;
+        id
+    }
+
+    /// Tells the user `text` in the echo area, where it stays until the next
+    /// key, and in the `*Messages*` buffer.
+    pub fn message(&mut self, level: Level, text: &str) {
+        log!(target: ECHO_TARGET, level, "{}", text);
+        self.echo = Some(Echo {
+            level,
+            text: text.to_owned(),
+            shown: Instant::now(),
+        });
     }
 
-    pub fn save_buffer(&mut self, new_path: Option<String>) {
-        let buffer = self.get_focused_buffer_mut();
+    /// Adds the messages logged since the last call to the `*Messages*`
+    /// buffer. The last warning or error among them that wasn't already shown
+    /// goes to the echo area.
+    pub fn log_messages(&mut self) {
+        let messages = logger::take_messages();
+        if messages.is_empty() {
+            return;
+        }
 
-        match new_path {
-            Some(path) => {
-                buffer.path = Some(PathBuf::from_str(path.as_str()).unwrap());
+        let id = match self.find_buffer(MESSAGES_NAME) {
+            Some(id) => id,
+            None => {
+                let mut buffer = Buffer::new();
+                buffer.name = MESSAGES_NAME.to_owned();
+                buffer.read_only = true;
+                self.insert_buffer(buffer)
             }
-            None => {}
-        }
-
-        match &buffer.path {
-            Some(file_path) => {
-                let text = &buffer.text();
-                let mut file = File::create(file_path)
-                    .expect(format!("Something messed up with the path {:?}", file_path).as_str());
-                
-                file.write_all(
-                    text.iter()
-                        .map(|&char| char as u8)
-                        .collect::<Vec<_>>()
-                        .as_slice(),
-                )
-                .expect("Unable to write data");
-
-                buffer.modified = false
+        };
+
+        let mut text = String::new();
+        for message in &messages {
+            match message.level {
+                Level::Info => text.push_str(&message.text),
+                level => text.push_str(&format!("{}: {}", level, message.text)),
             }
+            text.push('\n');
+        }
+        self.buffers.get_mut(&id).unwrap().append(&text);
+
+        let warning = messages
+            .into_iter()
+            .rev()
+            .find(|message| !message.echoed && message.level <= Level::Warn);
+        if let Some(warning) = warning {
+            self.echo = Some(Echo {
+                level: warning.level,
+                text: warning.text,
+                shown: Instant::now(),
+            });
+        }
+    }
+
+    /// Shows buffer `id` in the focused window.
+    pub fn focus_buffer(&mut self, id: BufferId) {
+        if !self.buffers.contains_key(&id) {
+            return;
+        }
+
+        self.sync_views();
+        let view = self.view_for(id);
+        let window = self.windows.get_mut(&self.focused_window).unwrap();
+
+        if window.buffer != id {
+            let old_view = std::mem::replace(&mut window.view, view);
+            window.previous_views.insert(window.buffer, old_view);
+            window.buffer = id;
+        }
+        self.focus_history.retain(|&other| other != id);
+        self.focus_history.push(id);
+    }
+
+    /// The most recently focused buffer other than the focused one.
+    pub fn other_buffer(&self) -> Option<BufferId> {
+        let focused = self.focused_buffer();
+        self.focus_history
+            .iter()
+            .rev()
+            .find(|&&id| id != focused)
+            .copied()
+    }
+
+    /// Removes buffer `id`, showing another buffer in the windows that showed
+    /// it.
+    pub fn kill_buffer(&mut self, id: BufferId) {
+        if !self.buffers.contains_key(&id) {
+            return;
+        }
+        self.sync_views();
+        self.remember_position(id);
+        self.buffers.remove(&id);
+
+        self.focus_history.retain(|&other| other != id);
+
+        if self.focused_buffer() == id {
+            match self.focus_history.last() {
+                Some(&other) => self.focus_buffer(other),
+                None => {
+                    self.add_buffer(Buffer::new());
+                }
+            }
+        }
+
+        let replacement = self.focused_buffer();
+        let ids = self.windows.keys().copied().collect::<Vec<_>>();
+
+        for window_id in ids {
+            let view = self.view_for(replacement);
+            let window = self.windows.get_mut(&window_id).unwrap();
+
+            window.previous_views.remove(&id);
+            if window.buffer == id {
+                window.buffer = replacement;
+                window.view = view;
+            }
+        }
+    }
+
+    /// Moves every view past the edits made through other views, so that the
+    /// edit logs can be dropped.
+    pub fn sync_views(&mut self) {
+        for window in self.windows.values_mut() {
+            if let Some(buffer) = self.buffers.get(&window.buffer) {
+                buffer.sync_view(&mut window.view);
+            }
+            for (id, view) in window.previous_views.iter_mut() {
+                if let Some(buffer) = self.buffers.get(id) {
+                    buffer.sync_view(view);
+                }
+            }
+        }
+        self.minibuffer.sync_view(&mut self.minibuffer_view);
+
+        for buffer in self.buffers.values_mut() {
+            buffer.forget_edits();
+        }
+        self.minibuffer.forget_edits();
+    }
+
+    /// A view for showing buffer `id` in the focused window, the one it had
+    /// there before or else a copy of the view of another window showing it.
+    fn view_for(&mut self, id: BufferId) -> View {
+        let focused = self.windows.get_mut(&self.focused_window).unwrap();
+        if focused.buffer == id {
+            return focused.view.clone();
+        }
+        if let Some(view) = focused.previous_views.remove(&id) {
+            return view;
+        }
+
+        match self.windows.values().find(|window| window.buffer == id) {
+            Some(window) => View {
+                mark: None,
+                ..window.view.clone()
+            },
+            None => self.buffers[&id].view(),
+        }
+    }
+
+    /// Adds `text` to the kill ring, dropping the oldest kill once it is full.
+    pub fn kill(&mut self, text: String) {
+        self.kill_ring.push(text);
+        if self.kill_ring.len() > KILL_RING_MAX {
+            self.kill_ring.remove(0);
+        }
+    }
+
+    /// Inserts the most recent kill at the cursor, in the minibuffer while it
+    /// is prompting.
+    pub fn yank(&mut self) {
+        let Some(text) = self.kill_ring.last().cloned() else {
+            return;
+        };
+
+        match self.state {
+            EditorState::PromptResponse => self.minibuffer.paste(&mut self.minibuffer_view, &text),
+            _ => {
+                let (buffer, view) = self.focused_mut();
+                buffer.paste(view, &text);
+            }
+        }
+    }
+
+    /// Adds a minibuffer response to the history, unless it repeats the last
+    /// one.
+    pub fn add_to_history(&mut self, response: &str) {
+        let history = &self.minibuffer_history;
+        if response.trim().is_empty() || history.last().is_some_and(|r| r == response) {
+            return;
+        }
+        self.minibuffer_history.push(response.to_owned());
+        if self.minibuffer_history.len() > HISTORY_MAX {
+            self.minibuffer_history.remove(0);
+        }
+    }
+
+    /// The view of buffer `id` in the focused window, or else the one another
+    /// window showed it with last.
+    fn last_view(&self, id: BufferId) -> View {
+        let focused = &self.windows[&self.focused_window];
+        if focused.buffer == id {
+            return focused.view.clone();
+        }
+
+        let windows = std::iter::once(focused).chain(self.windows.values());
+        for window in windows {
+            if let Some(view) = window.previous_views.get(&id) {
+                return view.clone();
+            }
+            if window.buffer == id {
+                return window.view.clone();
+            }
+        }
+        View::default()
+    }
+
+    /// A view of buffer `id` with the cursor at `cursor` and line `scroll` at
+    /// the top, kept inside the text in case the file got shorter.
+    fn view_at(&self, id: BufferId, cursor: usize, scroll: usize) -> View {
+        let buffer = &self.buffers[&id];
+        let view = buffer.view();
+
+        view.scroll.set(scroll.min(buffer.line_count()));
+        View {
+            cursor: cursor.min(buffer.char_count()),
+            ..view
+        }
+    }
+
+    // ---------- Windows ----------
+
+    pub fn get_window(&self, id: WindowId) -> &Window {
+        &self.windows[&id]
+    }
+
+    pub fn focused_window(&self) -> WindowId {
+        self.focused_window
+    }
+
+    /// Windows and the part of `area` each of them is drawn in.
+    pub fn window_rects(&self, area: Rect) -> Vec<(WindowId, Rect)> {
+        self.layout.rects(area)
+    }
+
+    /// Splits the focused window in two along `axis`, both showing its buffer.
+    /// The focus stays in the original window.
+    pub fn split_window(&mut self, axis: Axis) {
+        let id = self.next_window_id;
+        let focused = &self.windows[&self.focused_window];
+        let window = Window::new(
+            focused.buffer,
+            View {
+                mark: None,
+                ..focused.view.clone()
+            },
+        );
+
+        self.layout.split_window(self.focused_window, id, axis);
+        self.windows.insert(id, window);
+        self.next_window_id += 1;
+    }
+
+    pub fn delete_window(&mut self) {
+        if self.windows.len() == 1 {
+            warn!("Can't delete the only window");
+            return;
+        }
+
+        let id = self.focused_window;
+        self.other_window();
+        self.layout.remove_window(id);
+        self.windows.remove(&id);
+    }
+
+    pub fn delete_other_windows(&mut self) {
+        let focused = self.focused_window;
+        self.layout = Layout::Window(focused);
+        self.windows.retain(|&id, _| id == focused);
+    }
+
+    /// Moves the focus to the next window in the layout.
+    pub fn other_window(&mut self) {
+        let ids = self.layout.window_ids();
+        let position = ids.iter().position(|&id| id == self.focused_window);
+        let next = ids[position.map_or(0, |i| (i + 1) % ids.len())];
+
+        if next == self.focused_window {
+            return;
+        }
+
+        self.focused_window = next;
+        self.focus_buffer(self.windows[&next].buffer);
+    }
+
+    /// Scrolls the focused window a screen up or down, keeping two lines of
+    /// the previous screen in view. The windows are laid out in `area`.
+    pub fn scroll_page(&mut self, direction: Direction, area: Rect) {
+        let height = self
+            .window_rects(area)
+            .into_iter()
+            .find(|(id, _)| *id == self.focused_window)
+            .map_or(1, |(_, rect)| rect.height.saturating_sub(1) as usize);
+        let page = height.saturating_sub(2).max(1);
+
+        let (buffer, view) = self.focused_mut();
+        let lines = match buffer.mode {
+            Mode::Hex => buffer.char_count().div_ceil(HEX_ROW_LEN),
+            _ => buffer.line_count(),
+        };
+        let scroll = view.scroll.get();
+        let last_page = lines.saturating_sub(height);
+        view.scroll.set(match direction {
+            Direction::Up => scroll.saturating_sub(page),
+            _ => (scroll + page).min(last_page).max(scroll),
+        });
+
+        for _ in 0..page {
+            buffer.go(view, TextObject::Line, direction);
+        }
+    }
+
+    /// Grows the focused window by `delta` cells along `axis`, given the
+    /// windows are laid out in `area`.
+    pub fn resize_window(&mut self, axis: Axis, delta: i32, area: Rect) {
+        if !self
+            .layout
+            .resize_window(self.focused_window, axis, delta, area)
+        {
+            warn!("No window to resize against");
+        }
+    }
+
+    /// Shows the open buffers in the `*Buffer List*` buffer, refreshing it if
+    /// it already exists.
+    pub fn show_buffer_list(&mut self) {
+        let list_id = match self.find_buffer(BUFFER_LIST_NAME) {
+            Some(id) => id,
+            None => {
+                let mut buffer = Buffer::new();
+                buffer.name = BUFFER_LIST_NAME.to_owned();
+                self.add_buffer(buffer)
+            }
+        };
+
+        let ids = self.buffer_ids();
+        let name_width = self
+            .buffer_names()
+            .iter()
+            .map(|name| name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Buffer".len());
+
+        let mut text = format!(" M {:<name_width$} {:>10}  File", "Buffer", "Size");
+        for id in &ids {
+            let buffer = &self.buffers[id];
+            text.push_str(&format!(
+                "\n {} {:<name_width$} {:>10}  {}",
+                if buffer.modified { '*' } else { ' ' },
+                buffer.name,
+                buffer.char_count(),
+                buffer
+                    .path
+                    .as_ref()
+                    .map_or(String::new(), |p| p.display().to_string())
+            ));
+        }
+
+        let list = self.buffers.get_mut(&list_id).unwrap();
+        list.set_text(&text);
+        list.mode = Mode::BufferList(ids);
+        list.modified = false;
+
+        self.focus_buffer(list_id);
+        let (list, view) = self.focused_mut();
+        list.go_to_start(view);
+        list.go(view, TextObject::Line, Direction::Down);
+    }
+
+    pub fn focused_buffer(&self) -> BufferId {
+        self.windows[&self.focused_window].buffer
+    }
+
+    /// Shows `diff -u` of `old` and `new` in the `*Diff*` buffer.
+    fn show_diff(&mut self, old: &Path, new: &Path) -> io::Result<()> {
+        let output = Command::new("diff")
+            .args(["-u", "-N"])
+            .arg(old)
+            .arg(new)
+            .output()?;
+
+        let diff_id = match self.find_buffer(DIFF_NAME) {
+            Some(diff_id) => diff_id,
             None => {
-                todo!("handling saving with no path");
+                let mut buffer = Buffer::new();
+                buffer.name = DIFF_NAME.to_owned();
+                self.add_buffer(buffer)
+            }
+        };
+
+        let diff = self.buffers.get_mut(&diff_id).unwrap();
+        diff.set_text(&String::from_utf8_lossy(&output.stdout));
+        diff.modified = false;
+
+        self.focus_buffer(diff_id);
+        let (diff, view) = self.focused_mut();
+        diff.go_to_start(view);
+        Ok(())
+    }
+
+    /// Tells the file watcher about `path`, if there is one.
+    fn watch(&mut self, path: &Path) {
+        if let Some(watcher) = &mut self.watcher {
+            watcher.watch(path);
+        }
+    }
+
+    /// `name`, or `name<n>` for the first `n` that no buffer is using.
+    fn unique_name(&self, name: &str) -> String {
+        let mut unique = name.to_owned();
+        let mut n = 2;
+
+        while self.find_buffer(&unique).is_some() {
+            unique = format!("{}<{}>", name, n);
+            n += 1;
+        }
+        unique
+    }
+
+    /// Writes the focused buffer to its file, or to `new_path` which it visits
+    /// from then on.
+    pub fn save_buffer(&mut self, new_path: Option<String>) -> Result<()> {
+        if let Some(path) = new_path {
+            let path = PathBuf::from_str(path.as_str()).unwrap();
+            let name = self.unique_name(&buffer_name(Some(&path)));
+            self.watch(&path);
+            let buffer = self.get_focused_buffer_mut();
+
+            buffer.path = Some(path);
+            buffer.name = name;
+            buffer.backed_up = false;
+            buffer.disk = None;
+        }
+
+        let backup = self.backup;
+        let settings = self.settings(self.get_focused_buffer());
+        let (buffer, view) = self.focused_mut();
+        if settings.trim_whitespace_on_save && buffer.mode.is_text() && !buffer.is_large() {
+            buffer.delete_trailing_whitespace(view);
+        }
+        let Some(path) = &buffer.path else {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "no file name").into());
+        };
+        let backup = if buffer.backed_up {
+            Backup::None
+        } else {
+            backup
+        };
+
+        // Large files are still mapped, so they are replaced rather than
+        // written over
+        write_atomically(path, backup, |file| buffer.write_to(file))?;
+
+        buffer.backed_up = true;
+        buffer.modified = false;
+        buffer.disk = FileStamp::new(path, buffer.content_hash());
+        let path = path.clone();
+        // Large files only have their lines counted as far as they were shown
+        let message = match buffer.is_large() {
+            true => format!("Wrote {}", path.display()),
+            false => format!("Wrote {} ({} lines)", path.display(), buffer.line_count()),
+        };
+
+        // The changes are safe in the file now
+        if let Some(recovery) = recovery_path(&path) {
+            if let Err(e) = remove_file(&recovery) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Unable to remove {:?}: {}", recovery, e);
+                }
             }
         }
+        self.message(Level::Info, &message);
+        Ok(())
     }
 }
@@ -0,0 +1,127 @@
+use std::fs::{create_dir_all, metadata, read, remove_file, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use log::{info, warn};
+
+use crate::buffer::{Buffer, Mode};
+use crate::editor::{BufferId, Editor};
+use crate::files::{recovery_path, state_directory, write_atomically, Backup};
+
+impl Editor {
+    /// Writes the buffers changed since they were last autosaved to their
+    /// recovery files. Large files and buffers without a file are left out.
+    pub fn autosave(&mut self) {
+        for buffer in self.buffers.values_mut() {
+            if !buffer.modified || buffer.is_large() || buffer.autosaved == buffer.version() {
+                continue;
+            }
+            let Some(recovery) = buffer.path.as_deref().and_then(recovery_path) else {
+                continue;
+            };
+
+            match write_recovery_file(buffer, &recovery) {
+                Ok(()) => {
+                    buffer.autosaved = buffer.version();
+                    info!("Autosaved {} to {:?}", buffer.name, recovery);
+                }
+                Err(e) => warn!("Unable to autosave {}: {}", buffer.name, e),
+            }
+        }
+    }
+
+    /// Writes the modified buffers somewhere safe, for when the editor exits
+    /// without them being saved. Buffers visiting a file go to its recovery
+    /// file, which is offered when the file is opened again, others to the
+    /// state directory. Gives where each of them went.
+    pub fn rescue_buffers(&mut self) -> Vec<(String, io::Result<PathBuf>)> {
+        let mut rescued = vec![];
+
+        for buffer in self.buffers.values() {
+            let edited = matches!(buffer.mode, Mode::Text | Mode::Hex | Mode::Wdired(_));
+            if !buffer.modified || !edited {
+                continue;
+            }
+
+            let result = match buffer.path.as_deref().and_then(recovery_path) {
+                Some(recovery) => write_recovery_file(buffer, &recovery).map(|_| recovery),
+                None => rescue_unnamed(buffer),
+            };
+            rescued.push((buffer.name.clone(), result));
+        }
+        rescued
+    }
+
+    /// The recovery file of buffer `id`, if there is one that is newer than
+    /// the file it visits.
+    pub fn recovery_file(&self, id: BufferId) -> Option<PathBuf> {
+        let path = self.buffers.get(&id)?.path.as_ref()?;
+        let recovery = recovery_path(path)?;
+        let autosaved = metadata(&recovery).and_then(|m| m.modified()).ok()?;
+
+        match metadata(path).and_then(|m| m.modified()) {
+            Ok(saved) if saved > autosaved => None,
+            _ => Some(recovery),
+        }
+    }
+
+    /// Replaces the text of buffer `id` with its recovery file. The buffer
+    /// stays modified until it is saved.
+    pub fn recover(&mut self, id: BufferId) -> io::Result<()> {
+        let recovery = self.recovery_file(id).ok_or(ErrorKind::NotFound)?;
+        let text = read(&recovery)?
+            .iter()
+            .map(|&byte| byte as char)
+            .collect::<String>();
+
+        let buffer = self.buffers.get_mut(&id).unwrap();
+        buffer.set_text(&text);
+        buffer.autosaved = buffer.version();
+        Ok(())
+    }
+
+    pub fn discard_recovery(&mut self, id: BufferId) -> io::Result<()> {
+        match self.recovery_file(id) {
+            Some(recovery) => remove_file(recovery),
+            None => Ok(()),
+        }
+    }
+
+    /// Shows how the recovery file of buffer `id` differs from its file.
+    pub fn show_recovery_diff(&mut self, id: BufferId) -> io::Result<()> {
+        let recovery = self.recovery_file(id).ok_or(ErrorKind::NotFound)?;
+        let path = self.buffers[&id].path.clone().unwrap();
+
+        self.show_diff(&path, &recovery)
+    }
+}
+
+/// Writes `buffer` to `recovery`, which only its owner can read, like the
+/// file it is for may be.
+fn write_recovery_file(buffer: &Buffer, recovery: &Path) -> io::Result<()> {
+    if !recovery.exists() {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(recovery)?;
+    }
+
+    write_atomically(recovery, Backup::None, |file| buffer.write_to(file))
+}
+
+/// Writes a buffer that has no file to the `rescued` directory in the state
+/// directory.
+fn rescue_unnamed(buffer: &Buffer) -> io::Result<PathBuf> {
+    let directory = state_directory()
+        .ok_or(ErrorKind::NotFound)?
+        .join("rescued");
+    create_dir_all(&directory)?;
+
+    let name = buffer.name.replace('/', "_");
+    let path = directory.join(format!("{}.{}", name, process::id()));
+    write_recovery_file(buffer, &path)?;
+    Ok(path)
+}
@@ -0,0 +1,603 @@
+use std::collections::HashSet;
+use std::fs::{
+    canonicalize, create_dir_all, read_dir, rename, symlink_metadata, Metadata, OpenOptions,
+};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::{DateTime, Local};
+use log::{info, warn};
+
+use crate::buffer::{buffer_name, Buffer, Mode};
+use crate::editor::{BufferId, Editor};
+use crate::error::{EditorError, Result};
+use crate::files::{copy_recursively, move_path, remove_path};
+
+/// A directory shown as a listing. The first line is the directory, then each
+/// entry has a line of its own.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Dired {
+    pub directory: PathBuf,
+    /// Entry names in the order they are listed, `..` first
+    pub entries: Vec<String>,
+    /// Where the name starts on the line of each entry, after the mark,
+    /// permissions, size and modification time, and the length of the link
+    /// target shown after it
+    pub name_columns: Vec<(usize, usize)>,
+    pub marked: HashSet<String>,
+}
+
+impl Dired {
+    /// The entry on `line` of the listing, counting from 1.
+    pub fn entry(&self, line: usize) -> Option<&str> {
+        line.checked_sub(2)
+            .and_then(|i| self.entries.get(i))
+            .map(String::as_str)
+    }
+
+    /// The marked entries, or the entry on `line` if none are marked. `..` is
+    /// never one of them.
+    fn targets(&self, line: usize) -> Vec<PathBuf> {
+        let names = if self.marked.is_empty() {
+            self.entry(line).into_iter().collect::<Vec<_>>()
+        } else {
+            self.entries
+                .iter()
+                .filter(|name| self.marked.contains(*name))
+                .map(String::as_str)
+                .collect()
+        };
+
+        names
+            .into_iter()
+            .filter(|&name| name != "..")
+            .map(|name| self.directory.join(name))
+            .collect()
+    }
+
+    /// Whether replacing `start..end` of the listing `text` with `inserted`
+    /// only edits a file name, the one part of a listing that can be edited.
+    pub fn allows_edit(&self, text: &[char], start: usize, end: usize, inserted: &[char]) -> bool {
+        let line_start = text[..start]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |i| i + 1);
+        let line_end = text[start..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(text.len(), |i| start + i);
+        let line = text[..line_start].iter().filter(|&&c| c == '\n').count();
+
+        let Some(i) = line.checked_sub(1) else {
+            return false;
+        };
+        match (self.entries.get(i), self.name_columns.get(i)) {
+            (Some(name), Some(&(name_start, link_len))) => {
+                name != ".."
+                    && line_start + name_start <= start
+                    && end + link_len <= line_end
+                    && !inserted.contains(&'\n')
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Editor {
+    /// Lists `directory` in a Dired buffer and focuses it, reusing the buffer
+    /// already listing it if there is one.
+    pub fn open_directory(&mut self, directory: &Path) {
+        let directory = canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+        let open = self.buffer_ids().into_iter().find(|id| {
+            matches!(&self.buffers[id].mode, Mode::Dired(dired) if dired.directory == directory)
+        });
+
+        match open {
+            Some(id) => self.focus_buffer(id),
+            None => {
+                let mut buffer = Buffer::new();
+                buffer.name = directory.file_name().map_or("/".to_owned(), |name| {
+                    format!("{}/", name.to_string_lossy())
+                });
+                buffer.mode = Mode::Dired(Dired {
+                    directory,
+                    entries: vec![],
+                    name_columns: vec![],
+                    marked: HashSet::new(),
+                });
+                self.add_buffer(buffer);
+            }
+        }
+        self.refresh_directory();
+    }
+
+    /// Lists the directory of the focused Dired buffer again. Marks stay on
+    /// the entries that are still there and the cursor stays on its line.
+    pub fn refresh_directory(&mut self) {
+        let Some(dired) = self.focused_dired() else {
+            return;
+        };
+        let directory = dired.directory.clone();
+        let mut marked = dired.marked.clone();
+
+        let mut entries = match read_dir(&directory) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                warn!("Unable to read {:?}: {}", directory, e);
+                vec![]
+            }
+        };
+        entries.sort();
+        entries.insert(0, "..".to_owned());
+        marked.retain(|name| entries.contains(name));
+
+        let mut text = format!("  {}:", directory.display());
+        let mut name_columns = vec![];
+        for name in &entries {
+            let (line, columns) = listing_line(&directory.join(name), name, marked.contains(name));
+            text.push('\n');
+            text.push_str(&line);
+            name_columns.push(columns);
+        }
+
+        let (buffer, view) = self.focused_mut();
+        let line = buffer.cursor_position(view).0;
+        let entry_count = entries.len();
+
+        buffer.set_text(&text);
+        buffer.mode = Mode::Dired(Dired {
+            directory,
+            entries,
+            name_columns,
+            marked,
+        });
+        buffer.modified = false;
+
+        // A new listing starts on the first entry after `..`
+        let line = if line < 2 { 3 } else { line };
+        self.go_to_entry_line(line.min(entry_count + 1));
+    }
+
+    /// Visits the entry on the cursor's line, listing it if it is a directory.
+    pub fn dired_visit(&mut self) -> Result<()> {
+        let Some(path) = self.entry_at_cursor() else {
+            let line = self
+                .get_focused_buffer()
+                .cursor_position(self.get_focused_view())
+                .0;
+            return Err(EditorError::InvalidPosition(line));
+        };
+
+        if path.is_dir() {
+            self.open_directory(&path);
+            Ok(())
+        } else {
+            self.open_file(Some(path))
+        }
+    }
+
+    /// Lists the parent directory, with the cursor on the directory this came
+    /// from.
+    pub fn dired_up(&mut self) {
+        let Some(dired) = self.focused_dired() else {
+            return;
+        };
+        let Some(parent) = dired.directory.parent().map(Path::to_path_buf) else {
+            return;
+        };
+        let name = dired
+            .directory
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+
+        self.open_directory(&parent);
+        if let Some(name) = name {
+            self.go_to_entry(&name);
+        }
+    }
+
+    /// Marks or unmarks the entry on the cursor's line and moves to the next
+    /// line.
+    pub fn dired_mark(&mut self, mark: bool) {
+        let (buffer, view) = self.focused_mut();
+        let line = buffer.cursor_position(view).0;
+        let Mode::Dired(dired) = &mut buffer.mode else {
+            return;
+        };
+
+        match dired.entry(line).map(str::to_owned) {
+            Some(name) if name != ".." => {
+                if mark {
+                    dired.marked.insert(name);
+                } else {
+                    dired.marked.remove(&name);
+                }
+            }
+            _ => {}
+        }
+
+        self.refresh_directory();
+        self.go_to_entry_line(line + 1);
+    }
+
+    pub fn dired_unmark_all(&mut self) {
+        if let Mode::Dired(dired) = &mut self.get_focused_buffer_mut().mode {
+            dired.marked.clear();
+        }
+        self.refresh_directory();
+    }
+
+    /// Paths the Dired commands act on, see `Dired::targets`.
+    pub fn dired_targets(&self) -> Vec<PathBuf> {
+        let line = self
+            .get_focused_buffer()
+            .cursor_position(self.get_focused_view())
+            .0;
+        self.focused_dired()
+            .map_or(vec![], |dired| dired.targets(line))
+    }
+
+    /// Creates `name` in the listed directory, an empty file or a directory.
+    pub fn dired_create(&mut self, name: &Path, directory: bool) {
+        let Some(dired) = self.focused_dired() else {
+            return;
+        };
+        let path = dired.directory.join(name);
+
+        let result = if directory {
+            create_dir_all(&path)
+        } else {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map(|_| ())
+        };
+
+        match result {
+            Ok(()) => {
+                self.refresh_directory();
+                if let Some(name) = path.file_name() {
+                    self.go_to_entry(&name.to_string_lossy());
+                }
+            }
+            Err(e) => warn!("Unable to create {:?}: {}", path, e),
+        }
+    }
+
+    /// Deletes the targets, directories with everything in them.
+    pub fn dired_delete(&mut self) {
+        for path in self.dired_targets() {
+            if let Err(e) = remove_path(&path) {
+                warn!("Unable to delete {:?}: {}", path, e);
+            }
+        }
+        self.dired_unmark_all();
+    }
+
+    /// Copies or moves the targets to `target`, which has to be a directory
+    /// when there are several of them. Existing files are not replaced.
+    /// Buffers visiting a moved file follow it.
+    pub fn dired_transfer(&mut self, target: &Path, copy: bool) {
+        let Some(dired) = self.focused_dired() else {
+            return;
+        };
+        let target = dired.directory.join(target);
+        let sources = self.dired_targets();
+
+        let into_directory = target.is_dir();
+        if sources.len() > 1 && !into_directory {
+            warn!("{:?} is not a directory", target);
+            return;
+        }
+
+        for source in sources {
+            let destination = match source.file_name() {
+                Some(name) if into_directory => target.join(name),
+                _ => target.clone(),
+            };
+            if destination.symlink_metadata().is_ok() {
+                warn!("{:?} already exists", destination);
+                continue;
+            }
+
+            let visiting = self.buffers_visiting(&source);
+
+            let result = if copy {
+                copy_recursively(&source, &destination)
+            } else {
+                move_path(&source, &destination)
+            };
+
+            match result {
+                Ok(()) if !copy => {
+                    for id in visiting {
+                        self.rename_buffer_file(id, destination.clone());
+                    }
+                }
+                Ok(()) => {}
+                Err(e) => warn!("Unable to transfer {:?}: {}", source, e),
+            }
+        }
+        self.dired_unmark_all();
+    }
+
+    /// Makes the file names of the focused Dired buffer editable text.
+    pub fn wdired_start(&mut self) {
+        let buffer = self.get_focused_buffer_mut();
+        if let Mode::Dired(dired) = &buffer.mode {
+            buffer.mode = Mode::Wdired(dired.clone());
+        }
+    }
+
+    /// Renames the files whose names were edited and goes back to Dired.
+    /// Renames that conflict or fail are reported one by one and left out.
+    pub fn wdired_finish(&mut self) {
+        let buffer = self.get_focused_buffer();
+        let Mode::Wdired(dired) = &buffer.mode else {
+            return;
+        };
+        let dired = dired.clone();
+
+        let lines = buffer.text_lines();
+        if lines.len() != dired.entries.len() + 1 {
+            warn!("Lines were added or removed, the names no longer match the files");
+            return;
+        }
+
+        let renames = dired
+            .entries
+            .iter()
+            .zip(&dired.name_columns)
+            .zip(&lines[1..])
+            .map(|((old, &columns), line)| (old.clone(), edited_name(line, columns)))
+            .filter(|(old, new)| old != ".." && old != new)
+            .collect::<Vec<_>>();
+
+        // Files are moved out of the way first, so that names can be swapped
+        let mut moved = vec![];
+        for (i, (old, new)) in renames.iter().enumerate() {
+            if new.is_empty() {
+                warn!("No new name given for {}", old);
+            } else if renames.iter().filter(|(_, other)| other == new).count() > 1 {
+                warn!("Not renaming {}, {} is given to another file", old, new);
+            } else {
+                let from = dired.directory.join(old);
+                let temp = dired
+                    .directory
+                    .join(format!(".wdired-{}-{}", process::id(), i));
+
+                let visiting = self.buffers_visiting(&from);
+                match rename(&from, &temp) {
+                    Ok(()) => moved.push((from, temp, dired.directory.join(new), visiting)),
+                    Err(e) => warn!("Unable to rename {}: {}", old, e),
+                }
+            }
+        }
+
+        let mut renamed = 0;
+        for (from, temp, to, visiting) in moved {
+            let result = if to.symlink_metadata().is_ok() {
+                Err(io::Error::new(ErrorKind::AlreadyExists, "file exists"))
+            } else {
+                move_path(&temp, &to)
+            };
+
+            match result {
+                Ok(()) => {
+                    renamed += 1;
+                    for id in visiting {
+                        self.rename_buffer_file(id, to.clone());
+                    }
+                }
+                Err(e) => {
+                    warn!("Unable to rename {:?} to {:?}: {}", from, to, e);
+                    if let Err(e) = rename(&temp, &from) {
+                        warn!("{:?} was left at {:?}: {}", from, temp, e);
+                    }
+                }
+            }
+        }
+        info!("Renamed {} of {} files", renamed, renames.len());
+
+        self.get_focused_buffer_mut().mode = Mode::Dired(dired);
+        self.refresh_directory();
+    }
+
+    /// Goes back to Dired without renaming anything.
+    pub fn wdired_abort(&mut self) {
+        let buffer = self.get_focused_buffer_mut();
+        if let Mode::Wdired(dired) = &buffer.mode {
+            buffer.mode = Mode::Dired(dired.clone());
+            self.refresh_directory();
+        }
+    }
+
+    /// Points buffer `id` at `path` after its file was moved there.
+    fn rename_buffer_file(&mut self, id: BufferId, path: PathBuf) {
+        let name = self.unique_name(&buffer_name(Some(&path)));
+        if let Some(buffer) = self.buffers.get_mut(&id) {
+            buffer.name = name;
+            buffer.path = Some(path);
+        }
+    }
+
+    fn focused_dired(&self) -> Option<&Dired> {
+        match &self.get_focused_buffer().mode {
+            Mode::Dired(dired) => Some(dired),
+            _ => None,
+        }
+    }
+
+    fn entry_at_cursor(&self) -> Option<PathBuf> {
+        let dired = self.focused_dired()?;
+        let line = self
+            .get_focused_buffer()
+            .cursor_position(self.get_focused_view())
+            .0;
+
+        match dired.entry(line)? {
+            ".." => dired.directory.parent().map(Path::to_path_buf),
+            name => Some(dired.directory.join(name)),
+        }
+    }
+
+    fn go_to_entry(&mut self, name: &str) {
+        let index = self
+            .focused_dired()
+            .and_then(|dired| dired.entries.iter().position(|entry| entry == name));
+
+        if let Some(index) = index {
+            self.go_to_entry_line(index + 2);
+        }
+    }
+
+    /// Puts the cursor on the file name on `line`, counting from 1.
+    fn go_to_entry_line(&mut self, line: usize) {
+        let name_column = self
+            .focused_dired()
+            .and_then(|dired| dired.name_columns.get(line.checked_sub(2)?))
+            .map_or(0, |&(start, _)| start);
+        let (buffer, view) = self.focused_mut();
+        let text = buffer.text();
+
+        let start = text
+            .split(|&c| c == '\n')
+            .take(line.saturating_sub(1))
+            .map(|line| line.len() + 1)
+            .sum::<usize>();
+        if start > text.len() {
+            return;
+        }
+        let line_len = text[start..].iter().take_while(|&&c| c != '\n').count();
+
+        view.cursor = start + name_column.min(line_len);
+    }
+}
+
+/// The file name on an edited listing line, between the columns before it
+/// and the target of a link, see `Dired::name_columns`.
+fn edited_name(line: &[char], (name_start, link_len): (usize, usize)) -> String {
+    let name_end = line.len().saturating_sub(link_len).max(name_start);
+    line.get(name_start..name_end)
+        .map_or(String::new(), |name| name.iter().collect())
+}
+
+/// The line listing `path`, under `name`, and its `Dired::name_columns`.
+fn listing_line(path: &Path, name: &str, marked: bool) -> (String, (usize, usize)) {
+    let mark = if marked { '*' } else { ' ' };
+
+    let Ok(metadata) = symlink_metadata(path) else {
+        let columns = format!("{} {:10} {:>10} {:16}  ", mark, "?", "?", "?");
+        let name_start = columns.chars().count();
+        return (columns + name, (name_start, 0));
+    };
+
+    let modified = metadata.modified().map_or("?".to_owned(), |time| {
+        DateTime::<Local>::from(time)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    });
+    let link = if metadata.file_type().is_symlink() {
+        path.read_link()
+            .map_or(String::new(), |target| format!(" -> {}", target.display()))
+    } else {
+        String::new()
+    };
+
+    let columns = format!(
+        "{} {} {:>10} {:16}  ",
+        mark,
+        permissions(&metadata),
+        metadata.len(),
+        modified
+    );
+    let name_start = columns.chars().count();
+    let link_len = link.chars().count();
+    (columns + name + &link, (name_start, link_len))
+}
+
+/// Permissions the way `ls -l` shows them, like `drwxr-xr-x`.
+fn permissions(metadata: &Metadata) -> String {
+    let file_type = metadata.file_type();
+    let kind = if file_type.is_dir() {
+        'd'
+    } else if file_type.is_symlink() {
+        'l'
+    } else {
+        '-'
+    };
+    let mode = metadata.permissions().mode();
+
+    std::iter::once(kind)
+        .chain("rwxrwxrwx".chars().enumerate().map(|(i, c)| {
+            if mode & (1 << (8 - i)) != 0 {
+                c
+            } else {
+                '-'
+            }
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A listing of `entries`, named after 4 columns, and `d` linking to `e`.
+    fn listing() -> (Dired, Vec<char>) {
+        let dired = Dired {
+            directory: PathBuf::from("/tmp"),
+            entries: ["..", "abc", "d"].map(str::to_owned).to_vec(),
+            name_columns: vec![(4, 0), (4, 0), (4, 5)],
+            marked: HashSet::new(),
+        };
+        let text = "  /tmp:\n 1  ..\n 23 abc\n 4  d -> e".chars().collect();
+        (dired, text)
+    }
+
+    #[test]
+    fn allows_editing_only_names() {
+        let (dired, text) = listing();
+        let allows = |start, end, inserted: &str| {
+            dired.allows_edit(&text, start, end, &inserted.chars().collect::<Vec<_>>())
+        };
+
+        assert!(allows(20, 20, "x"));
+        assert!(allows(19, 22, "xyz"));
+        assert!(allows(22, 22, "x"));
+        assert!(allows(27, 28, "fg"));
+        // Not the columns before the name, nor the link target after it
+        assert!(!allows(18, 19, ""));
+        assert!(!allows(17, 17, "9"));
+        assert!(!allows(25, 26, ""));
+        assert!(!allows(28, 29, ""));
+        // Not the header, `..`, or the lines themselves
+        assert!(!allows(2, 2, "x"));
+        assert!(!allows(12, 12, "x"));
+        assert!(!allows(22, 23, ""));
+        assert!(!allows(20, 20, "\n"));
+    }
+
+    #[test]
+    fn reads_edited_names() {
+        let line = |text: &str| text.chars().collect::<Vec<_>>();
+
+        assert_eq!(edited_name(&line(" 23 abcd"), (4, 0)), "abcd");
+        assert_eq!(edited_name(&line(" 4  f -> e"), (4, 5)), "f");
+        assert_eq!(edited_name(&line(" 4  a -> b -> e"), (4, 5)), "a -> b");
+        assert_eq!(edited_name(&line(" 4   -> e"), (4, 5)), "");
+    }
+
+    #[test]
+    fn lists_names_after_wide_columns() {
+        let (line, (name_start, link_len)) = listing_line(Path::new("/nonexistent/x"), "x", true);
+        assert_eq!(&line[name_start..], "x");
+        assert_eq!(link_len, 0);
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs::{create_dir_all, read};
+use std::io::{self, ErrorKind};
+use std::path::{absolute, Path, PathBuf};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::Mode;
+use crate::editor::{BufferId, Editor};
+use crate::files::{state_directory, write_atomically, Backup};
+
+/// Number of files the recent files list keeps.
+const RECENT_FILES_MAX: usize = 100;
+
+/// A file that was opened, and where it was left.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecentFile {
+    pub path: PathBuf,
+    pub cursor: usize,
+    /// First line shown
+    pub scroll: usize,
+}
+
+/// Files opened lately, most recent first. The list is kept in the state
+/// directory, where every editor adds to it.
+#[derive(Default, Debug)]
+pub struct RecentFiles {
+    files: Vec<RecentFile>,
+    /// Files opened or left since the list was loaded
+    changed: Vec<PathBuf>,
+}
+
+fn recent_files_path() -> Option<PathBuf> {
+    Some(state_directory()?.join("recent-files.json"))
+}
+
+impl RecentFiles {
+    pub fn load() -> Self {
+        let files = match read_recent_files() {
+            Ok(files) => files,
+            Err(e) => {
+                warn!("Unable to read the recent files: {}", e);
+                vec![]
+            }
+        };
+
+        RecentFiles {
+            files,
+            changed: vec![],
+        }
+    }
+
+    /// Writes the files opened since the list was loaded to the state
+    /// directory, in front of the ones other editors opened meanwhile.
+    pub fn save(&self) -> io::Result<()> {
+        let path = recent_files_path().ok_or(ErrorKind::NotFound)?;
+        if self.changed.is_empty() {
+            return Ok(());
+        }
+
+        let mut files = self
+            .files
+            .iter()
+            .filter(|file| self.changed.contains(&file.path))
+            .cloned()
+            .collect::<Vec<_>>();
+        files.extend(
+            read_recent_files()?
+                .into_iter()
+                .filter(|file| !self.changed.contains(&file.path)),
+        );
+        files.truncate(RECENT_FILES_MAX);
+
+        if let Some(directory) = path.parent() {
+            create_dir_all(directory)?;
+        }
+        write_atomically(&path, Backup::None, |file| {
+            serde_json::to_writer_pretty(file, &files).map_err(io::Error::from)
+        })
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&RecentFile> {
+        let path = absolute(path).ok()?;
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    /// Puts `file` first in the list.
+    pub fn add(&mut self, file: RecentFile) {
+        self.files.retain(|other| other.path != file.path);
+        self.changed.retain(|path| path != &file.path);
+        self.changed.push(file.path.clone());
+
+        self.files.insert(0, file);
+        self.files.truncate(RECENT_FILES_MAX);
+    }
+
+    pub fn paths(&self) -> Vec<&Path> {
+        self.files.iter().map(|file| file.path.as_path()).collect()
+    }
+}
+
+fn read_recent_files() -> io::Result<Vec<RecentFile>> {
+    let path = recent_files_path().ok_or(ErrorKind::NotFound)?;
+    match read(path) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+impl Editor {
+    /// Puts the cursor of the newly opened buffer `id` back where its file was
+    /// left last time.
+    pub fn restore_position(&mut self, id: BufferId) {
+        let Some(path) = &self.buffers[&id].path else {
+            return;
+        };
+        let Some(file) = self.recent_files.get(path).cloned() else {
+            return;
+        };
+
+        let view = self.view_at(id, file.cursor, file.scroll);
+        let window = self.windows.get_mut(&self.focused_window).unwrap();
+        if window.buffer == id {
+            window.view = view;
+        }
+    }
+
+    /// Adds the file of buffer `id` to the recent files, with where its cursor
+    /// is.
+    pub fn remember_position(&mut self, id: BufferId) {
+        let buffer = &self.buffers[&id];
+        if !matches!(buffer.mode, Mode::Text | Mode::Hex) {
+            return;
+        }
+        let Some(path) = buffer.path.as_deref().and_then(|p| absolute(p).ok()) else {
+            return;
+        };
+
+        let view = self.last_view(id);
+        self.recent_files.add(RecentFile {
+            path,
+            cursor: view.cursor,
+            scroll: view.scroll.get(),
+        });
+    }
+
+    /// Remembers where the cursor is in every open file.
+    pub fn remember_positions(&mut self) {
+        self.sync_views();
+        for id in self.focus_history.clone() {
+            self.remember_position(id);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::fs::{read, remove_file, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::process;
+
+use log::info;
+
+use crate::editor::{BufferId, Editor};
+use crate::files::{hash_bytes, FileStamp};
+
+impl Editor {
+    /// Whether another program changed the file of buffer `id` since the
+    /// buffer last read or wrote it.
+    pub fn file_changed(&mut self, id: BufferId) -> bool {
+        let Some(buffer) = self.buffers.get_mut(&id) else {
+            return false;
+        };
+        match (&buffer.path, &mut buffer.disk) {
+            (Some(path), Some(disk)) => disk.changed(path),
+            _ => false,
+        }
+    }
+
+    /// Replaces the text of buffer `id` with its file, dropping any changes.
+    pub fn reload(&mut self, id: BufferId) -> io::Result<()> {
+        let buffer = self.buffers.get_mut(&id).ok_or(ErrorKind::NotFound)?;
+        buffer.reload()?;
+        buffer.autosaved = buffer.version();
+        info!("Reloaded {} from {:?}", buffer.name, buffer.path);
+        Ok(())
+    }
+
+    /// Keeps the text of buffer `id` over the changes made to its file, which
+    /// is no longer reported as changed until it changes again.
+    pub fn keep_changes(&mut self, id: BufferId) {
+        let buffer = self.buffers.get_mut(&id).unwrap();
+        let Some(path) = &buffer.path else {
+            return;
+        };
+        let hash = match buffer.is_large() {
+            true => None,
+            false => read(path).ok().map(|bytes| hash_bytes(&bytes)),
+        };
+
+        buffer.disk = FileStamp::new(path, hash);
+        buffer.modified = true;
+    }
+
+    /// Shows how the file of buffer `id` differs from the text of the buffer.
+    pub fn show_disk_diff(&mut self, id: BufferId) -> io::Result<()> {
+        let buffer = self.buffers.get(&id).ok_or(ErrorKind::NotFound)?;
+        let path = buffer.path.clone().ok_or(ErrorKind::NotFound)?;
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let text = env::temp_dir().join(format!(".{}.{}.buffer", name, process::id()));
+        let result = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&text)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                buffer.write_to(&mut writer)?;
+                writer.flush()
+            });
+
+        let result = result.and_then(|()| self.show_diff(&text, &path));
+        let _ = remove_file(&text);
+        result
+    }
+}
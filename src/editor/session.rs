@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{create_dir_all, read, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{absolute, Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::Mode;
+use crate::editor::window::Layout;
+use crate::editor::{Axis, BufferId, Editor, View, Window};
+use crate::files::{state_directory, write_atomically, Backup};
+
+/// What `--session` restores: the files that were open, how they were shown
+/// and what was typed and copied.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Session {
+    /// Files and directories in the order they were focused, most recent last
+    buffers: Vec<SessionBuffer>,
+    layout: Option<SessionLayout>,
+    /// Index of the focused window, in the order `C-x o` goes through them
+    focused_window: usize,
+    kill_ring: Vec<String>,
+    minibuffer_history: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionBuffer {
+    path: PathBuf,
+    view: SessionView,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug)]
+struct SessionView {
+    cursor: usize,
+    mark: Option<usize>,
+    scroll: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+enum SessionLayout {
+    Window {
+        /// Index in `Session::buffers`, none for buffers without a file
+        buffer: Option<usize>,
+        view: SessionView,
+    },
+    Split {
+        axis: Axis,
+        ratio: f32,
+        first: Box<SessionLayout>,
+        second: Box<SessionLayout>,
+    },
+}
+
+/// File session `name` is kept in, the default session of the working
+/// directory if there is no name.
+pub fn session_path(name: Option<&str>) -> Option<PathBuf> {
+    let name = match name {
+        Some(name) => name.replace('/', "_"),
+        None => env::current_dir().ok()?.to_string_lossy().replace('/', "!"),
+    };
+    Some(state_directory()?.join("sessions").join(name + ".json"))
+}
+
+impl Session {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match read(path) {
+            Ok(json) => Ok(serde_json::from_slice(&json)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Session::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the session to `path`, which only its owner can read since the
+    /// kill ring may hold anything.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(directory) = path.parent() {
+            create_dir_all(directory)?;
+        }
+        if !path.exists() {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?;
+        }
+
+        write_atomically(path, Backup::None, |file| {
+            serde_json::to_writer_pretty(file, self).map_err(io::Error::from)
+        })
+    }
+}
+
+impl Editor {
+    /// The open files and directories, the windows showing them and the
+    /// minibuffer history and kill ring.
+    pub fn session(&mut self) -> Session {
+        self.sync_views();
+
+        let ids = self
+            .focus_history
+            .iter()
+            .copied()
+            .filter(|id| self.session_file(*id).is_some())
+            .collect::<Vec<_>>();
+        let buffers = ids
+            .iter()
+            .map(|&id| SessionBuffer {
+                path: self.session_file(id).unwrap(),
+                view: session_view(&self.last_view(id)),
+            })
+            .collect();
+
+        let focused_window = self
+            .layout
+            .window_ids()
+            .iter()
+            .position(|&id| id == self.focused_window)
+            .unwrap_or(0);
+
+        Session {
+            buffers,
+            layout: Some(self.session_layout(&self.layout, &ids)),
+            focused_window,
+            kill_ring: self.kill_ring.clone(),
+            minibuffer_history: self.minibuffer_history.clone(),
+        }
+    }
+
+    /// Opens the files of `session` that still exist and lays out the windows
+    /// like they were.
+    pub fn restore_session(&mut self, session: Session) {
+        self.kill_ring = session.kill_ring;
+        self.minibuffer_history = session.minibuffer_history;
+
+        let mut ids = vec![];
+        for buffer in &session.buffers {
+            if !buffer.path.exists() {
+                info!("Not restoring {:?}, which no longer exists", buffer.path);
+                ids.push(None);
+                continue;
+            }
+
+            if let Err(e) = self.open_file(Some(buffer.path.clone())) {
+                warn!("Unable to restore {:?}: {}", buffer.path, e);
+                ids.push(None);
+                continue;
+            }
+            let id = self.focused_buffer();
+            let view = self.restored_view(id, buffer.view);
+            self.windows.get_mut(&self.focused_window).unwrap().view = view;
+            ids.push(Some(id));
+        }
+
+        let Some(&last) = self.focus_history.last() else {
+            return;
+        };
+        let Some(layout) = &session.layout else {
+            return;
+        };
+
+        // Every window can go back to the buffers that were opened
+        let window = self.windows.remove(&self.focused_window).unwrap();
+        let mut views = window.previous_views;
+        views.insert(window.buffer, window.view);
+        views.retain(|id, _| self.buffers.contains_key(id));
+
+        self.windows.clear();
+        self.layout = self.restore_layout(layout, &ids, last, &views);
+
+        let window_ids = self.layout.window_ids();
+        self.focused_window = window_ids[session.focused_window.min(window_ids.len() - 1)];
+        self.focus_buffer(self.windows[&self.focused_window].buffer);
+    }
+
+    /// The file or directory buffer `id` would be opened again from.
+    fn session_file(&self, id: BufferId) -> Option<PathBuf> {
+        let buffer = &self.buffers[&id];
+        match &buffer.mode {
+            Mode::Text | Mode::Hex => absolute(buffer.path.as_ref()?).ok(),
+            Mode::Dired(dired) => Some(dired.directory.clone()),
+            _ => None,
+        }
+    }
+
+    fn session_layout(&self, layout: &Layout, ids: &[BufferId]) -> SessionLayout {
+        match layout {
+            Layout::Window(id) => {
+                let window = &self.windows[id];
+                SessionLayout::Window {
+                    buffer: ids.iter().position(|&id| id == window.buffer),
+                    view: session_view(&window.view),
+                }
+            }
+            Layout::Split {
+                axis,
+                ratio,
+                first,
+                second,
+            } => SessionLayout::Split {
+                axis: *axis,
+                ratio: *ratio,
+                first: Box::new(self.session_layout(first, ids)),
+                second: Box::new(self.session_layout(second, ids)),
+            },
+        }
+    }
+
+    /// Creates the windows of `layout`. Windows whose buffer wasn't restored
+    /// show `fallback`.
+    fn restore_layout(
+        &mut self,
+        layout: &SessionLayout,
+        ids: &[Option<BufferId>],
+        fallback: BufferId,
+        views: &HashMap<BufferId, View>,
+    ) -> Layout {
+        match layout {
+            SessionLayout::Window { buffer, view } => {
+                let (buffer, view) = match buffer.and_then(|i| ids.get(i).copied().flatten()) {
+                    Some(id) => (id, self.restored_view(id, *view)),
+                    None => (fallback, views[&fallback].clone()),
+                };
+
+                let id = self.next_window_id;
+                let mut window = Window::new(buffer, view);
+                window.previous_views = views.clone();
+                window.previous_views.remove(&buffer);
+
+                self.windows.insert(id, window);
+                self.next_window_id += 1;
+                Layout::Window(id)
+            }
+            SessionLayout::Split {
+                axis,
+                ratio,
+                first,
+                second,
+            } => Layout::Split {
+                axis: *axis,
+                ratio: ratio.clamp(0.0, 1.0),
+                first: Box::new(self.restore_layout(first, ids, fallback, views)),
+                second: Box::new(self.restore_layout(second, ids, fallback, views)),
+            },
+        }
+    }
+
+    /// A view of buffer `id` like the saved one.
+    fn restored_view(&self, id: BufferId, saved: SessionView) -> View {
+        let len = self.buffers[&id].char_count();
+        View {
+            mark: saved.mark.map(|mark| mark.min(len)),
+            ..self.view_at(id, saved.cursor, saved.scroll)
+        }
+    }
+}
+
+fn session_view(view: &View) -> SessionView {
+    SessionView {
+        cursor: view.cursor,
+        mark: view.mark,
+        scroll: view.scroll.get(),
+    }
+}
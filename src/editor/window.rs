@@ -0,0 +1,311 @@
+use std::{cell::Cell, collections::HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::editor::BufferId;
+
+pub type WindowId = usize;
+
+/// Smallest window the layout gives out, a line of text and a status line.
+const MIN_HEIGHT: u16 = 2;
+const MIN_WIDTH: u16 = 8;
+
+/// Where a window is in a buffer. Offsets count characters of the buffer's
+/// text, and are moved along with edits made through other views.
+#[derive(Clone, Default, Debug)]
+pub struct View {
+    pub cursor: usize,
+    pub mark: Option<usize>,
+    /// Whether hex input goes to the low half of the byte under the cursor
+    pub low_nibble: bool,
+    /// First line shown, counting from 0. Moved by the renderer to keep the
+    /// cursor in view.
+    pub scroll: Cell<usize>,
+    /// Number of buffer edits the offsets account for
+    pub synced: usize,
+}
+
+/// A buffer shown in part of the screen.
+#[derive(Debug)]
+pub struct Window {
+    pub buffer: BufferId,
+    pub view: View,
+    /// Views of the buffers the window showed before, to go back to them
+    pub previous_views: HashMap<BufferId, View>,
+}
+
+impl Window {
+    pub fn new(buffer: BufferId, view: View) -> Self {
+        Window {
+            buffer,
+            view,
+            previous_views: HashMap::new(),
+        }
+    }
+}
+
+/// Area of the screen in terminal cells, with `x` and `y` counting from 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Axis {
+    /// Windows stacked on top of each other
+    Vertical,
+    /// Windows side by side, with a separator column between them
+    Horizontal,
+}
+
+/// How the screen is divided between windows. Each split gives `ratio` of its
+/// area to `first` and the rest to `second`.
+#[derive(Debug)]
+pub enum Layout {
+    Window(WindowId),
+    Split {
+        axis: Axis,
+        ratio: f32,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    /// Windows in the order `C-x o` cycles through them.
+    pub fn window_ids(&self) -> Vec<WindowId> {
+        match self {
+            Layout::Window(id) => vec![*id],
+            Layout::Split { first, second, .. } => {
+                let mut ids = first.window_ids();
+                ids.extend(second.window_ids());
+                ids
+            }
+        }
+    }
+
+    pub fn rects(&self, area: Rect) -> Vec<(WindowId, Rect)> {
+        match self {
+            Layout::Window(id) => vec![(*id, area)],
+            Layout::Split {
+                axis,
+                ratio,
+                first,
+                second,
+            } => {
+                let (first_area, second_area) = split_area(*axis, *ratio, area);
+                let mut rects = first.rects(first_area);
+                rects.extend(second.rects(second_area));
+                rects
+            }
+        }
+    }
+
+    /// Replaces window `id` with a split between it and `new_id`.
+    pub fn split_window(&mut self, id: WindowId, new_id: WindowId, axis: Axis) -> bool {
+        match self {
+            Layout::Window(window) if *window == id => {
+                *self = Layout::Split {
+                    axis,
+                    ratio: 0.5,
+                    first: Box::new(Layout::Window(id)),
+                    second: Box::new(Layout::Window(new_id)),
+                };
+                true
+            }
+            Layout::Window(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.split_window(id, new_id, axis) || second.split_window(id, new_id, axis)
+            }
+        }
+    }
+
+    /// Removes window `id`, giving its area to the other side of its split.
+    pub fn remove_window(&mut self, id: WindowId) -> bool {
+        let Layout::Split { first, second, .. } = self else {
+            return false;
+        };
+
+        let remaining = if matches!(**first, Layout::Window(window) if window == id) {
+            std::mem::replace(&mut **second, Layout::Window(id))
+        } else if matches!(**second, Layout::Window(window) if window == id) {
+            std::mem::replace(&mut **first, Layout::Window(id))
+        } else {
+            return first.remove_window(id) || second.remove_window(id);
+        };
+
+        *self = remaining;
+        true
+    }
+
+    /// Grows window `id` by `delta` cells along `axis`, shrinking its
+    /// neighbour in the closest split along that axis.
+    pub fn resize_window(&mut self, id: WindowId, axis: Axis, delta: i32, area: Rect) -> bool {
+        let Layout::Split {
+            axis: split_axis,
+            ratio,
+            first,
+            second,
+        } = self
+        else {
+            return false;
+        };
+
+        let in_first = first.window_ids().contains(&id);
+        if !in_first && !second.window_ids().contains(&id) {
+            return false;
+        }
+
+        // A split further down the tree is closer to the window
+        let (first_area, second_area) = split_area(*split_axis, *ratio, area);
+        let resized = if in_first {
+            first.resize_window(id, axis, delta, first_area)
+        } else {
+            second.resize_window(id, axis, delta, second_area)
+        };
+        if resized || *split_axis != axis {
+            return resized;
+        }
+
+        let (size, first_size, min_first, min_second) = match axis {
+            Axis::Vertical => (area.height, first_area.height, MIN_HEIGHT, MIN_HEIGHT),
+            // The separator column is on the second window's side
+            Axis::Horizontal => (area.width, first_area.width, MIN_WIDTH, MIN_WIDTH + 1),
+        };
+        let first_size = if in_first {
+            first_size as i32 + delta
+        } else {
+            first_size as i32 - delta
+        };
+        let max_first = (size as i32 - min_second as i32).max(min_first as i32);
+
+        *ratio = first_size.clamp(min_first as i32, max_first) as f32 / size.max(1) as f32;
+        true
+    }
+}
+
+/// Divides `area` between the two sides of a split, leaving room for the
+/// separator between windows side by side.
+fn split_area(axis: Axis, ratio: f32, area: Rect) -> (Rect, Rect) {
+    match axis {
+        Axis::Vertical => {
+            let height = ((area.height as f32 * ratio).round() as u16)
+                .min(area.height.saturating_sub(MIN_HEIGHT))
+                .max(MIN_HEIGHT.min(area.height));
+            (
+                Rect { height, ..area },
+                Rect {
+                    y: area.y + height,
+                    height: area.height - height,
+                    ..area
+                },
+            )
+        }
+        Axis::Horizontal => {
+            let width = ((area.width as f32 * ratio).round() as u16)
+                .min(area.width.saturating_sub(MIN_WIDTH + 1))
+                .max(MIN_WIDTH.min(area.width.saturating_sub(1)));
+            (
+                Rect { width, ..area },
+                Rect {
+                    x: area.x + width + 1,
+                    width: area.width.saturating_sub(width + 1),
+                    ..area
+                },
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect {
+        x: 1,
+        y: 1,
+        width: 80,
+        height: 24,
+    };
+
+    fn split(axis: Axis, first: Layout, second: Layout) -> Layout {
+        Layout::Split {
+            axis,
+            ratio: 0.5,
+            first: Box::new(first),
+            second: Box::new(second),
+        }
+    }
+
+    fn rect(x: u16, y: u16, width: u16, height: u16) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn lays_out_nested_splits() {
+        let below = split(Axis::Vertical, Layout::Window(2), Layout::Window(3));
+        let layout = split(Axis::Horizontal, Layout::Window(1), below);
+
+        assert_eq!(layout.window_ids(), [1, 2, 3]);
+        assert_eq!(
+            layout.rects(AREA),
+            [
+                (1, rect(1, 1, 40, 24)),
+                (2, rect(42, 1, 39, 12)),
+                (3, rect(42, 13, 39, 12)),
+            ]
+        );
+    }
+
+    #[test]
+    fn removes_the_last_windows_of_splits() {
+        let below = split(Axis::Vertical, Layout::Window(2), Layout::Window(3));
+        let mut layout = split(Axis::Horizontal, Layout::Window(1), below);
+
+        assert!(layout.remove_window(3));
+        assert_eq!(
+            layout.rects(AREA),
+            [(1, rect(1, 1, 40, 24)), (2, rect(42, 1, 39, 24))]
+        );
+        assert!(layout.remove_window(1));
+        assert_eq!(layout.rects(AREA), [(2, AREA)]);
+        assert!(!layout.remove_window(2));
+        assert!(!layout.remove_window(4));
+        assert_eq!(layout.window_ids(), [2]);
+    }
+
+    #[test]
+    fn resizes_windows_down_to_their_minimum_size() {
+        let mut layout = split(Axis::Vertical, Layout::Window(1), Layout::Window(2));
+        let heights = |layout: &Layout| -> Vec<u16> {
+            layout.rects(AREA).iter().map(|(_, r)| r.height).collect()
+        };
+
+        assert!(layout.resize_window(1, Axis::Vertical, -1, AREA));
+        assert_eq!(heights(&layout), [11, 13]);
+        assert!(layout.resize_window(2, Axis::Vertical, 30, AREA));
+        assert_eq!(heights(&layout), [MIN_HEIGHT, 24 - MIN_HEIGHT]);
+        assert!(layout.resize_window(1, Axis::Vertical, -1, AREA));
+        assert_eq!(heights(&layout), [MIN_HEIGHT, 24 - MIN_HEIGHT]);
+        assert!(layout.resize_window(1, Axis::Vertical, 30, AREA));
+        assert_eq!(heights(&layout), [24 - MIN_HEIGHT, MIN_HEIGHT]);
+
+        // Side by side windows leave a column for the separator
+        let mut layout = split(Axis::Horizontal, Layout::Window(1), Layout::Window(2));
+        assert!(layout.resize_window(1, Axis::Horizontal, 80, AREA));
+        let widths: Vec<u16> = layout.rects(AREA).iter().map(|(_, r)| r.width).collect();
+        assert_eq!(widths, [80 - MIN_WIDTH - 1, MIN_WIDTH]);
+
+        // There is no split along the other axis
+        assert!(!layout.resize_window(1, Axis::Vertical, 1, AREA));
+        assert!(!Layout::Window(1).resize_window(1, Axis::Vertical, 1, AREA));
+    }
+}
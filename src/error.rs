@@ -0,0 +1,48 @@
+use std::{fmt, io};
+
+/// Why a command failed. The editor keeps running and shows it in the echo
+/// area.
+#[derive(Debug)]
+pub enum EditorError {
+    Io(io::Error),
+    /// No buffer has this name
+    NoSuchBuffer(String),
+    /// The command needs something at this line, which has nothing there
+    InvalidPosition(usize),
+    /// The key or command isn't bound, or isn't available in this buffer
+    Unsupported(String),
+    /// The configuration file can't be read or has invalid settings
+    Config(String),
+    /// A prompt was answered with something the command can't use
+    InvalidInput(String),
+}
+
+pub type Result<T> = std::result::Result<T, EditorError>;
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditorError::Io(e) => write!(f, "{}", e),
+            EditorError::NoSuchBuffer(name) => write!(f, "No such buffer: {}", name),
+            EditorError::InvalidPosition(line) => write!(f, "Nothing to act on at line {}", line),
+            EditorError::Unsupported(command) => write!(f, "{} is not available here", command),
+            EditorError::Config(message) => write!(f, "Invalid configuration: {}", message),
+            EditorError::InvalidInput(input) => write!(f, "Invalid {}", input),
+        }
+    }
+}
+
+impl std::error::Error for EditorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EditorError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EditorError {
+    fn from(e: io::Error) -> Self {
+        EditorError::Io(e)
+    }
+}
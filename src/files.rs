@@ -0,0 +1,419 @@
+mod watcher;
+
+pub use watcher::FileWatcher;
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env,
+    ffi::CString,
+    fs::{
+        canonicalize, copy, create_dir, metadata, read, read_dir, read_link, remove_dir_all,
+        remove_file, rename, File, OpenOptions,
+    },
+    hash::Hasher,
+    io::{self, BufWriter, ErrorKind},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{chown, symlink, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    process,
+    time::SystemTime,
+};
+
+use clap::ValueEnum;
+
+/// How the previous contents of a file are kept when it is saved over.
+#[derive(ValueEnum, Clone, Copy, PartialEq, Default, Debug)]
+pub enum Backup {
+    #[default]
+    None,
+    /// Kept in `file~`
+    Simple,
+    /// Kept in `file.~1~`, `file.~2~` and so on
+    Numbered,
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(format!("{}{}", home.to_string_lossy(), rest))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Writes paths in the home directory starting with `~`, the way they are
+/// shown in prompts.
+pub fn abbreviate_home(path: &Path) -> String {
+    let path = path.to_string_lossy().into_owned();
+
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && path.starts_with(&home) => {
+            format!("~{}", &path[home.len()..])
+        }
+        _ => path,
+    }
+}
+
+/// Where the editor keeps its state, `$XDG_STATE_HOME/edit` or
+/// `~/.local/state/edit`.
+pub fn state_directory() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .map(|directory| directory.join("edit"))
+}
+
+/// Where the user configures the editor, `$XDG_CONFIG_HOME/edit` or
+/// `~/.config/edit`.
+pub fn config_directory() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|directory| directory.join("edit"))
+}
+
+/// The directory to start file prompts in, `directory` if there is one,
+/// otherwise the working directory. Ends with a `/`.
+pub fn default_directory(directory: Option<PathBuf>) -> String {
+    let directory = directory
+        .or_else(|| env::current_dir().ok())
+        .unwrap_or_default();
+
+    let mut directory = abbreviate_home(&directory);
+    if !directory.ends_with('/') {
+        directory.push('/');
+    }
+    directory
+}
+
+/// Paths that `input` can be completed to, with directories ending in `/`.
+/// Hidden files are only offered once a `.` has been typed.
+pub fn complete_path(input: &str) -> Vec<String> {
+    let (directory, prefix) = match input.rfind('/') {
+        Some(i) => input.split_at(i + 1),
+        None => ("", input),
+    };
+    let search_directory = match directory {
+        "" => PathBuf::from("."),
+        _ => expand_tilde(directory),
+    };
+
+    let Ok(entries) = read_dir(search_directory) else {
+        return vec![];
+    };
+
+    let mut candidates = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", directory, name, slash))
+        })
+        .collect::<Vec<_>>();
+
+    candidates.sort();
+    candidates
+}
+
+/// Whether `a` and `b` name the same file, also for files that don't exist
+/// yet.
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (canonicalize(a), canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// What a file on disk looked like when a buffer last read or wrote it, to
+/// tell when another program changes it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+    /// Hash of the contents, not kept for large files
+    hash: Option<u64>,
+}
+
+impl FileStamp {
+    /// Stamps the file at `path` as it is now, with contents hashing to `hash`.
+    pub fn new(path: &Path, hash: Option<u64>) -> Option<Self> {
+        let metadata = metadata(path).ok()?;
+
+        Some(FileStamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode: metadata.ino(),
+            hash,
+        })
+    }
+
+    /// Whether the file at `path` has changed since it was stamped. Files that
+    /// were only touched, or written with the same contents, have not, and are
+    /// stamped again.
+    pub fn changed(&mut self, path: &Path) -> bool {
+        let Some(stamp) = FileStamp::new(path, self.hash) else {
+            return true;
+        };
+        if stamp == *self {
+            return false;
+        }
+
+        match (self.hash, read(path)) {
+            (Some(hash), Ok(bytes)) if stamp.len == self.len && hash_bytes(&bytes) == hash => {
+                *self = stamp;
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// Whether the file at `path` can be written by this process.
+pub fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a NUL-terminated string that lives until `access`
+    // returns, which only reads it.
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// Copies `from` to `to`, with everything in it if it is a directory. Symbolic
+/// links are copied as links.
+pub fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    let file_type = from.symlink_metadata()?.file_type();
+
+    if file_type.is_symlink() {
+        symlink(read_link(from)?, to)
+    } else if file_type.is_dir() {
+        create_dir(to)?;
+        for entry in read_dir(from)? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        copy(from, to).map(|_| ())
+    }
+}
+
+/// Moves `from` to `to`, copying it when they are on different file systems.
+pub fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            copy_recursively(from, to)?;
+            remove_path(from)
+        }
+        result => result,
+    }
+}
+
+/// Removes `path`, with everything in it if it is a directory. Links to
+/// directories are removed, not followed.
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    if path.symlink_metadata()?.is_dir() {
+        remove_dir_all(path)
+    } else {
+        remove_file(path)
+    }
+}
+
+/// The file unsaved changes to `path` are autosaved to, `#name#` next to it.
+pub fn recovery_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    Some(path.with_file_name(format!("#{}#", name)))
+}
+
+/// Replaces the file at `path` with what `write` writes. The new contents go
+/// to a temporary file in the same directory first, which is synced and then
+/// renamed over the file, so that a failed save never leaves it half written.
+/// Links are followed, and the permissions and owner of the file are kept.
+pub fn write_atomically<F>(path: &Path, backup: Backup, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let path = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    let original = metadata(&path).ok();
+
+    if original.is_some() {
+        if let Some(backup_path) = backup_path(&path, backup) {
+            copy(&path, backup_path)?;
+        }
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = directory.join(format!(".{}.{}.tmp", name, process::id()));
+
+    let result = (|| -> io::Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        if let Some(original) = &original {
+            file.set_permissions(original.permissions())?;
+            // Only works for files of other users when running as root
+            let _ = chown(&temp, Some(original.uid()), Some(original.gid()));
+        }
+
+        let mut file = BufWriter::new(file);
+        write(&mut file)?;
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        rename(&temp, &path)
+    })();
+
+    if result.is_err() {
+        let _ = remove_file(&temp);
+    }
+    result?;
+
+    // The rename only survives a crash once the directory is synced too
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+    Ok(())
+}
+
+/// Where the previous contents of `path` are kept, if they are.
+fn backup_path(path: &Path, backup: Backup) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+
+    match backup {
+        Backup::None => None,
+        Backup::Simple => Some(path.with_file_name(format!("{}~", name))),
+        Backup::Numbered => {
+            let prefix = format!("{}.~", name);
+            let last = read_dir(path.parent()?)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()?
+                        .strip_prefix(&prefix)?
+                        .strip_suffix('~')?
+                        .parse::<u32>()
+                        .ok()
+                })
+                .max()
+                .unwrap_or(0);
+
+            Some(path.with_file_name(format!("{}.~{}~", name, last + 1)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        fs::{self, Permissions},
+        io::Write,
+        os::unix::fs::PermissionsExt,
+    };
+
+    /// An empty directory for a test to write files in.
+    fn test_directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("files-{}-{}", process::id(), name));
+        let _ = remove_dir_all(&directory);
+        create_dir(&directory).unwrap();
+        directory
+    }
+
+    fn names(directory: &Path) -> Vec<String> {
+        let mut names = read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    fn write_text(path: &Path, backup: Backup, text: &str) -> io::Result<()> {
+        write_atomically(path, backup, |file| file.write_all(text.as_bytes()))
+    }
+
+    #[test]
+    fn writes_atomically_keeping_permissions() {
+        let directory = test_directory("atomic");
+        let path = directory.join("file");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, Permissions::from_mode(0o640)).unwrap();
+        symlink("file", directory.join("link")).unwrap();
+
+        write_text(&directory.join("link"), Backup::None, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        assert!(directory
+            .join("link")
+            .symlink_metadata()
+            .unwrap()
+            .is_symlink());
+
+        // A failed write leaves the file as it was
+        let failed = write_atomically(&path, Backup::None, |file| {
+            file.write_all(b"half")?;
+            Err(io::Error::other("failed"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(names(&directory), ["file", "link"]);
+
+        write_text(&directory.join("created"), Backup::Simple, "text").unwrap();
+        assert_eq!(names(&directory), ["created", "file", "link"]);
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_backups() {
+        let directory = test_directory("backups");
+        let path = directory.join("file");
+        assert_eq!(backup_path(&path, Backup::None), None);
+        assert_eq!(
+            backup_path(&path, Backup::Simple),
+            Some(directory.join("file~"))
+        );
+
+        fs::write(&path, "1").unwrap();
+        write_text(&path, Backup::Simple, "2").unwrap();
+        write_text(&path, Backup::Simple, "3").unwrap();
+        assert_eq!(fs::read_to_string(directory.join("file~")).unwrap(), "2");
+
+        write_text(&path, Backup::Numbered, "4").unwrap();
+        write_text(&path, Backup::Numbered, "5").unwrap();
+        assert_eq!(names(&directory), ["file", "file.~1~", "file.~2~", "file~"]);
+        assert_eq!(fs::read_to_string(directory.join("file.~1~")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(directory.join("file.~2~")).unwrap(), "4");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "5");
+
+        // Numbers go on from the highest one, not the count
+        fs::write(directory.join("file.~9~"), "").unwrap();
+        fs::write(directory.join("file.~x~"), "").unwrap();
+        assert_eq!(
+            backup_path(&path, Backup::Numbered),
+            Some(directory.join("file.~10~"))
+        );
+        remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{absolute, Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use inotify::{Inotify, WatchMask, Watches};
+use log::warn;
+
+/// Watches the directories of open files for files being written or moved
+/// there. Programs often replace a file by renaming another one over it,
+/// which a watch on the file itself would not see.
+pub struct FileWatcher {
+    watches: Watches,
+    /// Watched directories by watch descriptor
+    directories: Arc<Mutex<HashMap<i32, PathBuf>>>,
+}
+
+impl FileWatcher {
+    /// Starts watching on a thread of its own, which calls `changed` with the
+    /// path of every file that changes in a watched directory.
+    pub fn start<F>(changed: F) -> io::Result<Self>
+    where
+        F: Fn(PathBuf) + Send + 'static,
+    {
+        let mut inotify = Inotify::init()?;
+        let watches = inotify.watches();
+        let directories = Arc::new(Mutex::new(HashMap::<i32, PathBuf>::new()));
+        let watched = directories.clone();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                let events = match inotify.read_events_blocking(&mut buffer) {
+                    Ok(events) => events,
+                    Err(e) => {
+                        warn!("Stopped watching files: {}", e);
+                        return;
+                    }
+                };
+
+                for event in events {
+                    let id = event.wd.get_watch_descriptor_id();
+                    let directory = watched.lock().unwrap().get(&id).cloned();
+
+                    if let (Some(directory), Some(name)) = (directory, event.name) {
+                        changed(directory.join(name));
+                    }
+                }
+            }
+        });
+
+        Ok(FileWatcher {
+            watches,
+            directories,
+        })
+    }
+
+    /// Watches the directory `path` is in, or the one it links to.
+    pub fn watch(&mut self, path: &Path) {
+        let directory = match path.canonicalize() {
+            Ok(path) => path.parent().map(Path::to_path_buf),
+            Err(_) => absolute(path)
+                .ok()
+                .and_then(|p| p.parent()?.canonicalize().ok()),
+        };
+        let Some(directory) = directory else {
+            return;
+        };
+
+        let mut directories = self.directories.lock().unwrap();
+        if directories.values().any(|watched| watched == &directory) {
+            return;
+        }
+
+        match self
+            .watches
+            .add(&directory, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+        {
+            Ok(descriptor) => {
+                directories.insert(descriptor.get_watch_descriptor_id(), directory);
+            }
+            Err(e) => warn!("Unable to watch {:?}: {}", directory, e),
+        }
+    }
+}
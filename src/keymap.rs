@@ -1,19 +1,495 @@
-// use std::collections::HashMap;
-//
-// use termion::event::Key;
-//
-// use crate::controller::ControlCommand;
-//
-// pub struct Keymap {
-//     keymaps: HashMap<Key, Option<ControlCommand>>,
-// }
-//
-// impl Keymap {
-//     pub fn map(&mut self, key: Key, command: ControlCommand) {
-//         self.keymaps.remove
-//     }
-// }
-//
-// pub fn get_command(keymap: Keymap, ) -> Option<ControlCommand> {
-//     keymap.get()
-// }
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use termion::event::Key;
+
+/// What a key is bound to.
+#[derive(Clone, Debug)]
+pub enum Binding {
+    /// Name of the command the key runs, see `find_command`
+    Command(String),
+    /// Keymap for the key that follows, like the one after `C-x`
+    Prefix(Keymap),
+}
+
+/// Bindings of keys to commands. Keys can be prefixes of longer sequences,
+/// bound to nested keymaps.
+#[derive(Clone, Default, Debug)]
+pub struct Keymap {
+    bindings: HashMap<Key, Binding>,
+}
+
+/// What a key sequence means in a keymap.
+#[derive(PartialEq, Debug)]
+pub enum Lookup {
+    Command(String),
+    /// More keys are needed
+    Prefix,
+    Unbound,
+}
+
+impl Keymap {
+    /// A keymap of `bindings`, pairs of key sequences like `C-x C-f` and
+    /// command names.
+    pub fn new(bindings: &[(&str, &str)]) -> Self {
+        let mut keymap = Keymap::default();
+        for (keys, command) in bindings {
+            keymap.bind(&parse_keys(keys).unwrap(), command);
+        }
+        keymap
+    }
+
+    /// Binds `keys` to `command`, with keymaps for the prefix keys. A key
+    /// bound to a command stops being a prefix, and the other way round.
+    pub fn bind(&mut self, keys: &[Key], command: &str) {
+        let Some((&last, prefix)) = keys.split_last() else {
+            return;
+        };
+
+        let mut keymap = self;
+        for key in prefix {
+            let binding = keymap
+                .bindings
+                .entry(*key)
+                .or_insert_with(|| Binding::Prefix(Keymap::default()));
+            if let Binding::Command(_) = binding {
+                *binding = Binding::Prefix(Keymap::default());
+            }
+
+            let Binding::Prefix(nested) = binding else {
+                unreachable!();
+            };
+            keymap = nested;
+        }
+        keymap
+            .bindings
+            .insert(last, Binding::Command(command.to_owned()));
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        let mut keymap = self;
+        for (i, key) in keys.iter().enumerate() {
+            match keymap.bindings.get(key) {
+                Some(Binding::Command(command)) if i == keys.len() - 1 => {
+                    return Lookup::Command(command.clone());
+                }
+                Some(Binding::Prefix(nested)) => keymap = nested,
+                _ => return Lookup::Unbound,
+            }
+        }
+        Lookup::Prefix
+    }
+
+    /// The key sequences bound to `command`.
+    pub fn keys_for(&self, command: &str) -> Vec<Vec<Key>> {
+        let mut found = vec![];
+        for (key, binding) in &self.bindings {
+            match binding {
+                Binding::Command(name) if name == command => found.push(vec![*key]),
+                Binding::Command(_) => {}
+                Binding::Prefix(nested) => {
+                    found.extend(nested.keys_for(command).into_iter().map(|mut keys| {
+                        keys.insert(0, *key);
+                        keys
+                    }));
+                }
+            }
+        }
+        found
+    }
+}
+
+/// Which keys edit text: Emacs' by default, or Vim's modal ones.
+#[derive(Deserialize, ValueEnum, Clone, Copy, PartialEq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeymapStyle {
+    #[default]
+    Emacs,
+    Vim,
+}
+
+/// The global keymap and the overlays that take precedence over it in some
+/// buffers. Overlays are named after the buffer modes, as in `Mode::name`,
+/// and there are `view` for buffers in view mode, `minibuffer` for
+/// prompts and `vim-normal`, `vim-insert` and `vim-visual` for the modes of
+/// the Vim keymap.
+#[derive(Clone, Debug)]
+pub struct Keymaps {
+    pub global: Keymap,
+    pub overlays: HashMap<String, Keymap>,
+}
+
+impl Default for Keymaps {
+    fn default() -> Self {
+        Keymaps {
+            global: Keymap::new(GLOBAL_BINDINGS),
+            overlays: HashMap::from([
+                ("hex".to_owned(), Keymap::new(HEX_BINDINGS)),
+                ("buffer-list".to_owned(), Keymap::new(BUFFER_LIST_BINDINGS)),
+                ("dired".to_owned(), Keymap::new(DIRED_BINDINGS)),
+                ("wdired".to_owned(), Keymap::new(WDIRED_BINDINGS)),
+                ("view".to_owned(), Keymap::new(VIEW_BINDINGS)),
+                ("vim-insert".to_owned(), Keymap::new(VIM_INSERT_BINDINGS)),
+            ]),
+        }
+    }
+}
+
+impl Keymaps {
+    /// Looks `keys` up in the `overlays` in order, then in the global keymap.
+    /// The first keymap that binds them decides.
+    pub fn lookup(&self, overlays: &[&str], keys: &[Key]) -> Lookup {
+        let keymaps = overlays
+            .iter()
+            .filter_map(|name| self.overlays.get(*name))
+            .chain([&self.global]);
+
+        for keymap in keymaps {
+            match keymap.lookup(keys) {
+                Lookup::Unbound => continue,
+                found => return found,
+            }
+        }
+        Lookup::Unbound
+    }
+
+    /// The key sequences bound to `command` in the global keymap, then in the
+    /// overlays, written like `C-x C-f`.
+    pub fn keys_for(&self, command: &str) -> Vec<String> {
+        let mut names = self.overlays.keys().collect::<Vec<_>>();
+        names.sort();
+        let overlays = names.into_iter().map(|name| &self.overlays[name]);
+
+        let mut keys = vec![];
+        for keymap in [&self.global].into_iter().chain(overlays) {
+            // Shorter sequences first
+            let mut found = keymap.keys_for(command);
+            found.sort_by_key(|keys| (keys.len(), keys_name(keys)));
+            let found = found.iter().map(|keys| keys_name(keys));
+            for name in found {
+                if !keys.contains(&name) {
+                    keys.push(name);
+                }
+            }
+        }
+        keys
+    }
+
+    /// Binds the key sequence `keys`, written like `C-x C-s`, to `command` in
+    /// the overlay named `overlay`, or the global keymap if there is none.
+    pub fn bind(&mut self, overlay: Option<&str>, keys: &str, command: &str) -> Result<(), String> {
+        let keys = parse_keys(keys)?;
+        let keymap = match overlay {
+            Some(name) => self.overlays.entry(name.to_owned()).or_default(),
+            None => &mut self.global,
+        };
+        keymap.bind(&keys, command);
+        Ok(())
+    }
+}
+
+const GLOBAL_BINDINGS: &[(&str, &str)] = &[
+    ("C-f", "forward-char"),
+    ("<right>", "forward-char"),
+    ("C-b", "backward-char"),
+    ("<left>", "backward-char"),
+    ("C-n", "next-line"),
+    ("<down>", "next-line"),
+    ("C-p", "previous-line"),
+    ("<up>", "previous-line"),
+    ("C-a", "beginning-of-line"),
+    ("C-e", "end-of-line"),
+    ("M-f", "forward-word"),
+    ("M-b", "backward-word"),
+    ("M-<", "beginning-of-buffer"),
+    ("M->", "end-of-buffer"),
+    ("DEL", "delete-backward-char"),
+    ("C-d", "delete-char"),
+    ("M-d", "kill-word"),
+    ("M-DEL", "backward-kill-word"),
+    ("C-k", "kill-line"),
+    ("C-y", "yank"),
+    ("M-w", "copy-region"),
+    ("C-t", "set-mark"),
+    ("M-q", "fill-paragraph"),
+    ("C-s", "save-buffer"),
+    ("C-c", "exit"),
+    ("C-x f", "set-fill-column"),
+    ("C-x l s", "sort-lines"),
+    ("C-x l i", "sort-lines-case-insensitive"),
+    ("C-x l n", "sort-lines-numerically"),
+    ("C-x l f", "sort-lines-by-field"),
+    ("C-x l r", "reverse-lines"),
+    ("C-x l u", "delete-duplicate-lines"),
+    ("C-x l a", "align-regexp"),
+    ("C-x b", "switch-to-buffer"),
+    ("C-x k", "kill-buffer"),
+    ("C-x C-b", "list-buffers"),
+    ("C-x 2", "split-window-below"),
+    ("C-x 3", "split-window-right"),
+    ("C-x 0", "delete-window"),
+    ("C-x 1", "delete-other-windows"),
+    ("C-x o", "other-window"),
+    ("C-x ^", "enlarge-window"),
+    ("C-x -", "shrink-window"),
+    ("C-x }", "enlarge-window-horizontally"),
+    ("C-x {", "shrink-window-horizontally"),
+    ("C-x C-f", "find-file"),
+    ("C-x C-r", "recent-files"),
+    ("C-x C-q", "toggle-read-only"),
+    ("C-x C-c", "save-buffers-kill-editor"),
+    ("M-x", "execute-command"),
+    ("C-h f", "describe-command"),
+];
+
+const HEX_BINDINGS: &[(&str, &str)] = &[("/", "search-hex")];
+
+const BUFFER_LIST_BINDINGS: &[(&str, &str)] = &[("RET", "buffer-list-select")];
+
+const DIRED_BINDINGS: &[(&str, &str)] = &[
+    ("RET", "dired-visit"),
+    ("f", "dired-visit"),
+    ("^", "dired-up"),
+    ("g", "dired-refresh"),
+    ("n", "next-line"),
+    ("SPC", "next-line"),
+    ("p", "previous-line"),
+    ("m", "dired-mark"),
+    ("u", "dired-unmark"),
+    ("U", "dired-unmark-all"),
+    ("+", "dired-create-directory"),
+    ("c", "dired-create-file"),
+    ("D", "dired-delete"),
+    ("R", "dired-rename"),
+    ("C", "dired-copy"),
+    ("C-x C-q", "wdired-start"),
+];
+
+const WDIRED_BINDINGS: &[(&str, &str)] = &[
+    ("C-c C-c", "wdired-finish"),
+    ("C-c C-k", "wdired-abort"),
+    ("C-x C-q", "wdired-finish"),
+];
+
+/// Buffers in view mode are paged through like with less.
+const VIEW_BINDINGS: &[(&str, &str)] = &[
+    ("SPC", "scroll-page-down"),
+    ("DEL", "scroll-page-up"),
+    ("q", "quit-window"),
+];
+
+/// Keys typed in Vim's normal and visual modes are handled by `vim`, these
+/// are the ones that leave insert mode.
+const VIM_INSERT_BINDINGS: &[(&str, &str)] = &[("ESC", "vim-normal-mode")];
+
+/// Keys with names of their own, as written in key sequences.
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("RET", Key::Char('\n')),
+    ("SPC", Key::Char(' ')),
+    ("TAB", Key::Char('\t')),
+    ("DEL", Key::Backspace),
+    ("ESC", Key::Esc),
+    ("<left>", Key::Left),
+    ("<right>", Key::Right),
+    ("<up>", Key::Up),
+    ("<down>", Key::Down),
+    ("<home>", Key::Home),
+    ("<end>", Key::End),
+    ("<prior>", Key::PageUp),
+    ("<next>", Key::PageDown),
+    ("<delete>", Key::Delete),
+    ("<insert>", Key::Insert),
+];
+
+/// Parses a key sequence written like `C-x C-s` or `M-q`, with Emacs' names
+/// for keys like `RET` and `<up>`.
+pub fn parse_keys(text: &str) -> Result<Vec<Key>, String> {
+    let keys = text
+        .split_whitespace()
+        .map(parse_key)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("no keys given".to_owned());
+    }
+    Ok(keys)
+}
+
+fn parse_key(text: &str) -> Result<Key, String> {
+    let invalid = || format!("invalid key: {}", text);
+
+    if let Some(&(_, key)) = KEY_NAMES.iter().find(|(name, _)| *name == text) {
+        return Ok(key);
+    }
+    if let Some(n) = text.strip_prefix("<f").and_then(|t| t.strip_suffix('>')) {
+        return match n.parse() {
+            Ok(n @ 1..=12) => Ok(Key::F(n)),
+            _ => Err(invalid()),
+        };
+    }
+
+    let (modifier, rest) = match text.split_at_checked(2) {
+        Some(("C-", rest)) => (Some(Key::Ctrl as fn(char) -> Key), rest),
+        Some(("M-", rest)) => (Some(Key::Alt as fn(char) -> Key), rest),
+        _ => (None, text),
+    };
+    let c = match KEY_NAMES.iter().find(|(name, _)| *name == rest) {
+        Some((_, Key::Char(c))) => *c,
+        Some((_, Key::Backspace)) => '\u{7f}',
+        _ => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(invalid()),
+            }
+        }
+    };
+
+    match modifier {
+        Some(modifier) => Ok(modifier(c)),
+        None if c == '\u{7f}' => Ok(Key::Backspace),
+        None => Ok(Key::Char(c)),
+    }
+}
+
+/// How a key is written in key sequences and messages, like `C-x` or `RET`.
+pub fn key_name(key: Key) -> String {
+    if let Some((name, _)) = KEY_NAMES.iter().find(|(_, other)| *other == key) {
+        return (*name).to_owned();
+    }
+
+    let name = |c: char| match c {
+        '\n' => "RET".to_owned(),
+        ' ' => "SPC".to_owned(),
+        '\t' => "TAB".to_owned(),
+        '\u{7f}' => "DEL".to_owned(),
+        c => c.to_string(),
+    };
+    match key {
+        Key::Char(c) => name(c),
+        Key::Ctrl(c) => format!("C-{}", name(c)),
+        Key::Alt(c) => format!("M-{}", name(c)),
+        Key::F(n) => format!("<f{}>", n),
+        key => format!("{:?}", key),
+    }
+}
+
+/// How a key sequence is written, like `C-x C-s`.
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|&key| key_name(key))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(name: &str) -> Lookup {
+        Lookup::Command(name.to_owned())
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(
+            parse_keys("C-x C-s"),
+            Ok(vec![Key::Ctrl('x'), Key::Ctrl('s')])
+        );
+        assert_eq!(
+            parse_keys("  M-q RET  <up> <f5>"),
+            Ok(vec![Key::Alt('q'), Key::Char('\n'), Key::Up, Key::F(5)])
+        );
+        assert_eq!(
+            parse_keys("DEL C-DEL M-SPC"),
+            Ok(vec![Key::Backspace, Key::Ctrl('\u{7f}'), Key::Alt(' ')])
+        );
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("C-").is_err());
+        assert!(parse_keys("xy").is_err());
+        assert!(parse_keys("<f13>").is_err());
+        assert!(parse_keys("C-<up>").is_err());
+    }
+
+    #[test]
+    fn names_keys_the_way_they_are_parsed() {
+        let names = [
+            "a", "A", "%", "RET", "SPC", "TAB", "DEL", "ESC", "<up>", "<next>", "<f1>", "<f12>",
+            "C-a", "C-RET", "C-DEL", "M-x", "M-SPC", "M-%",
+        ];
+        for name in names {
+            assert_eq!(key_name(parse_key(name).unwrap()), name);
+        }
+        assert_eq!(keys_name(&parse_keys("C-x   4 C-f").unwrap()), "C-x 4 C-f");
+    }
+
+    #[test]
+    fn looks_up_key_sequences() {
+        let keymap = Keymap::new(&[("C-x C-s", "save-buffer"), ("C-f", "forward-char")]);
+        let lookup = |keys| keymap.lookup(&parse_keys(keys).unwrap());
+
+        assert_eq!(lookup("C-f"), command("forward-char"));
+        assert_eq!(lookup("C-x"), Lookup::Prefix);
+        assert_eq!(lookup("C-x C-s"), command("save-buffer"));
+        assert_eq!(lookup("C-x C-f"), Lookup::Unbound);
+        assert_eq!(lookup("C-f C-f"), Lookup::Unbound);
+        assert_eq!(lookup("C-x C-s C-s"), Lookup::Unbound);
+    }
+
+    #[test]
+    fn replaces_prefixes_and_commands() {
+        let mut keymap = Keymap::new(&[("C-c a", "a"), ("C-c b", "b"), ("C-z", "z")]);
+
+        // Binding a prefix drops its sequences
+        keymap.bind(&parse_keys("C-c").unwrap(), "c");
+        assert_eq!(keymap.lookup(&parse_keys("C-c").unwrap()), command("c"));
+        assert_eq!(
+            keymap.lookup(&parse_keys("C-c a").unwrap()),
+            Lookup::Unbound
+        );
+        assert!(keymap.keys_for("a").is_empty());
+
+        // And binding a sequence turns the command into a prefix
+        keymap.bind(&parse_keys("C-z C-z").unwrap(), "zz");
+        assert_eq!(keymap.lookup(&parse_keys("C-z").unwrap()), Lookup::Prefix);
+        assert_eq!(
+            keymap.lookup(&parse_keys("C-z C-z").unwrap()),
+            command("zz")
+        );
+        assert!(keymap.keys_for("z").is_empty());
+
+        keymap.bind(&[], "nothing");
+        assert!(keymap.keys_for("nothing").is_empty());
+    }
+
+    #[test]
+    fn looks_up_overlays_first() {
+        let mut keymaps = Keymaps {
+            global: Keymap::new(&[("q", "self-insert"), ("C-x k", "kill-buffer")]),
+            overlays: HashMap::new(),
+        };
+        keymaps.bind(Some("view"), "q", "quit-window").unwrap();
+        keymaps.bind(Some("dired"), "q", "dired-quit").unwrap();
+        keymaps.bind(Some("dired"), "C-x", "dired-x").unwrap();
+        let lookup = |overlays: &[&str], keys| keymaps.lookup(overlays, &parse_keys(keys).unwrap());
+
+        assert_eq!(lookup(&[], "q"), command("self-insert"));
+        assert_eq!(lookup(&["view"], "q"), command("quit-window"));
+        assert_eq!(lookup(&["dired", "view"], "q"), command("dired-quit"));
+        assert_eq!(lookup(&["view", "dired"], "q"), command("quit-window"));
+        // Overlays that don't exist or don't bind the keys are skipped
+        assert_eq!(lookup(&["hex", "view"], "q"), command("quit-window"));
+        assert_eq!(lookup(&["view"], "C-x k"), command("kill-buffer"));
+        // Even when the global keymap would bind longer sequences
+        assert_eq!(lookup(&["dired"], "C-x"), command("dired-x"));
+        assert_eq!(lookup(&["dired"], "C-x k"), command("kill-buffer"));
+        assert_eq!(lookup(&["dired"], "C-y"), Lookup::Unbound);
+
+        assert!(keymaps.bind(None, "C-", "nothing").is_err());
+        keymaps.bind(None, "C-x k", "kill-current-buffer").unwrap();
+        assert_eq!(
+            keymaps.lookup(&[], &parse_keys("C-x k").unwrap()),
+            command("kill-current-buffer")
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    fs::create_dir_all,
+    mem,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::Local;
+use log::{Level, LevelFilter};
+
+use crate::files::state_directory;
+
+/// Target of the records logged by `Editor::message`, which shows them in the
+/// echo area itself.
+pub const ECHO_TARGET: &str = "edit::echo";
+
+/// A log record for the `*Messages*` buffer.
+pub struct LogMessage {
+    pub level: Level,
+    pub text: String,
+    /// Whether it was already shown in the echo area
+    pub echoed: bool,
+}
+
+/// Log records not yet added to the `*Messages*` buffer.
+static MESSAGES: Mutex<Vec<LogMessage>> = Mutex::new(vec![]);
+
+/// Where the log goes without `--log-file`.
+pub fn default_log_path() -> Option<PathBuf> {
+    Some(state_directory()?.join("edit.log"))
+}
+
+/// Logs records at `level` and above to the file at `path`. Messages of
+/// the editor, at info level and above, are also kept for the `*Messages*`
+/// buffer, even if the file can't be written.
+pub fn setup(path: Option<&Path>, level: LevelFilter) -> Result<(), fern::InitError> {
+    let messages = fern::Dispatch::new()
+        .level(LevelFilter::Info)
+        .chain(fern::Output::call(|record| {
+            MESSAGES.lock().unwrap().push(LogMessage {
+                level: record.level(),
+                text: record.args().to_string(),
+                echoed: record.target() == ECHO_TARGET,
+            });
+        }));
+
+    let mut dispatch = fern::Dispatch::new().chain(messages);
+    let file = match path.map(|path| log_file(path, level)) {
+        Some(Ok(file)) => {
+            dispatch = dispatch.chain(file);
+            Ok(())
+        }
+        Some(Err(e)) => Err(e),
+        None => Ok(()),
+    };
+
+    dispatch.apply()?;
+    file
+}
+
+fn log_file(path: &Path, level: LevelFilter) -> Result<fern::Dispatch, fern::InitError> {
+    if let Some(directory) = path.parent() {
+        create_dir_all(directory)?;
+    }
+
+    Ok(fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "[{} {} {}] {}",
+                Local::now().format("%F %T"),
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .level(level)
+        .chain(fern::log_file(path)?))
+}
+
+/// The messages logged since this was last called.
+pub fn take_messages() -> Vec<LogMessage> {
+    mem::take(&mut *MESSAGES.lock().unwrap())
+}
@@ -1,10 +1,14 @@
 mod buffer;
+mod config;
 mod controller;
 mod editor;
+mod error;
+mod files;
 mod keymap;
+mod logger;
 mod renderer;
 mod cli;
 
 pub fn main() {
-    controller::run();
+    std::process::exit(controller::run());
 }
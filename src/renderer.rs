@@ -1,5 +1,6 @@
 pub mod terminal_renderer;
 pub mod debug_terminal_renderer;
+pub mod theme;
 
 use crate::editor::Editor;
 
@@ -11,5 +12,7 @@ pub trait Renderer {
     fn render_line(&mut self, editor: &Editor);
     fn render_status_line(&mut self, editor: &Editor);
     fn render_minibuffer_prompt(&mut self, editor: &Editor, message: &str);
+    /// Shows the message in the echo area, or clears it if there is none.
+    fn render_echo(&mut self, editor: &Editor);
     fn clear_minibuffer(&mut self, editor: &Editor);
 }
@@ -1,9 +1,9 @@
-use crate::renderer::Renderer;
+use crate::renderer::{terminal_renderer::screen_size, Renderer};
 
 use termion::{
     clear, color, cursor,
     raw::{IntoRawMode, RawTerminal},
-    style, terminal_size,
+    style,
 };
 
 use std::io::{stdout, Stdout, Write};
@@ -27,7 +27,7 @@ impl Renderer for DebugTerminalRenderer {
 
     fn render_editor(&mut self, editor: &Editor) {
         let buffer = editor.get_focused_buffer();
-        let (width, height) = terminal_size().expect("Could not get terminal size");
+        let (width, height) = screen_size();
         let lines = buffer.text_lines_raw();
         let lines_trimmed = lines.iter().take((height - 2) as usize).collect::<Vec<_>>();
         let gutter_offset = buffer.line_count().to_string().len().max(2) as u16 + 1;
@@ -92,7 +92,7 @@ impl Renderer for DebugTerminalRenderer {
 
         // Rendering cursor
         let buffer = editor.get_focused_buffer();
-        let (line, column) = buffer.cursor_position();
+        let (line, column) = buffer.cursor_position(editor.get_focused_view());
         let gutter_offset = (buffer.line_count().to_string().len() + 1).max(3) as u16;
 
         write!(
@@ -119,6 +119,8 @@ impl Renderer for DebugTerminalRenderer {
 
     fn render_minibuffer_prompt(&mut self, _: &Editor, _: &str) {}
 
+    fn render_echo(&mut self, _: &Editor) {}
+
     fn clear_minibuffer(&mut self, _: &Editor) {}
 }
 
@@ -127,8 +129,8 @@ impl DebugTerminalRenderer {
         let buffer = editor.get_focused_buffer();
         let text = &buffer.data;
 
-        let (width, height) = terminal_size().unwrap();
-        let (line, column) = buffer.cursor_position();
+        let (width, height) = screen_size();
+        let (line, column) = buffer.cursor_position(editor.get_focused_view());
 
         let mut t = height - 8;
         let mut iota = || {
@@ -180,9 +182,9 @@ impl DebugTerminalRenderer {
         .unwrap();
         write!(
             self.stdout,
-            "{}cursor_offset: {}",
+            "{}cursor: {}",
             cursor::Goto(1 as u16, iota()),
-            buffer.cursor_offset
+            editor.get_focused_view().cursor
         )
         .unwrap();
         write!(
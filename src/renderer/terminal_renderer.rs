@@ -1,5 +1,11 @@
-use crate::{buffer::Buffer, controller::EditorState, renderer::Renderer};
-use log::info;
+use crate::{
+    buffer::{Buffer, Mode, HEX_ROW_LEN},
+    config::Settings,
+    controller::{vim_mode, visual_selection, VimMode},
+    editor::{Rect, View, WindowId},
+    renderer::Renderer,
+};
+use log::{info, Level};
 use termion::{
     clear, color, cursor,
     raw::{IntoRawMode, RawTerminal},
@@ -14,11 +20,13 @@ use termion::screen::{AlternateScreen, IntoAlternateScreen};
 
 use crate::editor::Editor;
 
-pub const STATUS_BAR_HEIGHT: u16 = 2;
+pub const MINIBUFFER_HEIGHT: u16 = 1;
+
+/// Width of the offset column and the space after it in the hex view.
+const HEX_OFFSET_WIDTH: usize = 10;
 
 pub struct TerminalRenderer {
     stdout: AlternateScreen<RawTerminal<Stdout>>, // stdout: RawTerminal<Stdout>,
-    window_start: u16,
 }
 
 impl Renderer for TerminalRenderer {
@@ -29,301 +37,661 @@ impl Renderer for TerminalRenderer {
                 .unwrap()
                 .into_alternate_screen()
                 .unwrap(), // stdout: stdout().into_raw_mode().unwrap(),
-            window_start: 0,
         }
     }
 
     fn render_all(&mut self, editor: &Editor) {
-        self.render_editor(&editor);
-        self.render_status_line(&editor);
-        self.render_cursor(&editor);
+        self.render_editor(editor);
+        self.render_status_line(editor);
+        self.render_cursor(editor);
 
         self.stdout.flush().unwrap();
     }
 
     fn render_editor(&mut self, editor: &Editor) {
-        self.update_window(&editor);
+        self.scroll_to_cursor(editor);
+
+        write!(self.stdout, "{}", cursor::Hide).unwrap();
+
+        for (id, rect) in editor.window_rects(window_area()) {
+            self.render_window(editor, id, rect);
+        }
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn render_line(&mut self, editor: &Editor) {
+        let buffer = editor.get_focused_buffer();
+        let focused = editor.focused_window();
+        let rects = editor.window_rects(window_area());
+
+        // Other windows showing the buffer need redrawing too
+        let shared = rects.iter().any(|(id, _)| {
+            *id != focused && editor.get_window(*id).buffer == editor.get_window(focused).buffer
+        });
+
+        // Wrapped lines can push the ones below them down
+        if buffer.mode == Mode::Hex || shared || editor.settings(buffer).soft_wrap {
+            self.render_editor(editor);
+            self.render_cursor(editor);
+            return;
+        }
+
+        self.update_window(editor);
+        let rect = self.focused_rect(editor);
+        let view = editor.get_focused_view();
+        let scroll = view.scroll.get();
+        let (line_number, _) = buffer.cursor_position(view);
+
+        if let Some(row) = (line_number - 1).checked_sub(scroll) {
+            write!(self.stdout, "{}", cursor::Hide).unwrap();
+            self.render_row(editor, focused, rect, row as u16, line_number, 0);
+        }
+        self.render_cursor(editor);
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn render_cursor(&mut self, editor: &Editor) {
+        self.update_window(editor);
         let buffer = editor.get_focused_buffer();
-        let (width, height) = terminal_size().expect("Could not get terminal size");
-        let lines = buffer.text_lines();
-        let lines_trimmed = lines // TODO: This doesn't need to be a copy, use slice instead
-            .iter()
-            .enumerate()
-            .map(|(i, line)| (i + 1, line))
-            .skip(self.window_start as usize)
-            .take((height - STATUS_BAR_HEIGHT) as usize)
-            .collect::<Vec<_>>();
-        let gutter_offset = self.gutter_offset(&editor) as u16;
-
-        let selection = buffer.get_selection();
+        let rect = self.focused_rect(editor);
+        let view = editor.get_focused_view();
+        let scroll = view.scroll.get();
+
+        let (x, y) = if buffer.mode == Mode::Hex {
+            let column = view.cursor % HEX_ROW_LEN;
+            let x = HEX_OFFSET_WIDTH
+                + 3 * column
+                + (column >= HEX_ROW_LEN / 2) as usize
+                + view.low_nibble as usize;
+            (x, view.cursor / HEX_ROW_LEN - scroll)
+        } else {
+            cursor_cell(editor, buffer, view, scroll, rect)
+        };
+
+        let goto = cursor::Goto(rect.x + x as u16, rect.y + y as u16);
+        match vim_mode(editor) {
+            Some(VimMode::Insert) => write!(self.stdout, "{}{}", goto, cursor::BlinkingBar),
+            _ => write!(self.stdout, "{}{}", goto, cursor::BlinkingBlock),
+        }
+        .unwrap();
+        write!(self.stdout, "{}", cursor::Show).unwrap();
+
+        self.stdout.flush().unwrap();
+    }
+
+    fn render_status_line(&mut self, editor: &Editor) {
+        self.update_window(editor);
 
         write!(self.stdout, "{}", cursor::Hide).unwrap();
 
-        for i in 0..lines_trimmed.len() {
-            let line_position = (i + 1) as u16;
-            let line_number = lines_trimmed[i].0 as u16;
-            let line = lines_trimmed[i]
-                .1
-                .iter()
-                .take(width as usize)
-                .collect::<String>();
+        for (id, rect) in editor.window_rects(window_area()) {
+            self.render_window_status_line(editor, id, rect);
+        }
 
-            // Draw line content and selection if needed
-            for (i, c) in line.chars().enumerate() {
-                if let Some(((a1, a2), (b1, b2))) = selection {
-                    let in_selection = ((a1 + 1)..=(b1 - 1)).contains(&(line_number as usize))
-                        || ((line_number as usize == a1 && i + 1 >= a2 && a1 != b1)
-                            || (line_number as usize == b1 && i + 1 < b2 && a1 != b1))
-                        || (line_number as usize == a1 && a1 == b1 && a2 <= i + 1 && i + 1 < b2);
-
-                    if in_selection {
-                        write!(self.stdout, "{}", color::Bg(color::LightBlue)).unwrap();
-                    } else {
-                        write!(self.stdout, "{}", color::Bg(color::Reset)).unwrap();
-                    }
-                }
-                write!(
-                    self.stdout,
-                    "{}{c}",
-                    cursor::Goto(gutter_offset + 1 + i as u16, line_position)
-                )
-                .unwrap();
-            }
+        write!(
+            self.stdout,
+            "{}{}",
+            color::Bg(color::Reset),
+            color::Fg(color::Reset),
+        )
+        .unwrap();
 
-            write!(self.stdout, "{}", color::Bg(color::Reset)).unwrap();
+        self.stdout.flush().unwrap();
+    }
 
-            // Clearing everything that was not overwritten
-            write!(
-                self.stdout,
-                "{}{}",
-                cursor::Goto(gutter_offset + 1 + line.len() as u16, line_position),
-                clear::UntilNewline
-            )
-            .unwrap();
+    fn render_minibuffer_prompt(&mut self, editor: &Editor, message: &str) {
+        let (width, height) = screen_size();
+        let response = editor.minibuffer.text().iter().collect::<String>();
 
-            // Clearing the gutter
-            for i in 1..=gutter_offset {
-                write!(
-                    self.stdout,
-                    "{}{} ",
-                    color::Bg(color::Reset),
-                    cursor::Goto(i as u16, line_number)
-                )
-                .unwrap();
-            }
+        write!(
+            self.stdout,
+            "{}{}",
+            cursor::Goto(1, height),
+            clear::CurrentLine
+        )
+        .unwrap();
+        write!(
+            self.stdout,
+            "{}{}: {}{}",
+            style::Faint,
+            message,
+            style::Reset,
+            response
+        )
+        .unwrap();
+
+        // Showing the completion candidates after the response
+        if !editor.completions.is_empty() {
+            let prompt_len = message.chars().count() + 2 + response.chars().count();
+            let completions = format!(" {{{}}}", editor.completions.join(" | "))
+                .chars()
+                .take((width as usize).saturating_sub(prompt_len))
+                .collect::<String>();
 
-            // Drawing the line number
-            let number_offset = gutter_offset - (line_number.to_string().len()) as u16;
             write!(
                 self.stdout,
-                "{}{}{}",
+                "{}{}{}{}",
                 style::Faint,
-                cursor::Goto(number_offset, line_position),
-                line_number
+                completions,
+                style::Reset,
+                cursor::Goto(prompt_len as u16 + 1, height)
             )
             .unwrap();
-            write!(self.stdout, "{}", style::Reset).unwrap();
         }
 
-        // Clearing all lines until the status line
-        for line in (lines_trimmed.len() + 1) as u16..=(height - STATUS_BAR_HEIGHT) {
-            write!(
-                self.stdout,
-                "{}{}",
-                cursor::Goto(1, line),
-                clear::CurrentLine
-            )
+        self.stdout.flush().unwrap();
+    }
+
+    fn render_echo(&mut self, editor: &Editor) {
+        let (width, height) = screen_size();
+        write!(
+            self.stdout,
+            "{}{}{}",
+            cursor::Hide,
+            cursor::Goto(1, height),
+            clear::CurrentLine
+        )
+        .unwrap();
+
+        if let Some(echo) = &editor.echo {
+            let text = echo.text.chars().take(width as usize).collect::<String>();
+            match echo.level {
+                Level::Error => write!(self.stdout, "{}", color::Fg(editor.theme.error)),
+                Level::Warn => write!(self.stdout, "{}", color::Fg(editor.theme.warning)),
+                _ => write!(self.stdout, "{}", color::Fg(color::Reset)),
+            }
             .unwrap();
+            write!(self.stdout, "{}{}", text, color::Fg(color::Reset)).unwrap();
         }
 
-        self.stdout.flush().unwrap();
+        self.render_cursor(editor);
     }
 
-    fn render_line(&mut self, editor: &Editor) {
-        let buffer = editor.get_focused_buffer();
-        let lines = buffer.text_lines();
-        let (mut line_number, _) = buffer.cursor_position();
-        let line = lines.iter().collect::<Vec<_>>()[line_number - 1];
-        line_number -= 1;
+    fn clear_minibuffer(&mut self, _: &Editor) {
+        let (_, height) = screen_size();
 
         write!(
             self.stdout,
-            "{}{}{}{}",
-            cursor::Goto(
-                self.gutter_offset(&editor) as u16 + 1,
-                line_number as u16 - self.window_start + 1
-            ),
-            cursor::Hide,
-            clear::UntilNewline,
-            line.iter().collect::<String>()
+            "{}{}",
+            cursor::Goto(1, height),
+            clear::CurrentLine
         )
         .unwrap();
 
-        self.render_cursor(&editor);
-
         self.stdout.flush().unwrap();
     }
+}
 
-    fn render_cursor(&mut self, editor: &Editor) {
-        self.update_window(&editor);
+impl Drop for TerminalRenderer {
+    fn drop(&mut self) {
+        // Raw mode and the alternate screen are left by the fields. This can
+        // run while panicking, so failing to write is ignored.
+        let _ = write!(self.stdout, "{}{}", cursor::Show, style::Reset);
+        let _ = self.stdout.flush();
+    }
+}
+
+impl TerminalRenderer {
+    /// Scrolls the focused window so that the cursor is in view, redrawing it
+    /// if that moved it.
+    fn update_window(&mut self, editor: &Editor) {
+        if self.scroll_to_cursor(editor) {
+            write!(self.stdout, "{}", cursor::Hide).unwrap();
+            self.render_window(editor, editor.focused_window(), self.focused_rect(editor));
+        }
+    }
+
+    /// Moves the scroll of the focused window to have the cursor in view.
+    /// Returns whether it changed.
+    fn scroll_to_cursor(&self, editor: &Editor) -> bool {
+        let window = editor.get_window(editor.focused_window());
         let buffer = editor.get_focused_buffer();
+        let rect = self.focused_rect(editor);
+
+        let old_window_start = window.view.scroll.get();
+        let line = match buffer.mode {
+            Mode::Hex => window.view.cursor / HEX_ROW_LEN + 1,
+            _ => buffer.cursor_position(&window.view).0,
+        };
+        let window_height = text_height(rect) as usize;
+
+        // Whether the cursor is below the window when it starts at `scroll`,
+        // which wrapped lines can make it be before `window_height` lines
+        let below = |scroll| match buffer.mode {
+            Mode::Hex => line > scroll + window_height,
+            _ => cursor_cell(editor, buffer, &window.view, scroll, rect).1 >= window_height,
+        };
+
+        if line <= old_window_start
+            || line > old_window_start + window_height
+            || below(old_window_start)
+        {
+            let mut scroll = (line - 1).saturating_sub(window_height.div(2));
+            while scroll + 1 < line && below(scroll) {
+                scroll += 1;
+            }
+            window.view.scroll.set(scroll);
+        }
+
+        if old_window_start == window.view.scroll.get() {
+            return false;
+        }
+
+        info!(
+            "window start updated from {} to {}",
+            old_window_start,
+            window.view.scroll.get()
+        );
+        true
+    }
 
-        let (line_number, column_number) = buffer.cursor_position();
-        let gutter_offset = (buffer.line_count().to_string().len() + 1).max(3) as u16;
+    fn focused_rect(&self, editor: &Editor) -> Rect {
+        editor
+            .window_rects(window_area())
+            .into_iter()
+            .find(|(id, _)| *id == editor.focused_window())
+            .map(|(_, rect)| rect)
+            .unwrap()
+    }
+
+    /// Draws the text of window `id` in `rect`, and the separator to the right
+    /// of it unless it is at the edge of the screen.
+    fn render_window(&mut self, editor: &Editor, id: WindowId, rect: Rect) {
+        let window = editor.get_window(id);
+        let Some(buffer) = editor.get_buffer(window.buffer) else {
+            return;
+        };
+
+        if buffer.mode == Mode::Hex {
+            self.render_hex(buffer, window.view.scroll.get(), rect);
+        } else {
+            let settings = editor.settings(buffer);
+            let width = text_width(buffer, settings, rect);
+            let height = text_height(rect);
+            let mut line_number = window.view.scroll.get() + 1;
+            let mut row = 0;
+
+            while row < height {
+                for part in 0..line_rows(buffer, settings, line_number, width) {
+                    if row == height {
+                        break;
+                    }
+                    self.render_row(editor, id, rect, row, line_number, part);
+                    row += 1;
+                }
+                line_number += 1;
+            }
+        }
 
+        let (width, _) = screen_size();
+        if rect.x + rect.width <= width {
+            for y in rect.y..rect.y + rect.height {
+                write!(
+                    self.stdout,
+                    "{}{}│{}",
+                    cursor::Goto(rect.x + rect.width, y),
+                    style::Faint,
+                    style::Reset
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    /// Draws one row of text with its line number, padding it to the width of
+    /// the window. Soft-wrapped lines take several rows, `part` is the one to
+    /// draw.
+    fn render_row(
+        &mut self,
+        editor: &Editor,
+        id: WindowId,
+        rect: Rect,
+        row: u16,
+        line_number: usize,
+        part: usize,
+    ) {
+        let window = editor.get_window(id);
+        let Some(buffer) = editor.get_buffer(window.buffer) else {
+            return;
+        };
+        let lines = buffer.lines(line_number - 1, 1);
+
+        write!(self.stdout, "{}", cursor::Goto(rect.x, rect.y + row)).unwrap();
+
+        let Some(line) = lines.first() else {
+            write!(self.stdout, "{}", " ".repeat(rect.width as usize)).unwrap();
+            return;
+        };
+
+        let settings = editor.settings(buffer);
+        let gutter_offset = gutter_offset(buffer, settings);
+        let text_width = text_width(buffer, settings, rect);
+
+        // Drawing the line number, next to the first row of the line
+        if gutter_offset > 0 && part == 0 {
+            write!(
+                self.stdout,
+                "{}{:>width$} {}",
+                style::Faint,
+                line_number,
+                style::Reset,
+                width = gutter_offset - 1
+            )
+            .unwrap();
+        } else {
+            write!(self.stdout, "{}", " ".repeat(gutter_offset)).unwrap();
+        }
+
+        // Vim's visual mode has a selection of its own in the focused window
+        let visual = id == editor.focused_window();
+        let selection = visual
+            .then(|| visual_selection(editor, &window.view))
+            .flatten()
+            .or_else(|| buffer.get_selection(&window.view));
+
+        // Draw line content and selection if needed, the columns from `start`
+        // to `end` of it
+        let columns = display_columns(line, settings.tab_width);
+        let start = part * text_width;
+        let end = start + text_width;
+        let mut line_len = 0;
+        for (i, &c) in line.iter().enumerate() {
+            let (from, to) = (columns[i].max(start), columns[i + 1].min(end));
+            if from >= end {
+                break;
+            }
+            if from >= to {
+                continue;
+            }
+
+            if let Some(((a1, a2), (b1, b2))) = selection {
+                let in_selection = ((a1 + 1)..=(b1 - 1)).contains(&line_number)
+                    || ((line_number == a1 && i + 1 >= a2 && a1 != b1)
+                        || (line_number == b1 && i + 1 < b2 && a1 != b1))
+                    || (line_number == a1 && a1 == b1 && a2 <= i + 1 && i + 1 < b2);
+
+                if in_selection {
+                    write!(self.stdout, "{}", color::Bg(editor.theme.selection)).unwrap();
+                } else {
+                    write!(self.stdout, "{}", color::Bg(color::Reset)).unwrap();
+                }
+            }
+            match c {
+                '\t' => write!(self.stdout, "{}", " ".repeat(to - from)).unwrap(),
+                c => write!(self.stdout, "{c}").unwrap(),
+            }
+            line_len = to - start;
+        }
+
+        // Clearing everything that was not overwritten
         write!(
             self.stdout,
-            "{}{}{}",
-            cursor::Goto(
-                column_number as u16 + gutter_offset,
-                line_number as u16 - self.window_start
-            ),
-            cursor::Show,
-            cursor::BlinkingBlock
+            "{}{}",
+            color::Bg(color::Reset),
+            " ".repeat(text_width - line_len)
         )
         .unwrap();
 
-        self.stdout.flush().unwrap();
+        self.render_fill_column_indicator(editor, rect, row, gutter_offset, line_len);
     }
 
-    fn render_status_line(&mut self, editor: &Editor) {
-        self.update_window(&editor);
-        let buffer = editor.get_focused_buffer();
-        let (width, height) = terminal_size().expect("Could not get terminal size");
-        let (line, column) = buffer.cursor_position();
+    fn render_window_status_line(&mut self, editor: &Editor, id: WindowId, rect: Rect) {
+        let window = editor.get_window(id);
+        let Some(buffer) = editor.get_buffer(window.buffer) else {
+            return;
+        };
+        let focused = id == editor.focused_window();
         let mut file_name = String::new();
 
+        if let Some(mode) = vim_mode(editor).filter(|_| focused) {
+            file_name.push_str(mode.tag());
+            file_name.push(' ');
+        }
+
         // Generating file name as string
         match &buffer.path {
             Some(p) => file_name.push_str(p.to_str().unwrap()),
-            None => file_name.push_str("[No Name]"),
+            None => file_name.push_str(&buffer.name),
         }
 
         if buffer.modified {
             file_name.push_str("[+]");
         }
 
+        if buffer.is_large() {
+            file_name.push_str(" (large file)");
+        }
+
+        if buffer.view_mode {
+            file_name.push_str(" (view)");
+        } else if buffer.read_only {
+            file_name.push_str(" (read-only)");
+        }
+
+        match buffer.mode {
+            Mode::Hex => file_name.push_str(" (hex)"),
+            Mode::Wdired(_) => file_name.push_str(" (editing file names)"),
+            _ => {}
+        }
+
         // Drawing the status info
         let status_info_left = format!(" {} ", file_name);
-        let status_info_right = format!(" {}:{} ", line, column);
-
-        write!(self.stdout, "{}", cursor::Hide).unwrap();
+        let status_info_right = match buffer.mode {
+            Mode::Hex => format!(" {:#010x} ", window.view.cursor),
+            _ => {
+                let (line, column) = buffer.cursor_position(&window.view);
+                format!(" {}:{} ", line, column)
+            }
+        };
 
+        let width = rect.width as usize;
+        let gap = width.saturating_sub(status_info_left.chars().count() + status_info_right.len());
+        let status_line = format!(
+            "{}{}{}",
+            status_info_left,
+            " ".repeat(gap),
+            status_info_right
+        )
+        .chars()
+        .take(width)
+        .collect::<String>();
+
+        let (foreground, background) = if focused {
+            editor.theme.status_line
+        } else {
+            editor.theme.inactive_status_line
+        };
         write!(
             self.stdout,
-            "{}{}{}{}",
-            cursor::Goto(1 as u16, (height - 1) as u16),
-            color::Fg(color::Black),
-            color::Bg(color::White),
-            status_info_left
+            "{}{}",
+            color::Fg(foreground),
+            color::Bg(background)
         )
         .unwrap();
 
         write!(
             self.stdout,
-            "{}{}{}{}",
-            cursor::Goto(
-                (width as usize - status_info_right.len()) as u16,
-                (height - 1) as u16
-            ),
-            color::Fg(color::Black),
-            color::Bg(color::White),
-            status_info_right
+            "{}{}",
+            cursor::Goto(rect.x, rect.y + rect.height - 1),
+            status_line
         )
         .unwrap();
+    }
+
+    /// Draws the visible rows of a binary buffer as offset, hex and ASCII
+    /// columns.
+    fn render_hex(&mut self, buffer: &Buffer, scroll: usize, rect: Rect) {
+        for row in 0..text_height(rect) {
+            let offset = (scroll + row as usize) * HEX_ROW_LEN;
+            let bytes = buffer.bytes(offset, offset + HEX_ROW_LEN);
+
+            write!(self.stdout, "{}", cursor::Goto(rect.x, rect.y + row)).unwrap();
+
+            if bytes.is_empty() && offset > 0 {
+                write!(self.stdout, "{}", " ".repeat(rect.width as usize)).unwrap();
+                continue;
+            }
+
+            let mut hex = String::new();
+            for i in 0..HEX_ROW_LEN {
+                if i == HEX_ROW_LEN / 2 {
+                    hex.push(' ');
+                }
+                match bytes.get(i) {
+                    Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                    None => hex.push_str("   "),
+                }
+            }
+
+            let ascii = bytes
+                .iter()
+                .map(|&byte| match byte {
+                    0x20..=0x7e => byte as char,
+                    _ => '.',
+                })
+                .collect::<String>();
+
+            let row = format!("{:08x}  {} |{}|", offset, hex, ascii);
+            let row = format!("{:<width$}", row, width = rect.width as usize)
+                .chars()
+                .take(rect.width as usize)
+                .collect::<String>();
+            let (offset, rest) = row.split_at(8.min(row.len()));
 
-        // Drawing the gap and resetting
-        for i in status_info_left.len()..(width as usize) - status_info_right.len() {
             write!(
                 self.stdout,
-                "{} ",
-                cursor::Goto(i as u16, (height - 1) as u16)
+                "{}{}{}{}",
+                style::Faint,
+                offset,
+                style::Reset,
+                rest
             )
             .unwrap();
         }
+    }
 
-        write!(
-            self.stdout,
-            "{}{}",
-            color::Bg(color::Reset),
-            color::Fg(color::Reset),
-        )
-        .unwrap();
+    fn render_fill_column_indicator(
+        &mut self,
+        editor: &Editor,
+        rect: Rect,
+        row: u16,
+        gutter_offset: usize,
+        line_len: usize,
+    ) {
+        if !editor.fill_column_indicator || line_len > editor.fill_column {
+            return;
+        }
 
-        self.stdout.flush().unwrap();
+        let column = gutter_offset + editor.fill_column;
+
+        if column < rect.width as usize {
+            write!(
+                self.stdout,
+                "{}{}│{}",
+                cursor::Goto(rect.x + column as u16, rect.y + row),
+                style::Faint,
+                style::Reset
+            )
+            .unwrap();
+        }
     }
+}
 
-    fn render_minibuffer_prompt(&mut self, editor: &Editor, message: &str) {
-        let (_, height) = terminal_size().unwrap();
+/// Size of the terminal, or the usual 80 by 24 if it can't be told.
+pub fn screen_size() -> (u16, u16) {
+    terminal_size().unwrap_or((80, 24))
+}
 
-        write!(
-            self.stdout,
-            "{}{}",
-            cursor::Goto(1, height),
-            clear::CurrentLine
-        )
-        .unwrap();
-        write!(
-            self.stdout,
-            "{}{}: {}{}",
-            style::Faint,
-            message,
-            style::Reset,
-            editor.minibuffer.text().into_iter().collect::<String>()
-        )
-        .unwrap();
+/// Where the windows are laid out, the screen above the minibuffer.
+pub fn window_area() -> Rect {
+    let (width, height) = screen_size();
 
-        self.stdout.flush().unwrap();
+    Rect {
+        x: 1,
+        y: 1,
+        width,
+        height: height.saturating_sub(MINIBUFFER_HEIGHT),
     }
+}
 
-    fn clear_minibuffer(&mut self, _: &Editor) {
-        let (_, height) = terminal_size().unwrap();
+/// Rows of text in a window, which are all but its status line.
+fn text_height(rect: Rect) -> u16 {
+    rect.height.saturating_sub(1)
+}
 
-        write!(
-            self.stdout,
-            "{}{}",
-            cursor::Goto(1, height),
-            clear::CurrentLine
-        )
-        .unwrap();
+/// Width of the line numbers and the space after them, if they are shown.
+fn gutter_offset(buffer: &Buffer, settings: Settings) -> usize {
+    if !settings.line_numbers {
+        return 0;
+    }
+    buffer.line_count().to_string().len().max(2) + 1
+}
 
-        self.stdout.flush().unwrap();
+/// Columns of text in a window, which are the ones right of the line numbers.
+fn text_width(buffer: &Buffer, settings: Settings, rect: Rect) -> usize {
+    (rect.width as usize)
+        .saturating_sub(gutter_offset(buffer, settings))
+        .max(1)
+}
+
+/// Columns the characters of `line` start at on screen, with tabs reaching to
+/// the next tab stop, followed by the width of the whole line.
+fn display_columns(line: &[char], tab_width: usize) -> Vec<usize> {
+    let mut columns = Vec::with_capacity(line.len() + 1);
+    let mut column = 0;
+    for &c in line {
+        columns.push(column);
+        column += match c {
+            '\t' => tab_width - column % tab_width,
+            _ => 1,
+        };
     }
+    columns.push(column);
+    columns
 }
 
-impl TerminalRenderer {
-    fn update_window(&mut self, editor: &Editor) {
-        let old_window_start = self.window_start;
-        let buffer = editor.get_focused_buffer();
-        let (_, height) = terminal_size().expect("Could not get terminal size");
-        let (line, _) = buffer.cursor_position();
-        let window_height = height - STATUS_BAR_HEIGHT;
-        let window_end = self.window_start + window_height;
-
-        if line as u16 <= self.window_start {
-            let to_center_it = line as i32 - window_height.div(2) as i32;
-            self.window_start = to_center_it.max(0) as u16;
-        } else if line as u16 > window_end {
-            let to_center_it = line as i32 + window_height.div(2) as i32;
-            self.window_start = to_center_it.max(0) as u16;
-        }
+/// Rows line `line_number` takes in a window `width` columns wide. Wrapped
+/// lines leave room for the cursor after their end.
+fn line_rows(buffer: &Buffer, settings: Settings, line_number: usize, width: usize) -> usize {
+    if !settings.soft_wrap {
+        return 1;
+    }
+    match buffer.lines(line_number - 1, 1).first() {
+        Some(line) => display_columns(line, settings.tab_width)[line.len()] / width + 1,
+        None => 1,
+    }
+}
 
-        if old_window_start != self.window_start {
-            info!(
-                "window start updated from {} to {}",
-                old_window_start, self.window_start
-            );
-            self.render_editor(editor);
+/// Where the cursor of `view` is in a window at `rect` scrolled to line
+/// `scroll`, as the column and row in the window.
+fn cursor_cell(
+    editor: &Editor,
+    buffer: &Buffer,
+    view: &View,
+    scroll: usize,
+    rect: Rect,
+) -> (usize, usize) {
+    let settings = editor.settings(buffer);
+    let (line_number, column_number) = buffer.cursor_position(view);
+    let column = match buffer.lines(line_number - 1, 1).first() {
+        Some(line) => {
+            let columns = display_columns(line, settings.tab_width);
+            columns[(column_number - 1).min(line.len())]
         }
-    }
+        None => 0,
+    };
+    let gutter_offset = gutter_offset(buffer, settings);
+    let row = (line_number - 1).saturating_sub(scroll);
 
-    fn gutter_offset(&self, editor: &Editor) -> usize {
-        editor
-            .get_focused_buffer()
-            .line_count()
-            .to_string()
-            .len()
-            .max(2)
-            + 1
+    if !settings.soft_wrap {
+        return (gutter_offset + column, row);
     }
+
+    let width = text_width(buffer, settings, rect);
+    let above = (scroll + 1..line_number)
+        .map(|line| line_rows(buffer, settings, line, width))
+        .sum::<usize>();
+    (gutter_offset + column % width, above + column / width)
 }
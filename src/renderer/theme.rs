@@ -0,0 +1,67 @@
+use termion::color::AnsiValue;
+
+/// Colors the terminal renderer draws with, as ANSI color numbers so that
+/// they follow the palette of the terminal.
+#[derive(Clone, Copy, Debug)]
+pub struct Theme {
+    /// Background of selected text
+    pub selection: AnsiValue,
+    /// Foreground and background of the status line of the focused window
+    pub status_line: (AnsiValue, AnsiValue),
+    /// Foreground and background of the status lines of the other windows
+    pub inactive_status_line: (AnsiValue, AnsiValue),
+    /// Error messages in the echo area
+    pub error: AnsiValue,
+    /// Warnings in the echo area
+    pub warning: AnsiValue,
+}
+
+/// The themes the configuration can choose from by name.
+pub const THEMES: &[(&str, Theme)] = &[
+    (
+        "default",
+        Theme {
+            selection: AnsiValue(12),
+            status_line: (AnsiValue(0), AnsiValue(7)),
+            inactive_status_line: (AnsiValue(7), AnsiValue(8)),
+            error: AnsiValue(1),
+            warning: AnsiValue(3),
+        },
+    ),
+    (
+        // For terminals with a light background
+        "light",
+        Theme {
+            selection: AnsiValue(153),
+            status_line: (AnsiValue(15), AnsiValue(4)),
+            inactive_status_line: (AnsiValue(0), AnsiValue(252)),
+            error: AnsiValue(1),
+            warning: AnsiValue(130),
+        },
+    ),
+    (
+        "high-contrast",
+        Theme {
+            selection: AnsiValue(4),
+            status_line: (AnsiValue(0), AnsiValue(15)),
+            inactive_status_line: (AnsiValue(15), AnsiValue(0)),
+            error: AnsiValue(9),
+            warning: AnsiValue(11),
+        },
+    ),
+];
+
+impl Theme {
+    pub fn named(name: &str) -> Option<Theme> {
+        THEMES
+            .iter()
+            .find(|(other, _)| *other == name)
+            .map(|(_, theme)| *theme)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        THEMES[0].1
+    }
+}